@@ -0,0 +1,41 @@
+//! Compare two saved result sets, e.g. from yesterday's and today's run
+//!
+//! Both files are JSON arrays of `CymruIP2ASN` results as serialized with
+//! the `serde` feature.
+//!
+//! ```console
+//! $ cargo run -q --features serde --example diff old.json new.json
+//! ```
+
+use std::env;
+use std::fs::File;
+use std::process;
+
+use cymrust::CymruIP2ASN;
+
+fn load(path: &str) -> Vec<CymruIP2ASN> {
+    let file = File::open(path).unwrap_or_else(|err| {
+        eprintln!("{}: {}", path, err);
+        process::exit(2);
+    });
+    serde_json::from_reader(file).unwrap_or_else(|err| {
+        eprintln!("{}: {}", path, err);
+        process::exit(2);
+    })
+}
+
+fn main() {
+    let args: Vec<String> = env::args().skip(1).collect();
+    if args.len() != 2 {
+        eprintln!("usage: diff OLD.json NEW.json");
+        process::exit(2);
+    }
+
+    let changes = cymrust::diff::diff(&load(&args[0]), &load(&args[1]));
+    for change in &changes {
+        println!("{}", change);
+    }
+    if !changes.is_empty() {
+        process::exit(1);
+    }
+}
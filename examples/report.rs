@@ -0,0 +1,20 @@
+use std::io::{self, BufRead};
+use std::net::IpAddr;
+
+fn main() {
+    let mut results = Vec::new();
+
+    for line in io::stdin().lock().lines() {
+        let line = line.unwrap();
+        let ip: IpAddr = match line.trim().parse() {
+            Ok(ip) => ip,
+            Err(_) => continue,
+        };
+        match cymrust::cymru_ip2asn(ip) {
+            Ok(mut found) => results.append(&mut found),
+            Err(err) => eprintln!("{}: {}", ip, err),
+        }
+    }
+
+    println!("{}", cymrust::report::report(&results));
+}
@@ -5,6 +5,8 @@ fn main() {
     let first_arg = env::args().nth(1).unwrap();
     let ip: IpAddr = first_arg.parse().unwrap();
 
-    let cymru = cymrust::cymru_ip2asn(ip);
-    println!("{:#?}", cymru)
+    match cymrust::cymru_ip2asn(ip) {
+        Ok(results) => print!("{}", cymrust::format::whois_verbose(&results)),
+        Err(err) => eprintln!("{}", err),
+    }
 }
@@ -0,0 +1,45 @@
+/*
+ * C interface of cymrust, available with the `ffi` feature.
+ *
+ * All strings are NUL terminated UTF-8 and owned by the results; they stay
+ * valid until cymrust_results_free() is called.
+ */
+
+#ifndef CYMRUST_H
+#define CYMRUST_H
+
+#include <stddef.h>
+#include <stdint.h>
+
+#ifdef __cplusplus
+extern "C" {
+#endif
+
+typedef struct CymrustIp2Asn {
+    char *ip_addr;
+    char *bgp_prefix;
+    uint32_t as_number;
+    char *as_name;
+    char *country_code;
+    char *registry;
+    char *allocated;  /* NULL if not known */
+    uint64_t expires; /* seconds since the Unix epoch */
+} CymrustIp2Asn;
+
+typedef struct CymrustResults {
+    CymrustIp2Asn *items;
+    size_t len;
+    char *error; /* NULL if lookup succeeded */
+} CymrustResults;
+
+/* Query IP-to-ASN mapping for IP address. Never returns NULL. */
+CymrustResults *cymrust_ip2asn(const char *ip);
+
+/* Release results returned by cymrust_ip2asn(). Accepts NULL. */
+void cymrust_results_free(CymrustResults *results);
+
+#ifdef __cplusplus
+}
+#endif
+
+#endif /* CYMRUST_H */
@@ -0,0 +1,184 @@
+//! Append-only log of every lookup a client performs
+//!
+//! [`AuditLog`](struct.AuditLog.html) is given to a client with
+//! [`CymruClient::with_audit_log`](../struct.CymruClient.html#method.with_audit_log).
+//! It writes one JSON object per line for each lookup, recording exactly
+//! which DNS names were queried on its behalf:
+//!
+//! ```text
+//! {"timestamp":"2021-03-01T12:00:00.000Z","input":"192.0.2.1","backend":"cymru-dns","queries":["1.2.0.192.origin.asn.cymru.com.","AS64500.asn.cymru.com."],"outcome":"ok","latency_ms":12.345}
+//! ```
+//!
+//! Failed lookups have `"outcome":"error"` and an `"error"` field with the
+//! error message. Lines are flushed as they are written, so the log survives
+//! the process being killed.
+
+use std::fmt::{self, Write as _};
+use std::fs::OpenOptions;
+use std::io::{self, Write};
+use std::path::Path;
+use std::sync::Mutex;
+use std::time::{Duration, SystemTime};
+
+use chrono::{DateTime, SecondsFormat, Utc};
+
+use crate::client::QueryStats;
+use crate::Error;
+
+/// Sink writing one JSON line per lookup
+///
+pub struct AuditLog {
+    writer: Mutex<Box<dyn Write + Send>>,
+}
+
+impl AuditLog {
+    /// Write audit lines to `writer`
+    ///
+    pub fn new<W: Write + Send + 'static>(writer: W) -> Self {
+        AuditLog {
+            writer: Mutex::new(Box::new(writer)),
+        }
+    }
+
+    /// Write audit lines by appending them to file at `path`
+    ///
+    pub fn append<T: AsRef<Path>>(path: T) -> io::Result<Self> {
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+        Ok(Self::new(file))
+    }
+
+    /// Write line about lookup of `input`, which sent `queries` to `backend`
+    ///
+    /// Failing to write is logged but doesn't fail the lookup.
+    ///
+    pub(crate) fn record<T>(
+        &self,
+        timestamp: SystemTime,
+        input: &dyn fmt::Display,
+        backend: &str,
+        queries: &[QueryStats],
+        result: &Result<T, Error>,
+        latency: Duration,
+    ) {
+        let mut line = String::from("{");
+        let timestamp =
+            DateTime::<Utc>::from(timestamp).to_rfc3339_opts(SecondsFormat::Millis, true);
+        let _ = write!(line, "\"timestamp\":{}", json_string(&timestamp));
+        let _ = write!(line, ",\"input\":{}", json_string(&input.to_string()));
+        let _ = write!(line, ",\"backend\":{}", json_string(backend));
+        line.push_str(",\"queries\":[");
+        for (i, query) in queries.iter().enumerate() {
+            if i > 0 {
+                line.push(',');
+            }
+            line.push_str(&json_string(&query.query));
+        }
+        line.push(']');
+        match result {
+            Ok(_) => line.push_str(",\"outcome\":\"ok\""),
+            Err(err) => {
+                let _ = write!(
+                    line,
+                    ",\"outcome\":\"error\",\"error\":{}",
+                    json_string(&err.to_string())
+                );
+            }
+        }
+        let _ = writeln!(
+            line,
+            ",\"latency_ms\":{:.3}}}",
+            latency.as_secs_f64() * 1000.0
+        );
+
+        let mut writer = match self.writer.lock() {
+            Ok(writer) => writer,
+            Err(poisoned) => poisoned.into_inner(),
+        };
+        if let Err(err) = writer
+            .write_all(line.as_bytes())
+            .and_then(|_| writer.flush())
+        {
+            warn!("Failed to write audit log: {}", err);
+        }
+    }
+}
+
+/// Quote `s` as a JSON string
+///
+pub(crate) fn json_string(s: &str) -> String {
+    let mut quoted = String::with_capacity(s.len() + 2);
+    quoted.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => quoted.push_str("\\\""),
+            '\\' => quoted.push_str("\\\\"),
+            '\n' => quoted.push_str("\\n"),
+            '\r' => quoted.push_str("\\r"),
+            '\t' => quoted.push_str("\\t"),
+            c if (c as u32) < 0x20 => {
+                let _ = write!(quoted, "\\u{:04x}", c as u32);
+            }
+            c => quoted.push(c),
+        }
+    }
+    quoted.push('"');
+    quoted
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{self, Write};
+    use std::sync::{Arc, Mutex};
+
+    use super::{json_string, AuditLog};
+    use crate::{fixtures, CymruClient};
+
+    #[derive(Clone, Default)]
+    struct SharedBuf(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for SharedBuf {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.lock().unwrap().write(buf)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_audit_log() {
+        let buf = SharedBuf::default();
+        let client = CymruClient::with_provider(fixtures::mock_provider())
+            .with_audit_log(AuditLog::new(buf.clone()));
+
+        client.ip2asn("8.8.8.8".parse().unwrap()).unwrap();
+        client.asn(64511u32).unwrap_err();
+
+        let log = String::from_utf8(buf.0.lock().unwrap().clone()).unwrap();
+        let lines: Vec<serde_json::Value> = log
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        assert_eq!(lines.len(), 2);
+
+        assert_eq!(lines[0]["input"], "8.8.8.8");
+        assert_eq!(lines[0]["backend"], "mock");
+        assert_eq!(
+            lines[0]["queries"],
+            serde_json::json!(["8.8.8.8.origin.asn.cymru.com.", "AS15169.asn.cymru.com."])
+        );
+        assert_eq!(lines[0]["outcome"], "ok");
+        assert!(lines[0]["latency_ms"].is_number());
+        assert!(lines[0]["timestamp"].as_str().unwrap().ends_with('Z'));
+
+        assert_eq!(lines[1]["input"], "AS64511");
+        assert_eq!(lines[1]["outcome"], "error");
+        assert!(lines[1]["error"].is_string());
+    }
+
+    #[test]
+    fn test_json_string() {
+        assert_eq!(json_string("a\"b\\c\n\u{1}"), "\"a\\\"b\\\\c\\n\\u0001\"");
+    }
+}
@@ -0,0 +1,325 @@
+//! Record real answers to a cassette file and replay them later
+//!
+//! [`RecordingProvider`](struct.RecordingProvider.html) wraps another
+//! provider and appends every successful answer to a cassette.
+//! [`ReplayProvider`](struct.ReplayProvider.html) serves those answers back
+//! without any network access, so integration tests run deterministically
+//! offline.
+//!
+//! Cassettes are plain text, one answer per line:
+//!
+//! ```text
+//! <query name> TAB <ttl seconds> [TAB cname=<canonical name>] TAB <record> ...
+//! ```
+//!
+//! The `cname=` field is there when the query name is an alias. Backslashes,
+//! tabs and line breaks inside fields are escaped as `\\`, `\t`, `\r` and
+//! `\n`, and records starting with `cname=` as `\cname=`. Empty lines and
+//! lines starting with `#` are ignored.
+
+use std::collections::HashMap;
+use std::fs::{File, OpenOptions};
+use std::io::{self, BufRead, BufReader, Write};
+use std::net::IpAddr;
+use std::path::Path;
+use std::sync::Mutex;
+use std::time::Duration;
+
+use crate::provider::{Provider, TxtAnswer};
+use crate::{Error, Stage};
+
+/// Start of the cassette field naming the canonical name of an alias
+const CNAME_FIELD: &str = "cname=";
+
+/// Provider recording answers of another provider into a cassette
+///
+pub struct RecordingProvider<P> {
+    inner: P,
+    cassette: Mutex<Box<dyn Write + Send>>,
+}
+
+impl<P: Provider> RecordingProvider<P> {
+    /// Record answers of `inner` into `cassette` writer
+    ///
+    pub fn new<W: Write + Send + 'static>(inner: P, cassette: W) -> Self {
+        RecordingProvider {
+            inner,
+            cassette: Mutex::new(Box::new(cassette)),
+        }
+    }
+
+    /// Record answers of `inner` by appending them to cassette file at `path`
+    ///
+    pub fn append<T: AsRef<Path>>(inner: P, path: T) -> io::Result<Self> {
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+        Ok(Self::new(inner, file))
+    }
+}
+
+impl<P: Provider> Provider for RecordingProvider<P> {
+    fn name(&self) -> &'static str {
+        self.inner.name()
+    }
+
+    fn is_network(&self) -> bool {
+        self.inner.is_network()
+    }
+
+    /// Reverse lookups are passed through, but not recorded
+    fn ptr(&self, query: &str, stage: Stage) -> Result<Vec<String>, Error> {
+        self.inner.ptr(query, stage)
+    }
+
+    /// Address lookups are passed through, but not recorded
+    fn addrs(&self, query: &str, stage: Stage) -> Result<Vec<IpAddr>, Error> {
+        self.inner.addrs(query, stage)
+    }
+
+    fn txt(&self, query: &str, stage: Stage) -> Result<TxtAnswer, Error> {
+        let answer = self.inner.txt(query, stage)?;
+
+        let mut line = escape(query);
+        line.push('\t');
+        line.push_str(&answer.ttl.as_secs().to_string());
+        if let Some(canonical_name) = &answer.canonical_name {
+            line.push('\t');
+            line.push_str(CNAME_FIELD);
+            line.push_str(&escape(canonical_name));
+        }
+        for record in &answer.records {
+            line.push('\t');
+            if record.starts_with(CNAME_FIELD) {
+                line.push('\\');
+            }
+            line.push_str(&escape(record));
+        }
+        line.push('\n');
+
+        let mut cassette = match self.cassette.lock() {
+            Ok(cassette) => cassette,
+            Err(poisoned) => poisoned.into_inner(),
+        };
+        cassette
+            .write_all(line.as_bytes())
+            .and_then(|_| cassette.flush())?;
+
+        Ok(answer)
+    }
+}
+
+/// Provider serving answers from a recorded cassette
+///
+/// Queries missing from the cassette fail with `Error::NoResultsFound`. When
+/// the same query was recorded several times, the last answer wins.
+///
+#[derive(Debug, Clone, Default)]
+pub struct ReplayProvider {
+    answers: HashMap<String, TxtAnswer>,
+}
+
+impl ReplayProvider {
+    /// Load cassette from file at `path`
+    ///
+    pub fn open<T: AsRef<Path>>(path: T) -> io::Result<Self> {
+        Self::from_reader(BufReader::new(File::open(path)?))
+    }
+
+    /// Load cassette from `reader`
+    ///
+    /// # Errors
+    ///
+    /// Returns `io::ErrorKind::InvalidData` error for lines that are not valid
+    /// cassette entries.
+    ///
+    pub fn from_reader<R: BufRead>(reader: R) -> io::Result<Self> {
+        let mut answers = HashMap::new();
+
+        for line in reader.lines() {
+            let line = line?;
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            let mut fields = line.split('\t');
+            let query = fields.next().map(unescape).unwrap_or_default();
+            let ttl: u64 = match fields.next().map(str::parse) {
+                Some(Ok(ttl)) => ttl,
+                _ => {
+                    return Err(io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        format!("invalid cassette line {:?}", line),
+                    ))
+                }
+            };
+            let mut fields = fields.peekable();
+            let canonical_name = fields
+                .next_if(|field| field.starts_with(CNAME_FIELD))
+                .map(|field| unescape(&field[CNAME_FIELD.len()..]));
+            let answer = TxtAnswer {
+                ttl: Duration::from_secs(ttl),
+                records: fields.map(unescape).collect(),
+                discarded: 0,
+                canonical_name,
+                answered_by: None,
+            };
+            answers.insert(query, answer);
+        }
+
+        Ok(ReplayProvider { answers })
+    }
+}
+
+impl Provider for ReplayProvider {
+    fn name(&self) -> &'static str {
+        "replay"
+    }
+
+    fn is_network(&self) -> bool {
+        false
+    }
+
+    fn txt(&self, query: &str, stage: Stage) -> Result<TxtAnswer, Error> {
+        match self.answers.get(query) {
+            Some(answer) => Ok(answer.clone()),
+            None => Err(Error::NoResultsFound {
+                query: query.to_string(),
+                stage,
+            }),
+        }
+    }
+}
+
+/// Escape cassette field separators in `field`
+///
+fn escape(field: &str) -> String {
+    let mut escaped = String::with_capacity(field.len());
+    for c in field.chars() {
+        match c {
+            '\\' => escaped.push_str("\\\\"),
+            '\t' => escaped.push_str("\\t"),
+            '\n' => escaped.push_str("\\n"),
+            '\r' => escaped.push_str("\\r"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+/// Reverse `escape`
+///
+fn unescape(field: &str) -> String {
+    let mut unescaped = String::with_capacity(field.len());
+    let mut chars = field.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            unescaped.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('t') => unescaped.push('\t'),
+            Some('n') => unescaped.push('\n'),
+            Some('r') => unescaped.push('\r'),
+            Some(other) => unescaped.push(other),
+            None => unescaped.push('\\'),
+        }
+    }
+    unescaped
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{self, Write};
+    use std::sync::{Arc, Mutex};
+    use std::time::Duration;
+
+    use super::{escape, unescape, RecordingProvider, ReplayProvider};
+    use crate::provider::{MockProvider, Provider, TxtAnswer};
+    use crate::{Error, Stage};
+
+    #[derive(Clone, Default)]
+    struct SharedBuf(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for SharedBuf {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.lock().unwrap().write(buf)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_escape_roundtrip() {
+        let field = "a\tb\\c\nd";
+        assert_eq!(escape(field), "a\\tb\\\\c\\nd");
+        assert_eq!(unescape(&escape(field)), field);
+    }
+
+    /// Provider answering as if every query name were an alias
+    struct Aliased(MockProvider);
+
+    impl Provider for Aliased {
+        fn name(&self) -> &'static str {
+            self.0.name()
+        }
+
+        fn txt(&self, query: &str, stage: Stage) -> Result<TxtAnswer, Error> {
+            let mut answer = self.0.txt(query, stage)?;
+            answer.canonical_name = Some("origin.example.net.".to_string());
+            Ok(answer)
+        }
+    }
+
+    #[test]
+    fn test_record_and_replay() {
+        let mock = MockProvider::new().answer(
+            "AS64500.asn.cymru.com.",
+            Duration::from_secs(300),
+            &["64500 | US | arin | 2010-01-01 | EXAMPLE\tTAB"],
+        );
+        let buf = SharedBuf::default();
+        let recorder = RecordingProvider::new(mock, buf.clone());
+        let recorded = recorder.txt("AS64500.asn.cymru.com.", Stage::Asn).unwrap();
+        assert!(recorder.txt("AS64501.asn.cymru.com.", Stage::Asn).is_err());
+
+        let cassette = buf.0.lock().unwrap().clone();
+        let replay = ReplayProvider::from_reader(&cassette[..]).unwrap();
+        let replayed = replay.txt("AS64500.asn.cymru.com.", Stage::Asn).unwrap();
+        assert_eq!(replayed, recorded);
+        assert!(replay.txt("AS64501.asn.cymru.com.", Stage::Asn).is_err());
+
+        // Aliases keep their canonical name, and records looking like one
+        // stay records
+        let query = "1.2.0.192.origin.asn.cymru.com.";
+        let aliased = MockProvider::new().answer(
+            query,
+            Duration::from_secs(60),
+            &[
+                "cname=not really",
+                "64500 | 192.0.2.0/24 | US | arin | 2010-01-01",
+            ],
+        );
+        let buf = SharedBuf::default();
+        let recorder = RecordingProvider::new(Aliased(aliased), buf.clone());
+        let recorded = recorder.txt(query, Stage::Origin).unwrap();
+
+        let cassette = buf.0.lock().unwrap().clone();
+        assert!(String::from_utf8_lossy(&cassette).starts_with(
+            "1.2.0.192.origin.asn.cymru.com.\t60\tcname=origin.example.net.\t\\cname="
+        ));
+        let replay = ReplayProvider::from_reader(&cassette[..]).unwrap();
+        let replayed = replay.txt(query, Stage::Origin).unwrap();
+        assert_eq!(
+            replayed.canonical_name.as_deref(),
+            Some("origin.example.net.")
+        );
+        assert_eq!(replayed, recorded);
+    }
+
+    #[test]
+    fn test_replay_invalid_line() {
+        let cassette = "# comment\n\nAS1.asn.cymru.com.\tnot-a-ttl\n";
+        assert!(ReplayProvider::from_reader(cassette.as_bytes()).is_err());
+    }
+}
@@ -0,0 +1,2539 @@
+//! Reusable client for Cymru's IP-to-ASN mapping service
+
+use std::cmp;
+use std::collections::{BTreeMap, BTreeSet, HashMap, VecDeque};
+use std::fmt;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::{Arc, Condvar, Mutex, MutexGuard, OnceLock};
+use std::thread;
+use std::time::{Duration, Instant, SystemTime};
+
+use trust_dns_resolver::error::ResolveErrorKind;
+use trust_dns_resolver::proto::op::ResponseCode;
+use trust_dns_resolver::Resolver;
+
+use crate::audit::AuditLog;
+#[cfg(feature = "geo")]
+use crate::geo::{Geo, GeoLookup};
+use crate::provider::{DnsProvider, Provider, TxtAnswer, TxtRecords};
+use crate::special::special_purpose;
+use crate::{
+    asn_query, escape_as_name, flatten_origins, origin_anomaly, origin_query, parse_cymru_asn,
+    parse_cymru_prefix_origins, ptr_query, trace, AsNumber, CymruASN, CymruIP2ASN, CymruOrigin,
+    CymruPrefixOrigins, DedupPolicy, Error, OriginAnomaly, Source, Stage, Strictness, Target,
+};
+
+/// Hooks for bridging client activity into an application's metrics system
+///
+/// All methods have empty default implementations, so implementors only need
+/// to override the events they care about. Hooks are called synchronously
+/// from the thread doing the lookup and should return quickly.
+///
+/// Every hook gets the `backend` the query was sent to, as named by
+/// [`Provider::name`](provider/trait.Provider.html#tymethod.name), so that
+/// slow or failing sources can be told apart when several are in use.
+///
+pub trait Metrics: Send + Sync {
+    /// DNS query for `query` name is about to be sent to `backend`
+    fn query_start(&self, _backend: &str, _query: &str) {}
+
+    /// DNS query for `query` name finished at `backend`, successfully or
+    /// not, after `elapsed` time
+    fn query_finish(&self, _backend: &str, _query: &str, _elapsed: Duration) {}
+
+    /// DNS query for `query` name failed at `backend` with `error`
+    fn error(&self, _backend: &str, _query: &str, _error: &Error) {}
+
+    /// DNS query for `query` name failed transiently at `backend` and is
+    /// sent again, for the `retry`th time
+    ///
+    /// See [`CymruClient::with_retries`](struct.CymruClient.html#method.with_retries).
+    ///
+    fn retry(&self, _backend: &str, _query: &str, _retry: usize) {}
+
+    /// Answer for `query` name was found in the client's `cache` instead of
+    /// being queried
+    fn cache_hit(&self, _cache: &str, _query: &str) {}
+
+    /// Answer for `query` name was not found in the client's `cache`, so it
+    /// is queried
+    fn cache_miss(&self, _cache: &str, _query: &str) {}
+}
+
+/// Timing and bookkeeping for a single lookup
+///
+/// Returned alongside results by
+/// [`CymruClient::ip2asn_with_stats`](struct.CymruClient.html#method.ip2asn_with_stats)
+/// and [`CymruClient::asn_with_stats`](struct.CymruClient.html#method.asn_with_stats).
+///
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct LookupStats {
+    /// DNS queries performed, in the order they were sent
+    pub queries: Vec<QueryStats>,
+    /// Answers served from a cache instead of DNS
+    pub cache_hits: usize,
+}
+
+impl LookupStats {
+    /// Number of DNS round trips performed
+    ///
+    pub fn round_trips(&self) -> usize {
+        self.queries.len()
+    }
+
+    /// Total time spent waiting for DNS answers
+    ///
+    pub fn total_duration(&self) -> Duration {
+        self.queries.iter().map(|q| q.duration).sum()
+    }
+
+    /// Total number of TXT records discarded
+    ///
+    /// Results of lookups which discarded records may be incomplete, so
+    /// consumers may want to trust them less.
+    ///
+    pub fn discarded(&self) -> usize {
+        self.queries.iter().map(|q| q.discarded).sum()
+    }
+
+    /// Queries, failures and time spent, summed up per backend
+    ///
+    pub fn by_backend(&self) -> BTreeMap<&'static str, BackendStats> {
+        let mut backends = BTreeMap::<_, BackendStats>::new();
+        for query in &self.queries {
+            let backend = backends.entry(query.backend).or_default();
+            backend.queries += 1;
+            backend.duration += query.duration;
+            if !query.success {
+                backend.errors += 1;
+            }
+        }
+        backends
+    }
+}
+
+/// Statistics of the queries sent to one backend during a lookup
+///
+/// Returned by [`LookupStats::by_backend`](struct.LookupStats.html#method.by_backend).
+///
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct BackendStats {
+    /// Number of queries sent
+    pub queries: usize,
+    /// Number of queries which failed
+    pub errors: usize,
+    /// Total time spent waiting for answers
+    pub duration: Duration,
+}
+
+/// Statistics of a single DNS query
+///
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct QueryStats {
+    /// DNS name queried
+    pub query: String,
+    /// Backend which answered the query
+    pub backend: &'static str,
+    /// Time from sending the query to receiving the answer or error
+    pub duration: Duration,
+    /// Whether the query succeeded
+    pub success: bool,
+    /// Number of TXT records received
+    pub records: usize,
+    /// Number of received TXT records which were discarded for not being
+    /// valid UTF-8 or not parsing
+    pub discarded: usize,
+    /// Name the answer was found under, if the queried name is an alias
+    /// (CNAME) of it
+    pub canonical_name: Option<String>,
+}
+
+/// Raw DNS answer as received, before any parsing
+///
+/// Passed to the hook registered with
+/// [`CymruClient::with_response_hook`](struct.CymruClient.html#method.with_response_hook).
+///
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RawResponse<'a> {
+    /// DNS name queried
+    pub query: &'a str,
+    /// Time to live of the answer
+    pub ttl: Duration,
+    /// TXT record strings of the answer
+    pub records: &'a TxtRecords,
+}
+
+type ResponseHook = Box<dyn Fn(&RawResponse<'_>) + Send + Sync>;
+
+/// IP-to-ASN results together with the address' reverse DNS name
+///
+/// Returned by [`CymruClient::ip_info`](struct.CymruClient.html#method.ip_info).
+/// Fields are added with features, e.g. `geo`, so values are built with
+/// [`new`](#method.new) rather than struct literals.
+///
+#[derive(Debug, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct IpInfo {
+    /// IP address looked up
+    pub ip: IpAddr,
+    /// IP-to-ASN mapping results
+    pub results: Vec<CymruIP2ASN>,
+    /// Host name from the address' PTR record, without the trailing dot
+    ///
+    /// `None` if reverse DNS is disabled or the lookup found no name.
+    pub hostname: Option<String>,
+    /// Location from the Geo-IP database set with
+    /// [`CymruClient::with_geo`](struct.CymruClient.html#method.with_geo)
+    ///
+    /// This is unrelated to the registry `country_code` of the results.
+    #[cfg(feature = "geo")]
+    pub geo: Option<Geo>,
+}
+
+impl IpInfo {
+    /// Create info of `ip` with given results and reverse DNS name, and no
+    /// location
+    ///
+    pub fn new(ip: IpAddr, results: Vec<CymruIP2ASN>, hostname: Option<String>) -> Self {
+        IpInfo {
+            ip,
+            results,
+            hostname,
+            #[cfg(feature = "geo")]
+            geo: None,
+        }
+    }
+}
+
+/// Number of threads doing the queries of a bulk lookup
+const BULK_WORKERS: usize = 8;
+
+/// Limits on upstream failures during a bulk lookup
+///
+/// When a bulk job exceeds its budget, the upstream is clearly unhealthy and
+/// the inputs not looked up yet fail with `Error::BudgetExhausted` instead of
+/// being queried. Only resolver errors and rate limiting count as failures;
+/// inputs without results are a normal outcome.
+///
+/// ```
+/// use cymrust::{fixtures, CymruClient, ErrorBudget};
+///
+/// let client = CymruClient::with_provider(fixtures::mock_provider());
+/// let client = client.with_error_budget(ErrorBudget {
+///     max_errors: 1000,
+///     max_error_ratio: 0.2,
+///     min_lookups: 100,
+/// });
+/// ```
+///
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ErrorBudget {
+    /// Maximum number of failed lookups
+    pub max_errors: usize,
+    /// Maximum fraction of failed lookups, from 0.0 to 1.0
+    pub max_error_ratio: f64,
+    /// Number of lookups to do before `max_error_ratio` applies
+    pub min_lookups: usize,
+}
+
+/// Bounds on the size of answers a client accepts
+///
+/// Answers exceeding any of these fail with `Error::ResponseTooLarge`
+/// instead of being parsed, so a misbehaving resolver or poisoned cache
+/// can't make a long-running service balloon in memory. Cymru's answers are
+/// a few short records, far below the defaults.
+///
+/// ```
+/// use cymrust::{fixtures, CymruClient, ResponseLimits};
+///
+/// let client = CymruClient::with_provider(fixtures::mock_provider());
+/// let client = client.with_response_limits(ResponseLimits {
+///     max_records: 16,
+///     ..ResponseLimits::default()
+/// });
+/// ```
+///
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ResponseLimits {
+    /// Maximum number of TXT records in an answer, counting discarded ones
+    pub max_records: usize,
+    /// Maximum length of a single TXT record, in bytes
+    pub max_record_len: usize,
+    /// Maximum number of results parsed from an answer, counting every
+    /// origin AS of a prefix separately
+    pub max_results: usize,
+}
+
+impl Default for ResponseLimits {
+    fn default() -> Self {
+        ResponseLimits {
+            max_records: 256,
+            max_record_len: 4096,
+            max_results: 256,
+        }
+    }
+}
+
+impl ResponseLimits {
+    /// Fail for `query` if `size` exceeds `max`
+    ///
+    fn check(
+        query: &str,
+        stage: Stage,
+        limit: &'static str,
+        size: usize,
+        max: usize,
+    ) -> Result<(), Error> {
+        if size <= max {
+            return Ok(());
+        }
+        warn!(
+            "Rejecting answer to {}: {} {} > {}",
+            query, limit, size, max
+        );
+        Err(Error::ResponseTooLarge {
+            query: query.to_string(),
+            stage,
+            limit,
+            size,
+            max,
+        })
+    }
+}
+
+/// Adjustment of the time to live reflected in results' `expires`
+///
+/// Cymru's TTLs are short, which suits the resolver's own cache but not
+/// consumers persisting results into stores of their own. The TTL of each
+/// answer is first multiplied by `scale`, then clamped between `min` and
+/// `max`. Only the `expires` of results changes; DNS answers are still cached
+/// by the resolver for as long as they say.
+///
+/// ```
+/// use std::time::Duration;
+/// use cymrust::{fixtures, CymruClient, ResultTtl};
+///
+/// // Never less than an hour, never more than a day
+/// let client = CymruClient::with_provider(fixtures::mock_provider());
+/// let client = client.with_result_ttl(ResultTtl {
+///     min: Duration::from_secs(3600),
+///     max: Duration::from_secs(86400),
+///     ..ResultTtl::default()
+/// });
+/// ```
+///
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ResultTtl {
+    /// Factor to multiply TTLs with, 1.0 keeping them as they are
+    pub scale: f64,
+    /// Shortest TTL to put in results
+    pub min: Duration,
+    /// Longest TTL to put in results
+    pub max: Duration,
+}
+
+impl Default for ResultTtl {
+    /// TTLs as they are
+    fn default() -> Self {
+        ResultTtl {
+            scale: 1.0,
+            min: Duration::from_secs(0),
+            max: Duration::MAX,
+        }
+    }
+}
+
+impl ResultTtl {
+    /// Adjust answer `ttl`
+    ///
+    pub fn apply(&self, ttl: Duration) -> Duration {
+        let scaled = Duration::try_from_secs_f64(ttl.as_secs_f64() * self.scale.max(0.0))
+            .unwrap_or(Duration::MAX);
+        cmp::max(cmp::min(scaled, self.max), self.min)
+    }
+}
+
+/// Most AS numbers whose details a client remembers at once
+const ASN_MEMO_CAPACITY: usize = 4096;
+
+/// Name of the AS details memo in cache hooks of `Metrics`
+const ASN_MEMO_CACHE: &str = "asn-memo";
+
+/// AS details remembered across lookups for the TTL of their answers
+///
+/// Expiry is judged with `Instant`, so clock steps don't extend or cut
+/// short the time answers are remembered.
+///
+#[derive(Default)]
+struct AsnMemo {
+    entries: Mutex<HashMap<AsNumber, (Instant, Vec<CymruASN>)>>,
+}
+
+impl AsnMemo {
+    fn lock(&self) -> MutexGuard<'_, HashMap<AsNumber, (Instant, Vec<CymruASN>)>> {
+        match self.entries.lock() {
+            Ok(entries) => entries,
+            Err(poisoned) => poisoned.into_inner(),
+        }
+    }
+
+    /// Remembered details of `asn`, unless expired
+    ///
+    fn get(&self, asn: AsNumber) -> Option<Vec<CymruASN>> {
+        let entries = self.lock();
+        let (valid_until, results) = entries.get(&asn)?;
+        if *valid_until <= Instant::now() {
+            return None;
+        }
+        Some(results.clone())
+    }
+
+    /// Remember `results` for `asn` for `ttl`
+    ///
+    /// When full, expired entries are dropped first. If none are, `results`
+    /// are not remembered.
+    ///
+    fn insert(&self, asn: AsNumber, results: &[CymruASN], ttl: Duration) {
+        let now = Instant::now();
+        let valid_until = match now.checked_add(ttl) {
+            Some(valid_until) if valid_until > now => valid_until,
+            _ => return,
+        };
+        let mut entries = self.lock();
+        if entries.len() >= ASN_MEMO_CAPACITY && !entries.contains_key(&asn) {
+            entries.retain(|_, (valid_until, _)| *valid_until > now);
+            if entries.len() >= ASN_MEMO_CAPACITY {
+                return;
+            }
+        }
+        entries.insert(asn, (valid_until, results.to_vec()));
+    }
+}
+
+/// AS details remembered by the clients of the crate's top-level functions
+///
+fn shared_asn_memo() -> Arc<AsnMemo> {
+    static MEMO: OnceLock<Arc<AsnMemo>> = OnceLock::new();
+    MEMO.get_or_init(Arc::default).clone()
+}
+
+/// Length of IPv4 networks whose addresses share their origin answer in
+/// practice, as routes more specific than this are rarely propagated
+///
+const NETWORK_PREFIX_V4: u32 = 24;
+
+/// Length of IPv6 networks whose addresses share their origin answer in
+/// practice, like `NETWORK_PREFIX_V4`
+///
+const NETWORK_PREFIX_V6: u32 = 48;
+
+/// The /24 (IPv4) or /48 (IPv6) network `ip` belongs to, as its first
+/// address
+///
+fn network(ip: IpAddr) -> IpAddr {
+    match ip {
+        IpAddr::V4(ipv4) => {
+            let mask = u32::MAX << (32 - NETWORK_PREFIX_V4);
+            IpAddr::V4(Ipv4Addr::from(u32::from(ipv4) & mask))
+        }
+        IpAddr::V6(ipv6) => {
+            let mask = u128::MAX << (128 - NETWORK_PREFIX_V6);
+            IpAddr::V6(Ipv6Addr::from(u128::from(ipv6) & mask))
+        }
+    }
+}
+
+/// Whether `origins` answered for `ip` are all routes covering its whole
+/// network, so that they are the answer for every address in it too
+///
+fn covers_network(ip: IpAddr, origins: &[CymruOrigin]) -> bool {
+    let max = match ip {
+        IpAddr::V4(_) => NETWORK_PREFIX_V4,
+        IpAddr::V6(_) => NETWORK_PREFIX_V6,
+    };
+    origins.iter().all(|origin| {
+        let len = origin
+            .bgp_prefix
+            .rsplit_once('/')
+            .map(|(_, len)| len.parse::<u32>());
+        matches!(len, Some(Ok(len)) if len <= max)
+    })
+}
+
+/// Whether `err` may well not happen again if the query is sent again,
+/// e.g. a timeout or SERVFAIL, rather than an answer that there's nothing
+/// to find or a refusal
+///
+fn transient(err: &Error) -> bool {
+    match err {
+        Error::Resolver { source, .. } => match source.kind() {
+            ResolveErrorKind::NoRecordsFound { response_code, .. } => {
+                *response_code == ResponseCode::ServFail
+            }
+            _ => true,
+        },
+        _ => false,
+    }
+}
+
+/// Whether `result` is a failure of the upstream, e.g. SERVFAIL, timeout or
+/// rate limiting, rather than e.g. an address without results
+///
+fn upstream_failure<T>(result: &Result<T, Error>) -> bool {
+    matches!(
+        result,
+        Err(Error::Resolver { .. }) | Err(Error::RateLimited { .. })
+    )
+}
+
+/// Limit on lookups in flight at once during a bulk job, adapting to
+/// upstream failures
+///
+/// The limit starts at `BULK_WORKERS`. An upstream failure halves it, down
+/// to one, and each run of as many successes in a row as the limit raises it
+/// by one again, additive increase, multiplicative decrease (AIMD) style.
+/// Failures of lookups started before the last decrease don't decrease it
+/// again, so that one burst of failures halves the limit only once.
+///
+struct Window {
+    state: Mutex<WindowState>,
+    changed: Condvar,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct WindowState {
+    limit: usize,
+    in_flight: usize,
+    successes: usize,
+    generation: usize,
+}
+
+impl Window {
+    fn new() -> Self {
+        Window {
+            state: Mutex::new(WindowState {
+                limit: BULK_WORKERS,
+                in_flight: 0,
+                successes: 0,
+                generation: 0,
+            }),
+            changed: Condvar::new(),
+        }
+    }
+
+    /// Wait for room for one more lookup and return the generation of the
+    /// limit it started under
+    ///
+    fn acquire(&self) -> usize {
+        let mut state = match self.state.lock() {
+            Ok(state) => state,
+            Err(poisoned) => poisoned.into_inner(),
+        };
+        while state.in_flight >= state.limit {
+            state = match self.changed.wait(state) {
+                Ok(state) => state,
+                Err(poisoned) => poisoned.into_inner(),
+            };
+        }
+        state.in_flight += 1;
+        state.generation
+    }
+
+    /// Finish lookup started under `generation`, adapting the limit to
+    /// whether it `failed`
+    ///
+    fn release(&self, generation: usize, failed: bool) {
+        let mut state = match self.state.lock() {
+            Ok(state) => state,
+            Err(poisoned) => poisoned.into_inner(),
+        };
+        state.in_flight -= 1;
+        if failed {
+            state.successes = 0;
+            if generation == state.generation && state.limit > 1 {
+                state.limit /= 2;
+                state.generation += 1;
+                debug!(
+                    "Upstream failing, limiting bulk lookup to {} at once",
+                    state.limit
+                );
+            }
+        } else {
+            state.successes += 1;
+            if state.successes >= state.limit && state.limit < BULK_WORKERS {
+                state.limit += 1;
+                state.successes = 0;
+            }
+        }
+        drop(state);
+        self.changed.notify_all();
+    }
+}
+
+/// Failure bookkeeping and concurrency control shared by all lookups of one
+/// bulk job
+///
+pub(crate) struct JobBudget<'a> {
+    budget: Option<&'a ErrorBudget>,
+    window: Window,
+    lookups: AtomicUsize,
+    errors: AtomicUsize,
+    exhausted: AtomicBool,
+}
+
+impl JobBudget<'_> {
+    /// Do `lookup` once there's room for it in the window, unless the budget
+    /// is already exhausted, and count its outcome
+    ///
+    pub(crate) fn run<T, F>(&self, lookup: F) -> Result<T, Error>
+    where
+        F: FnOnce() -> Result<T, Error>,
+    {
+        let generation = self.window.acquire();
+        let result = self.counted(lookup);
+        self.window.release(generation, upstream_failure(&result));
+        result
+    }
+
+    /// Do `lookup` unless the budget is already exhausted, and count its
+    /// outcome
+    ///
+    fn counted<T, F>(&self, lookup: F) -> Result<T, Error>
+    where
+        F: FnOnce() -> Result<T, Error>,
+    {
+        let budget = match self.budget {
+            Some(budget) => budget,
+            None => return lookup(),
+        };
+        if self.exhausted.load(Ordering::Relaxed) {
+            return Err(Error::BudgetExhausted {
+                lookups: self.lookups.load(Ordering::Relaxed),
+                errors: self.errors.load(Ordering::Relaxed),
+            });
+        }
+
+        let result = lookup();
+        let lookups = self.lookups.fetch_add(1, Ordering::Relaxed) + 1;
+        let errors = if upstream_failure(&result) {
+            self.errors.fetch_add(1, Ordering::Relaxed) + 1
+        } else {
+            self.errors.load(Ordering::Relaxed)
+        };
+        let over_ratio = lookups >= budget.min_lookups
+            && errors as f64 > budget.max_error_ratio * lookups as f64;
+        if (errors > budget.max_errors || over_ratio)
+            && !self.exhausted.swap(true, Ordering::Relaxed)
+        {
+            warn!(
+                "Aborting bulk lookup after {} errors in {} lookups",
+                errors, lookups
+            );
+        }
+        result
+    }
+}
+
+/// Call `lookup` once for every distinct key, from up to `BULK_WORKERS`
+/// threads at a time
+///
+/// Fewer lookups run at once while the upstream is failing, as limited by
+/// the job's `Window`.
+///
+pub(crate) fn bulk<K, T, I, F>(
+    keys: I,
+    job: &JobBudget<'_>,
+    lookup: F,
+) -> BTreeMap<K, Result<T, Error>>
+where
+    K: Ord + Copy + Send + Sync,
+    T: Send,
+    I: IntoIterator<Item = K>,
+    F: Fn(K) -> Result<T, Error> + Sync,
+{
+    let keys: Vec<K> = keys
+        .into_iter()
+        .collect::<BTreeSet<_>>()
+        .into_iter()
+        .collect();
+    let next = AtomicUsize::new(0);
+    let results = Mutex::new(BTreeMap::new());
+
+    thread::scope(|scope| {
+        for _ in 0..cmp::min(BULK_WORKERS, keys.len()) {
+            scope.spawn(|| {
+                while let Some(&key) = keys.get(next.fetch_add(1, Ordering::Relaxed)) {
+                    let result = job.run(|| lookup(key));
+                    let mut results = match results.lock() {
+                        Ok(results) => results,
+                        Err(poisoned) => poisoned.into_inner(),
+                    };
+                    results.insert(key, result);
+                }
+            });
+        }
+    });
+
+    match results.into_inner() {
+        Ok(results) => results,
+        Err(poisoned) => poisoned.into_inner(),
+    }
+}
+
+/// Add `discarded` records to the stats of the last query, if any
+///
+fn count_discarded(stats: Option<&mut LookupStats>, discarded: usize) {
+    if let Some(query) = stats.and_then(|s| s.queries.last_mut()) {
+        query.discarded += discarded;
+    }
+}
+
+/// Combine origin and AS details into an IP-to-ASN result for `ip`
+///
+fn ip2asn_result(
+    ip: IpAddr,
+    origin: &CymruOrigin,
+    asn: Option<&CymruASN>,
+    anomalous_origins: &Option<OriginAnomaly>,
+    source: &Source,
+) -> CymruIP2ASN {
+    CymruIP2ASN {
+        ip_addr: ip,
+        bgp_prefix: origin.bgp_prefix.clone(),
+        as_number: origin.as_number,
+        as_name: asn.map_or_else(String::new, |asn| asn.as_name.clone()),
+        country_code: origin.country_code.clone(),
+        registry: origin.registry.clone(),
+        allocated: origin.allocated.map(|s| s.to_string()),
+        expires: asn.map_or(origin.expires, |asn| cmp::min(origin.expires, asn.expires)),
+        anomalous_origins: anomalous_origins.clone(),
+        source: source.clone(),
+    }
+}
+
+/// Client for querying Cymru's IP-to-ASN mapping service
+///
+/// Unlike the [`cymru_ip2asn`](fn.cymru_ip2asn.html) and
+/// [`cymru_asn`](fn.cymru_asn.html) functions, which set up a new DNS
+/// resolver for every call, `CymruClient` owns one resolver and reuses it for
+/// all of its queries.
+///
+pub struct CymruClient {
+    provider: Box<dyn Provider>,
+    metrics: Option<Box<dyn Metrics>>,
+    response_hook: Option<ResponseHook>,
+    offline: bool,
+    prefilter: bool,
+    reverse_dns: bool,
+    error_budget: Option<ErrorBudget>,
+    retries: usize,
+    result_ttl: ResultTtl,
+    response_limits: ResponseLimits,
+    asn_memo: Option<Arc<AsnMemo>>,
+    audit_log: Option<AuditLog>,
+    backoff_until: Mutex<Option<Instant>>,
+    strictness: Strictness,
+    dedup: DedupPolicy,
+    asn_details: bool,
+    truncate_queries: bool,
+    share_networks: bool,
+    ascii_as_names: bool,
+    #[cfg(feature = "geo")]
+    geo: Option<Box<dyn GeoLookup>>,
+}
+
+impl CymruClient {
+    /// Create client using the system's DNS resolver configuration
+    ///
+    /// # Errors
+    ///
+    /// Returns `Error::Io` if system's resolver configuration can't be read.
+    ///
+    pub fn new() -> Result<Self, Error> {
+        Ok(Self::with_provider(DnsProvider::new()?))
+    }
+
+    /// Create client for the crate's top-level functions
+    ///
+    /// Like [`new`](#method.new), but AS details are remembered across all
+    /// such clients, so that calling e.g. `cymru_ip2asn` repeatedly doesn't
+    /// query the same popular AS numbers every time.
+    ///
+    pub(crate) fn shared() -> Result<Self, Error> {
+        Ok(Self::new()?.with_shared_asn_memo())
+    }
+
+    /// Remember AS details across all clients doing so, like
+    /// [`shared`](#method.shared) ones
+    ///
+    pub(crate) fn with_shared_asn_memo(mut self) -> Self {
+        self.asn_memo = Some(shared_asn_memo());
+        self
+    }
+
+    /// Create client using given, already configured, DNS resolver
+    ///
+    pub fn with_resolver(resolver: Resolver) -> Self {
+        Self::with_provider(DnsProvider::with_resolver(resolver))
+    }
+
+    /// Create client answering queries from given
+    /// [`Provider`](provider/trait.Provider.html) instead of DNS
+    ///
+    pub fn with_provider<P: Provider + 'static>(provider: P) -> Self {
+        CymruClient {
+            provider: Box::new(provider),
+            metrics: None,
+            response_hook: None,
+            offline: false,
+            prefilter: true,
+            reverse_dns: false,
+            error_budget: None,
+            retries: 0,
+            result_ttl: ResultTtl::default(),
+            response_limits: ResponseLimits::default(),
+            asn_memo: Some(Arc::default()),
+            audit_log: None,
+            backoff_until: Mutex::new(None),
+            strictness: Strictness::default(),
+            dedup: DedupPolicy::default(),
+            asn_details: true,
+            truncate_queries: false,
+            share_networks: false,
+            ascii_as_names: false,
+            #[cfg(feature = "geo")]
+            geo: None,
+        }
+    }
+
+    /// Report client activity to given `Metrics` implementation
+    ///
+    pub fn with_metrics<M: Metrics + 'static>(mut self, metrics: M) -> Self {
+        self.metrics = Some(Box::new(metrics));
+        self
+    }
+
+    /// Call `hook` with every raw DNS answer before it is parsed
+    ///
+    /// This is meant for debugging and archiving exactly what Cymru answered.
+    /// The hook is called synchronously from the thread doing the lookup.
+    /// Answers rejected for exceeding the client's
+    /// [`ResponseLimits`](struct.ResponseLimits.html) are not passed to it.
+    ///
+    pub fn with_response_hook<F>(mut self, hook: F) -> Self
+    where
+        F: Fn(&RawResponse<'_>) + Send + Sync + 'static,
+    {
+        self.response_hook = Some(Box::new(hook));
+        self
+    }
+
+    /// Refuse all network I/O when `offline` is true
+    ///
+    /// Offline clients only answer from providers which don't touch the
+    /// network, such as [`MockProvider`](provider/struct.MockProvider.html)
+    /// or [`ReplayProvider`](cassette/struct.ReplayProvider.html). Queries
+    /// that would need the network fail with `Error::Offline` instead.
+    ///
+    pub fn with_offline(mut self, offline: bool) -> Self {
+        self.offline = offline;
+        self
+    }
+
+    /// Check addresses against special-purpose registries before querying
+    ///
+    /// Enabled by default: looking up an address which is never routed,
+    /// e.g. private-use or loopback, fails with `Error::NonRoutableInput`
+    /// without sending a query. See [`special`](special/index.html).
+    ///
+    /// Only providers doing network I/O are guarded, so mock and replay
+    /// providers can still serve answers for documentation addresses.
+    ///
+    pub fn with_prefilter(mut self, prefilter: bool) -> Self {
+        self.prefilter = prefilter;
+        self
+    }
+
+    /// Also resolve the PTR record of addresses looked up with
+    /// [`ip_info`](#method.ip_info)
+    ///
+    pub fn with_reverse_dns(mut self, reverse_dns: bool) -> Self {
+        self.reverse_dns = reverse_dns;
+        self
+    }
+
+    /// Abort bulk lookups early once they exceed `budget`
+    ///
+    /// Applies to each call of [`asn_bulk`](#method.asn_bulk),
+    /// [`ip2asn_many`](#method.ip2asn_many) and
+    /// [`lookup_all`](#method.lookup_all) separately. Bulk lookups are not
+    /// limited by default.
+    ///
+    pub fn with_error_budget(mut self, budget: ErrorBudget) -> Self {
+        self.error_budget = Some(budget);
+        self
+    }
+
+    /// Adjust the `expires` of results with `result_ttl`
+    ///
+    /// Results expire when their DNS answers do by default.
+    ///
+    pub fn with_result_ttl(mut self, result_ttl: ResultTtl) -> Self {
+        self.result_ttl = result_ttl;
+        self
+    }
+
+    /// Reject answers exceeding `limits`
+    ///
+    /// Defaults to [`ResponseLimits::default`](struct.ResponseLimits.html).
+    ///
+    pub fn with_response_limits(mut self, limits: ResponseLimits) -> Self {
+        self.response_limits = limits;
+        self
+    }
+
+    /// Remember AS details across lookups when `memo` is true
+    ///
+    /// Enabled by default: popular AS numbers are then queried once per TTL
+    /// of their answer instead of on every lookup of an address they
+    /// originate. Lookups answered from memory count as
+    /// [`cache_hits`](struct.LookupStats.html#structfield.cache_hits) and
+    /// send no query. At most 4096 AS numbers are remembered at once.
+    ///
+    pub fn with_asn_memo(mut self, memo: bool) -> Self {
+        self.asn_memo = if memo { Some(Arc::default()) } else { None };
+        self
+    }
+
+    /// Write a line about every lookup to `audit_log`
+    ///
+    /// Each IP address, AS number, reverse DNS and hostname lookup is
+    /// recorded with the DNS names it queried. Bulk lookups record every
+    /// address and AS number separately. See [`audit`](audit/index.html).
+    ///
+    pub fn with_audit_log(mut self, audit_log: AuditLog) -> Self {
+        self.audit_log = Some(audit_log);
+        self
+    }
+
+    /// Parse Cymru's answers with given `strictness`
+    ///
+    /// Parsing is lenient by default, discarding malformed records. Strict
+    /// clients fail lookups with `Error::Parse` instead, for pipelines which
+    /// rather stop than work with partial data.
+    ///
+    pub fn with_strictness(mut self, strictness: Strictness) -> Self {
+        self.strictness = strictness;
+        self
+    }
+
+    /// Keep IP-to-ASN results of origins according to `dedup`
+    ///
+    /// By default there's one result per origin AS.
+    ///
+    pub fn with_dedup(mut self, dedup: DedupPolicy) -> Self {
+        self.dedup = dedup;
+        self
+    }
+
+    /// Send queries failing transiently, with a timeout, SERVFAIL or other
+    /// resolver failure, again up to `retries` times
+    ///
+    /// Every attempt is a query of its own in metrics and stats. Queries
+    /// refused as rate limited are not retried. None are by default.
+    ///
+    pub fn with_retries(mut self, retries: usize) -> Self {
+        self.retries = retries;
+        self
+    }
+
+    /// Query AS details for IP-to-ASN results when `asn_details` is true,
+    /// as by default
+    ///
+    /// Without them, results are made of the origin answer alone and have
+    /// an empty `as_name`. This saves a query per origin AS, for callers
+    /// which only need AS numbers and prefixes.
+    ///
+    pub fn with_asn_details(mut self, asn_details: bool) -> Self {
+        self.asn_details = asn_details;
+        self
+    }
+
+    /// Query origins of the whole network of addresses when `truncate` is
+    /// true
+    ///
+    /// Origin queries then name the /24 (IPv4) or /48 (IPv6) network an
+    /// address belongs to instead of the address itself, which keeps it from
+    /// Cymru and resolvers on the way. Routes more specific than that are
+    /// rarely propagated, so results hardly ever differ. They still carry the
+    /// address looked up. Reverse DNS queries, if enabled, are not affected.
+    ///
+    pub fn with_query_truncation(mut self, truncate: bool) -> Self {
+        self.truncate_queries = truncate;
+        self
+    }
+
+    /// Share origin answers within /24 (IPv4) and /48 (IPv6) networks in
+    /// [`ip2asn_many`](#method.ip2asn_many) when `share` is true
+    ///
+    /// Only one address per network is queried first, and the others reuse
+    /// its answer if every route in it covers the whole network. Otherwise
+    /// they're queried on their own. This saves queries for address lists
+    /// clustered in few networks, but Cymru answers only with the routes
+    /// covering the address queried: a more specific route announced for
+    /// another part of the network, e.g. a /25 inside a /24, is missed by the
+    /// addresses sharing the answer. Off by default.
+    ///
+    pub fn with_network_sharing(mut self, share: bool) -> Self {
+        self.share_networks = share;
+        self
+    }
+
+    /// Escape AS names to printable ASCII when `ascii` is true
+    ///
+    /// AS names are always normalized to Unicode NFC with control characters
+    /// removed. With this, anything else but printable ASCII is escaped as
+    /// `\u{...}` too, e.g. for output which can't be trusted to handle
+    /// bidirectional or invisible characters well.
+    ///
+    pub fn with_ascii_as_names(mut self, ascii: bool) -> Self {
+        self.ascii_as_names = ascii;
+        self
+    }
+
+    /// Time left to hold off sending queries, after upstream last refused
+    /// one as rate limited
+    ///
+    /// The client itself doesn't wait; this is for loops driving it, such as
+    /// [`Paced`](struct.Paced.html) iterators.
+    ///
+    pub fn backoff_remaining(&self) -> Option<Duration> {
+        let backoff_until = match self.backoff_until.lock() {
+            Ok(backoff_until) => *backoff_until,
+            Err(poisoned) => *poisoned.into_inner(),
+        };
+        backoff_until
+            .map(|until| until.saturating_duration_since(Instant::now()))
+            .filter(|remaining| *remaining > Duration::from_secs(0))
+    }
+
+    /// Start bookkeeping for a new bulk job
+    ///
+    pub(crate) fn job(&self) -> JobBudget<'_> {
+        JobBudget {
+            budget: self.error_budget.as_ref(),
+            window: Window::new(),
+            lookups: AtomicUsize::new(0),
+            errors: AtomicUsize::new(0),
+            exhausted: AtomicBool::new(false),
+        }
+    }
+
+    /// Locate addresses looked up with [`ip_info`](#method.ip_info) using
+    /// Geo-IP database `lookup`
+    ///
+    /// See [`geo`](geo/index.html) for why this is kept separate from
+    /// Cymru's data.
+    ///
+    #[cfg(feature = "geo")]
+    pub fn with_geo<G: GeoLookup + 'static>(mut self, lookup: G) -> Self {
+        self.geo = Some(Box::new(lookup));
+        self
+    }
+
+    /// Query Cymru's IP-to-ASN mapping for IP address
+    ///
+    /// See [`cymru_ip2asn`](fn.cymru_ip2asn.html) for details.
+    ///
+    pub fn ip2asn(&self, ip: IpAddr) -> Result<Vec<CymruIP2ASN>, Error> {
+        self.audited(&ip, None, |stats| self.ip2asn_stats(ip, stats))
+    }
+
+    /// Query Cymru's IP-to-ASN mapping for IP address and return
+    /// [`LookupStats`](struct.LookupStats.html) of the lookup alongside the
+    /// results
+    ///
+    pub fn ip2asn_with_stats(&self, ip: IpAddr) -> Result<(Vec<CymruIP2ASN>, LookupStats), Error> {
+        let mut stats = LookupStats::default();
+        let results = self.audited(&ip, Some(&mut stats), |stats| self.ip2asn_stats(ip, stats))?;
+        Ok((results, stats))
+    }
+
+    fn ip2asn_stats(
+        &self,
+        ip: IpAddr,
+        mut stats: Option<&mut LookupStats>,
+    ) -> Result<Vec<CymruIP2ASN>, Error> {
+        let (origins, backend) = self.origin_stats(ip, stats.as_deref_mut())?;
+        let anomaly = origin_anomaly(&origins);
+        let source = Source::from_backend(backend);
+        let mut results: Vec<CymruIP2ASN> = Vec::with_capacity(origins.len());
+        let mut asns: Vec<CymruASN> = Vec::new();
+
+        for origin in origins {
+            if self.dedup.is_duplicate(&results, &origin) {
+                continue;
+            }
+
+            let asn = if self.asn_details {
+                // Look each AS number up only once, even if kept more than once
+                let i = match asns.iter().position(|a| a.as_number == origin.as_number) {
+                    Some(i) => i,
+                    None => {
+                        let mut asn = self.asn_stats(origin.as_number, stats.as_deref_mut())?;
+                        asns.push(asn.swap_remove(0));
+                        asns.len() - 1
+                    }
+                };
+                Some(&asns[i])
+            } else {
+                None
+            };
+            results.push(ip2asn_result(ip, &origin, asn, &anomaly, &source));
+        }
+
+        if results.is_empty() {
+            return Err(Error::NoResultsFound {
+                query: self.origin_query(ip),
+                stage: Stage::Origin,
+            });
+        }
+
+        results.sort();
+        Ok(results)
+    }
+
+    /// Query Cymru's IP-to-ASN mapping for IP address and, if enabled with
+    /// [`with_reverse_dns`](#method.with_reverse_dns), its reverse DNS name
+    ///
+    /// With the `geo` feature, the address is also located using the
+    /// database set with `with_geo`, if any.
+    ///
+    /// Reverse DNS is best effort: failing to resolve the PTR record only
+    /// leaves `hostname` empty.
+    ///
+    pub fn ip_info(&self, ip: IpAddr) -> Result<IpInfo, Error> {
+        let results = self.ip2asn(ip)?;
+        let hostname = if self.reverse_dns {
+            self.hostname(ip)
+        } else {
+            None
+        };
+        let info = IpInfo::new(ip, results, hostname);
+        #[cfg(feature = "geo")]
+        let info = IpInfo {
+            geo: self.geo.as_ref().and_then(|geo| geo.locate(ip)),
+            ..info
+        };
+        Ok(info)
+    }
+
+    fn hostname(&self, ip: IpAddr) -> Option<String> {
+        let query = ptr_query(ip);
+        let names = self
+            .audited(&ip, None, |stats| {
+                self.instrumented(&query, Stage::Ptr, stats, || {
+                    self.provider.ptr(&query, Stage::Ptr)
+                })
+            })
+            .ok()?;
+        let name = names.into_iter().next()?;
+        Some(name.strip_suffix('.').unwrap_or(&name).to_string())
+    }
+
+    /// Look up the addresses of `host`
+    ///
+    pub(crate) fn host_addrs(&self, host: &str) -> Result<Vec<IpAddr>, Error> {
+        self.audited(&host, None, |stats| {
+            self.instrumented(host, Stage::Host, stats, || {
+                self.provider.addrs(host, Stage::Host)
+            })
+        })
+    }
+
+    /// Resolve information about AS number
+    ///
+    /// See [`cymru_asn`](fn.cymru_asn.html) for details.
+    ///
+    pub fn asn<I: Into<AsNumber>>(&self, asn: I) -> Result<Vec<CymruASN>, Error> {
+        let asn = asn.into();
+        self.audited(&Target::Asn(asn), None, |stats| self.asn_stats(asn, stats))
+    }
+
+    /// Resolve information about AS number and return
+    /// [`LookupStats`](struct.LookupStats.html) of the lookup alongside the
+    /// results
+    ///
+    pub fn asn_with_stats<I: Into<AsNumber>>(
+        &self,
+        asn: I,
+    ) -> Result<(Vec<CymruASN>, LookupStats), Error> {
+        let mut stats = LookupStats::default();
+        let asn = asn.into();
+        let results = self.audited(&Target::Asn(asn), Some(&mut stats), |stats| {
+            self.asn_stats(asn, stats)
+        })?;
+        Ok((results, stats))
+    }
+
+    /// Resolve information about many AS numbers concurrently
+    ///
+    /// See [`cymru_asn_bulk`](fn.cymru_asn_bulk.html) for details.
+    ///
+    pub fn asn_bulk<I>(&self, asns: I) -> BTreeMap<AsNumber, Result<Vec<CymruASN>, Error>>
+    where
+        I: IntoIterator<Item = AsNumber>,
+    {
+        bulk(asns, &self.job(), |asn| self.asn(asn))
+    }
+
+    /// Query Cymru's IP-to-ASN mapping for many IP addresses
+    ///
+    /// See [`cymru_ip2asn_many`](fn.cymru_ip2asn_many.html) for details.
+    ///
+    pub fn ip2asn_many(&self, ips: &[IpAddr]) -> Vec<Result<Vec<CymruIP2ASN>, Error>> {
+        self.ip2asn_many_in(ips, &self.job())
+    }
+
+    /// Query IP-to-ASN mapping for many IP addresses as part of bulk `job`
+    ///
+    pub(crate) fn ip2asn_many_in(
+        &self,
+        ips: &[IpAddr],
+        job: &JobBudget<'_>,
+    ) -> Vec<Result<Vec<CymruIP2ASN>, Error>> {
+        let lookup = |ip| self.audited(&ip, None, |stats| self.origin_stats(ip, stats));
+
+        // When sharing, query one address per /24 or /48 network first, and
+        // the others only if its answer has routes more specific than that,
+        // or is an error
+        let group = |ip| if self.share_networks { network(ip) } else { ip };
+        let mut firsts: BTreeMap<IpAddr, IpAddr> = BTreeMap::new();
+        for &ip in ips {
+            let first = firsts.entry(group(ip)).or_insert(ip);
+            *first = cmp::min(*first, ip);
+        }
+        let mut origins = bulk(firsts.values().copied(), job, lookup);
+        let rest: Vec<IpAddr> = ips
+            .iter()
+            .copied()
+            .filter(|ip| match &origins[&firsts[&group(*ip)]] {
+                _ if origins.contains_key(ip) => false,
+                Ok((origins, _)) => !covers_network(*ip, origins),
+                Err(_) => true,
+            })
+            .collect();
+        origins.extend(bulk(rest, job, lookup));
+
+        let asns = bulk(
+            origins
+                .values()
+                .filter(|_| self.asn_details)
+                .filter_map(|origins| origins.as_ref().ok())
+                .flat_map(|(origins, _)| origins)
+                .map(|origin| origin.as_number),
+            job,
+            |asn| self.asn(asn),
+        );
+
+        ips.iter()
+            .map(|ip| {
+                let answer = match origins.get(ip) {
+                    Some(answer) => answer,
+                    None => &origins[&firsts[&group(*ip)]],
+                };
+                let (origins, backend) = answer.as_ref().map_err(Error::duplicate)?;
+                let source = Source::from_backend(backend);
+                let anomaly = origin_anomaly(origins);
+                let mut results: Vec<CymruIP2ASN> = Vec::with_capacity(origins.len());
+                for origin in origins {
+                    if self.dedup.is_duplicate(&results, origin) {
+                        continue;
+                    }
+                    let asn = match asns.get(&origin.as_number) {
+                        Some(asn) => Some(&asn.as_ref().map_err(Error::duplicate)?[0]),
+                        None => None,
+                    };
+                    results.push(ip2asn_result(*ip, origin, asn, &anomaly, &source));
+                }
+                results.sort();
+                Ok(results)
+            })
+            .collect()
+    }
+
+    /// Query Cymru's IP-to-ASN mapping for a stream of IP addresses
+    ///
+    /// Addresses are taken from `ips` `buffer` at a time and looked up with
+    /// [`ip2asn_many`](#method.ip2asn_many), and results are yielded in input
+    /// order as each batch completes. At most `buffer` addresses and their
+    /// results are held at once, so arbitrarily long inputs are enriched in
+    /// constant memory. A `buffer` of zero is treated as one.
+    ///
+    /// ```
+    /// use cymrust::{fixtures, CymruClient};
+    ///
+    /// let client = CymruClient::with_provider(fixtures::mock_provider());
+    /// let ips = ["8.8.8.8", "1.1.1.1"].iter().map(|ip| ip.parse().unwrap());
+    /// for (ip, result) in client.ip2asn_stream(ips, 1000) {
+    ///     println!("{}: AS{}", ip, result.unwrap()[0].as_number);
+    /// }
+    /// ```
+    ///
+    /// [`IpReader`](input/struct.IpReader.html) remembers the addresses it
+    /// has read to drop duplicates; use its
+    /// [`keep_duplicates`](input/struct.IpReader.html#method.keep_duplicates)
+    /// to read input in constant memory too.
+    ///
+    pub fn ip2asn_stream<I>(&self, ips: I, buffer: usize) -> Ip2AsnStream<'_, I::IntoIter>
+    where
+        I: IntoIterator<Item = IpAddr>,
+    {
+        Ip2AsnStream {
+            client: self,
+            ips: ips.into_iter(),
+            buffer: cmp::max(buffer, 1),
+            pending: VecDeque::new(),
+        }
+    }
+
+    fn asn_stats(
+        &self,
+        asn: AsNumber,
+        stats: Option<&mut LookupStats>,
+    ) -> Result<Vec<CymruASN>, Error> {
+        let memo = match &self.asn_memo {
+            Some(memo) => memo,
+            None => return self.query_asn(asn, stats).map(|(results, _)| results),
+        };
+        if let Some(results) = memo.get(asn) {
+            if let Some(metrics) = &self.metrics {
+                metrics.cache_hit(ASN_MEMO_CACHE, &asn_query(asn));
+            }
+            if let Some(stats) = stats {
+                stats.cache_hits += 1;
+            }
+            return Ok(results);
+        }
+        if let Some(metrics) = &self.metrics {
+            metrics.cache_miss(ASN_MEMO_CACHE, &asn_query(asn));
+        }
+        let (results, ttl) = self.query_asn(asn, stats)?;
+        memo.insert(asn, &results, ttl);
+        Ok(results)
+    }
+
+    /// Query details of AS number, returning them with the TTL of the answer
+    ///
+    fn query_asn(
+        &self,
+        asn: AsNumber,
+        mut stats: Option<&mut LookupStats>,
+    ) -> Result<(Vec<CymruASN>, Duration), Error> {
+        let query = asn_query(asn);
+
+        let answer = self.resolve_txt(&query, Stage::Asn, stats.as_deref_mut())?;
+        let ttl = answer.ttl;
+        let now = SystemTime::now();
+        let cache_until = self.expires(now, answer.ttl);
+
+        let received = answer.records.len();
+        let mut results = parse_cymru_asn(&answer.records, cache_until, self.strictness)
+            .map_err(|malformed| malformed.into_error(&query, Stage::Asn))?;
+        ResponseLimits::check(
+            &query,
+            Stage::Asn,
+            "results",
+            results.len(),
+            self.response_limits.max_results,
+        )?;
+        if self.ascii_as_names {
+            for result in &mut results {
+                result.as_name = escape_as_name(&result.as_name);
+            }
+        }
+        count_discarded(stats, received - results.len());
+        if results.is_empty() {
+            debug!("No results found for {}", query);
+            return Err(Error::NoResultsFound {
+                query,
+                stage: Stage::Asn,
+            });
+        }
+        Ok((results, ttl))
+    }
+
+    /// Query only origin information of IP address, without AS details
+    ///
+    /// This is a single DNS query, so it's cheaper than
+    /// [`ip2asn`](#method.ip2asn) when AS names are not needed.
+    ///
+    pub fn origin(&self, ip: IpAddr) -> Result<Vec<CymruOrigin>, Error> {
+        self.audited(&ip, None, |stats| self.origin_stats(ip, stats))
+            .map(|(origins, _)| origins)
+    }
+
+    /// Origins of `ip`, with the name of the backend which answered
+    ///
+    fn origin_stats(
+        &self,
+        ip: IpAddr,
+        stats: Option<&mut LookupStats>,
+    ) -> Result<(Vec<CymruOrigin>, &'static str), Error> {
+        self.prefix_origins_stats(ip, stats)
+            .map(|(results, backend)| (flatten_origins(results), backend))
+    }
+
+    /// Query origin information of IP address, grouped by BGP prefix
+    ///
+    /// Like [`origin`](#method.origin), but prefixes originated by several
+    /// AS numbers are returned once, with all of them.
+    ///
+    pub fn prefix_origins(&self, ip: IpAddr) -> Result<Vec<CymruPrefixOrigins>, Error> {
+        self.audited(&ip, None, |stats| self.prefix_origins_stats(ip, stats))
+            .map(|(results, _)| results)
+    }
+
+    /// Prefix origins of `ip`, with the name of the backend which answered
+    ///
+    fn prefix_origins_stats(
+        &self,
+        ip: IpAddr,
+        mut stats: Option<&mut LookupStats>,
+    ) -> Result<(Vec<CymruPrefixOrigins>, &'static str), Error> {
+        if self.prefilter && self.provider.is_network() {
+            if let Some(purpose) = special_purpose(ip).filter(|p| !p.globally_reachable) {
+                debug!("Not querying {}: {} address", ip, purpose.name);
+                return Err(Error::NonRoutableInput { ip, purpose });
+            }
+        }
+
+        let query = self.origin_query(ip);
+
+        let answer = self.resolve_txt(&query, Stage::Origin, stats.as_deref_mut())?;
+        let now = SystemTime::now();
+        let cache_until = self.expires(now, answer.ttl);
+        let backend = answer.answered_by.unwrap_or_else(|| self.provider.name());
+
+        let received = answer.records.len();
+        let results = parse_cymru_prefix_origins(&answer.records, cache_until, self.strictness)
+            .map_err(|malformed| malformed.into_error(&query, Stage::Origin))?;
+        ResponseLimits::check(
+            &query,
+            Stage::Origin,
+            "results",
+            results.iter().map(|r| r.as_numbers.len()).sum(),
+            self.response_limits.max_results,
+        )?;
+        count_discarded(stats, received - results.len());
+        if results.is_empty() {
+            debug!("No results found for {}", query);
+            return Err(Error::NoResultsFound {
+                query,
+                stage: Stage::Origin,
+            });
+        }
+        Ok((results, backend))
+    }
+
+    /// Origin query for `ip`, or for its network if queries are truncated
+    ///
+    fn origin_query(&self, ip: IpAddr) -> String {
+        if self.truncate_queries {
+            origin_query(network(ip))
+        } else {
+            origin_query(ip)
+        }
+    }
+
+    /// Expiry time of results parsed `now` from answer with `ttl`
+    ///
+    /// Adjusted TTLs too long to represent fall back to the answer's own.
+    ///
+    fn expires(&self, now: SystemTime, ttl: Duration) -> SystemTime {
+        now.checked_add(self.result_ttl.apply(ttl))
+            .unwrap_or(now + ttl)
+    }
+
+    /// Resolve TXT record through the provider
+    ///
+    /// All instrumentation (tracing, metrics, stats and the raw-response
+    /// hook) happens here, so it applies equally to every provider.
+    ///
+    fn resolve_txt(
+        &self,
+        name: &str,
+        stage: Stage,
+        mut stats: Option<&mut LookupStats>,
+    ) -> Result<TxtAnswer, Error> {
+        let result = self.instrumented(name, stage, stats.as_deref_mut(), || {
+            self.provider.txt(name, stage)
+        });
+        if let (Ok(answer), Some(query)) = (&result, stats.and_then(|s| s.queries.last_mut())) {
+            query.records = answer.records.len() + answer.discarded;
+            query.discarded = answer.discarded;
+            query.canonical_name = answer.canonical_name.clone();
+            if let Some(backend) = answer.answered_by {
+                query.backend = backend;
+            }
+        }
+        let answer = result?;
+        let limits = &self.response_limits;
+        ResponseLimits::check(
+            name,
+            stage,
+            "records",
+            answer.records.len() + answer.discarded,
+            limits.max_records,
+        )?;
+        let longest = answer.records.iter().map(str::len).max().unwrap_or(0);
+        ResponseLimits::check(name, stage, "record length", longest, limits.max_record_len)?;
+        if let Some(hook) = &self.response_hook {
+            hook(&RawResponse {
+                query: name,
+                ttl: answer.ttl,
+                records: &answer.records,
+            });
+        }
+        Ok(answer)
+    }
+
+    /// Run `lookup` of `input`, writing a line about it to the audit log
+    ///
+    /// Queries are collected into `stats`, or into throwaway stats if not
+    /// given, so that the line can list them.
+    ///
+    fn audited<T, F>(
+        &self,
+        input: &dyn fmt::Display,
+        stats: Option<&mut LookupStats>,
+        lookup: F,
+    ) -> Result<T, Error>
+    where
+        F: FnOnce(Option<&mut LookupStats>) -> Result<T, Error>,
+    {
+        let audit_log = match &self.audit_log {
+            Some(audit_log) => audit_log,
+            None => return lookup(stats),
+        };
+
+        let mut own_stats = LookupStats::default();
+        let stats = stats.unwrap_or(&mut own_stats);
+        let first_query = stats.queries.len();
+        let timestamp = SystemTime::now();
+        let started = Instant::now();
+
+        let result = lookup(Some(&mut *stats));
+        audit_log.record(
+            timestamp,
+            input,
+            self.provider.name(),
+            &stats.queries[first_query..],
+            &result,
+            started.elapsed(),
+        );
+        result
+    }
+
+    /// Run provider `query` for DNS `name` with offline check, tracing,
+    /// metrics and stats, retrying transient failures as configured
+    ///
+    fn instrumented<T, F>(
+        &self,
+        name: &str,
+        stage: Stage,
+        mut stats: Option<&mut LookupStats>,
+        query: F,
+    ) -> Result<T, Error>
+    where
+        F: Fn() -> Result<T, Error>,
+    {
+        if self.offline && self.provider.is_network() {
+            return Err(Error::Offline {
+                query: name.to_string(),
+                stage,
+            });
+        }
+
+        let mut retries = 0;
+        loop {
+            let result = self.attempt(name, stage, stats.as_deref_mut(), &query);
+            match &result {
+                Err(err) if retries < self.retries && transient(err) => {
+                    retries += 1;
+                    debug!("Retrying {} ({}/{})", name, retries, self.retries);
+                    if let Some(metrics) = &self.metrics {
+                        metrics.retry(self.provider.name(), name, retries);
+                    }
+                }
+                _ => return result,
+            }
+        }
+    }
+
+    /// Run provider `query` for DNS `name` once, with tracing, metrics and
+    /// stats
+    ///
+    fn attempt<T, F>(
+        &self,
+        name: &str,
+        stage: Stage,
+        stats: Option<&mut LookupStats>,
+        query: F,
+    ) -> Result<T, Error>
+    where
+        F: Fn() -> Result<T, Error>,
+    {
+        let backend = self.provider.name();
+        let span = trace::QuerySpan::enter(name, stage.record_type(), backend);
+        if let Some(metrics) = &self.metrics {
+            metrics.query_start(backend, name);
+        }
+        let started = Instant::now();
+
+        let result = query();
+        let elapsed = started.elapsed();
+
+        span.finish(&result);
+        if let Some(stats) = stats {
+            stats.queries.push(QueryStats {
+                query: name.to_string(),
+                backend,
+                duration: elapsed,
+                success: result.is_ok(),
+                records: 0,
+                discarded: 0,
+                canonical_name: None,
+            });
+        }
+        if let Some(metrics) = &self.metrics {
+            metrics.query_finish(backend, name, elapsed);
+            if let Err(err) = &result {
+                metrics.error(backend, name, err);
+            }
+        }
+        if let Err(Error::RateLimited { backoff, .. }) = &result {
+            let mut backoff_until = match self.backoff_until.lock() {
+                Ok(backoff_until) => backoff_until,
+                Err(poisoned) => poisoned.into_inner(),
+            };
+            *backoff_until = Some(Instant::now() + *backoff);
+        }
+        if let Err(err) = &result {
+            debug!(
+                "{} lookup for {} failed: {}",
+                stage.record_type(),
+                name,
+                err
+            );
+        }
+        result
+    }
+}
+
+/// Iterator over IP-to-ASN results of a stream of IP addresses
+///
+/// Created with [`CymruClient::ip2asn_stream`](struct.CymruClient.html#method.ip2asn_stream).
+///
+pub struct Ip2AsnStream<'a, I> {
+    client: &'a CymruClient,
+    ips: I,
+    buffer: usize,
+    pending: VecDeque<(IpAddr, Result<Vec<CymruIP2ASN>, Error>)>,
+}
+
+impl<I: Iterator<Item = IpAddr>> Iterator for Ip2AsnStream<'_, I> {
+    type Item = (IpAddr, Result<Vec<CymruIP2ASN>, Error>);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.pending.is_empty() {
+            let ips: Vec<IpAddr> = self.ips.by_ref().take(self.buffer).collect();
+            let results = self.client.ip2asn_many(&ips);
+            self.pending.extend(ips.into_iter().zip(results));
+        }
+        self.pending.pop_front()
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        let (lower, upper) = self.ips.size_hint();
+        let pending = self.pending.len();
+        (
+            lower.saturating_add(pending),
+            upper.and_then(|upper| upper.checked_add(pending)),
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::Cell;
+    use std::cmp;
+    use std::net::{IpAddr, SocketAddr};
+    use std::sync::{Arc, Condvar, Mutex};
+    use std::time::{Duration, SystemTime};
+
+    use trust_dns_resolver::config::{NameServerConfigGroup, ResolverConfig, ResolverOpts};
+    use trust_dns_resolver::Resolver;
+
+    use super::{CymruClient, ErrorBudget, Metrics, ResponseLimits, ResultTtl, BULK_WORKERS};
+    use crate::provider::{MockProvider, Provider, TxtAnswer};
+    use crate::{AsNumber, CymruIP2ASN, DedupPolicy, Error, Stage, Strictness};
+
+    /// Resolver pointing at a local port nobody listens on, so that every
+    /// query fails fast without touching the network
+    fn unreachable_resolver() -> Resolver {
+        let addr: SocketAddr = "127.0.0.1:9".parse().unwrap();
+        let servers = NameServerConfigGroup::from_ips_clear(&[addr.ip()], addr.port(), true);
+        let config = ResolverConfig::from_parts(None, vec![], servers);
+        let opts = ResolverOpts {
+            timeout: Duration::from_millis(100),
+            attempts: 1,
+            ..ResolverOpts::default()
+        };
+        Resolver::new(config, opts).unwrap()
+    }
+
+    /// Queries in flight at once, and the most of them so far
+    #[derive(Default)]
+    struct InFlight {
+        counts: Mutex<(usize, usize)>,
+        changed: Condvar,
+    }
+
+    impl InFlight {
+        fn peak(&self) -> usize {
+            self.counts.lock().unwrap().1
+        }
+    }
+
+    /// Provider holding each query until another one is in flight too, so
+    /// concurrent callers are seen overlapping however they're scheduled
+    ///
+    /// Queries go on after a timeout, so sequential callers are merely slow.
+    ///
+    struct Overlapping {
+        inner: MockProvider,
+        in_flight: Arc<InFlight>,
+    }
+
+    impl Provider for Overlapping {
+        fn name(&self) -> &'static str {
+            self.inner.name()
+        }
+
+        fn is_network(&self) -> bool {
+            self.inner.is_network()
+        }
+
+        fn txt(&self, query: &str, stage: Stage) -> Result<TxtAnswer, Error> {
+            let mut counts = self.in_flight.counts.lock().unwrap();
+            counts.0 += 1;
+            counts.1 = cmp::max(counts.1, counts.0);
+            self.in_flight.changed.notify_all();
+            let timeout = Duration::from_secs(5);
+            let (mut counts, _) = self
+                .in_flight
+                .changed
+                .wait_timeout_while(counts, timeout, |(_, peak)| *peak < 2)
+                .unwrap();
+            counts.0 -= 1;
+            drop(counts);
+            self.inner.txt(query, stage)
+        }
+    }
+
+    #[derive(Clone, Default)]
+    struct Recorder(Arc<Mutex<Vec<String>>>);
+
+    impl Metrics for Recorder {
+        fn query_start(&self, backend: &str, query: &str) {
+            self.0
+                .lock()
+                .unwrap()
+                .push(format!("start {} {}", backend, query));
+        }
+
+        fn query_finish(&self, backend: &str, query: &str, _elapsed: Duration) {
+            self.0
+                .lock()
+                .unwrap()
+                .push(format!("finish {} {}", backend, query));
+        }
+
+        fn error(&self, backend: &str, query: &str, _error: &Error) {
+            self.0
+                .lock()
+                .unwrap()
+                .push(format!("error {} {}", backend, query));
+        }
+
+        fn retry(&self, backend: &str, query: &str, retry: usize) {
+            self.0
+                .lock()
+                .unwrap()
+                .push(format!("retry {} {} {}", backend, query, retry));
+        }
+
+        fn cache_hit(&self, cache: &str, query: &str) {
+            self.0
+                .lock()
+                .unwrap()
+                .push(format!("hit {} {}", cache, query));
+        }
+
+        fn cache_miss(&self, cache: &str, query: &str) {
+            self.0
+                .lock()
+                .unwrap()
+                .push(format!("miss {} {}", cache, query));
+        }
+    }
+
+    #[test]
+    fn test_metrics_hooks() {
+        let recorder = Recorder::default();
+        let client =
+            CymruClient::with_resolver(unreachable_resolver()).with_metrics(recorder.clone());
+        assert!(client.asn(23028u32).is_err());
+
+        let events = recorder.0.lock().unwrap();
+        assert_eq!(
+            *events,
+            vec![
+                "miss asn-memo AS23028.asn.cymru.com.",
+                "start cymru-dns AS23028.asn.cymru.com.",
+                "finish cymru-dns AS23028.asn.cymru.com.",
+                "error cymru-dns AS23028.asn.cymru.com.",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_asn_memo_metrics() {
+        let recorder = Recorder::default();
+        let client = CymruClient::with_provider(crate::fixtures::mock_provider())
+            .with_metrics(recorder.clone());
+        let ip = crate::fixtures::GOOGLE_IP.parse().unwrap();
+        client.ip2asn(ip).unwrap();
+        client.ip2asn(ip).unwrap();
+
+        let events = recorder.0.lock().unwrap();
+        let memo: Vec<&String> = events
+            .iter()
+            .filter(|event| event.contains("asn-memo"))
+            .collect();
+        assert_eq!(
+            memo,
+            vec![
+                "miss asn-memo AS15169.asn.cymru.com.",
+                "hit asn-memo AS15169.asn.cymru.com.",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_retries() {
+        let query = "AS64500.asn.cymru.com.";
+        let recorder = Recorder::default();
+        let mock = MockProvider::new().fail(query, "SERVFAIL");
+        let client = CymruClient::with_provider(mock)
+            .with_asn_memo(false)
+            .with_metrics(recorder.clone())
+            .with_retries(2);
+        assert!(client.asn(64500u32).is_err());
+
+        let events = recorder.0.lock().unwrap();
+        let attempt = |retry: Option<usize>| {
+            let mut events = Vec::new();
+            if let Some(retry) = retry {
+                events.push(format!("retry mock {} {}", query, retry));
+            }
+            events.push(format!("start mock {}", query));
+            events.push(format!("finish mock {}", query));
+            events.push(format!("error mock {}", query));
+            events
+        };
+        let expected: Vec<String> = [None, Some(1), Some(2)]
+            .iter()
+            .flat_map(|retry| attempt(*retry))
+            .collect();
+        assert_eq!(*events, expected);
+        drop(events);
+
+        // Answers that there's nothing to find aren't retried
+        recorder.0.lock().unwrap().clear();
+        assert!(client.asn(64501u32).is_err());
+        assert_eq!(recorder.0.lock().unwrap().len(), 3);
+    }
+
+    #[test]
+    fn test_error_context() {
+        let client = CymruClient::with_resolver(unreachable_resolver());
+
+        let err = client.asn(23028u32).unwrap_err();
+        assert_eq!(err.query(), Some("AS23028.asn.cymru.com."));
+        assert_eq!(err.stage(), Some(Stage::Asn));
+
+        let err = client.ip2asn("8.8.8.8".parse().unwrap()).unwrap_err();
+        assert_eq!(err.query(), Some("8.8.8.8.origin.asn.cymru.com."));
+        assert_eq!(err.stage(), Some(Stage::Origin));
+        assert!(err.to_string().contains("8.8.8.8.origin.asn.cymru.com."));
+    }
+
+    #[test]
+    fn test_stats_and_response_hook() {
+        let mock = MockProvider::new()
+            .answer(
+                "1.2.0.192.origin.asn.cymru.com.",
+                Duration::from_secs(60),
+                &["64500 64501 | 192.0.2.0/24 | US | arin | 2010-01-01"],
+            )
+            .answer(
+                "AS64500.asn.cymru.com.",
+                Duration::from_secs(60),
+                &["64500 | US | arin | 2010-01-01 | EXAMPLE-A"],
+            )
+            .answer(
+                "AS64501.asn.cymru.com.",
+                Duration::from_secs(60),
+                &["64501 | US | arin | 2010-01-01 | EXAMPLE-B", "garbage"],
+            );
+        let seen = Arc::new(Mutex::new(Vec::new()));
+        let hook_seen = seen.clone();
+        let client = CymruClient::with_provider(mock).with_response_hook(move |raw| {
+            hook_seen.lock().unwrap().push(raw.query.to_string());
+        });
+
+        let (results, stats) = client
+            .ip2asn_with_stats("192.0.2.1".parse().unwrap())
+            .unwrap();
+        assert_eq!(results.len(), 2);
+        assert_eq!(results[1].as_name, "EXAMPLE-B");
+        assert_eq!(stats.round_trips(), 3);
+        assert!(stats
+            .queries
+            .iter()
+            .all(|q| q.success && q.backend == "mock"));
+        let records: Vec<(usize, usize)> = stats
+            .queries
+            .iter()
+            .map(|q| (q.records, q.discarded))
+            .collect();
+        assert_eq!(records, vec![(1, 0), (1, 0), (2, 1)]);
+        assert_eq!(stats.discarded(), 1);
+        let backends = stats.by_backend();
+        assert_eq!(backends.len(), 1);
+        assert_eq!(backends["mock"].queries, 3);
+        assert_eq!(backends["mock"].errors, 0);
+        assert_eq!(backends["mock"].duration, stats.total_duration());
+        assert_eq!(
+            *seen.lock().unwrap(),
+            vec![
+                "1.2.0.192.origin.asn.cymru.com.",
+                "AS64500.asn.cymru.com.",
+                "AS64501.asn.cymru.com.",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_strictness() {
+        let mock = MockProvider::new().answer(
+            "AS64501.asn.cymru.com.",
+            Duration::from_secs(60),
+            &["64501 | US | arin | 2010-01-01 | EXAMPLE-B", "garbage"],
+        );
+        let client = CymruClient::with_provider(mock.clone());
+        assert_eq!(client.asn(64501u32).unwrap().len(), 1);
+
+        let client = CymruClient::with_provider(mock.clone()).with_strictness(Strictness::Strict);
+        match client.asn(64501u32) {
+            Err(Error::Parse {
+                query,
+                stage,
+                record,
+                ..
+            }) => {
+                assert_eq!(query, "AS64501.asn.cymru.com.");
+                assert_eq!(stage, Stage::Asn);
+                assert_eq!(record, "garbage");
+            }
+            other => panic!("unexpected {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_dedup() {
+        let mock = MockProvider::new()
+            .answer(
+                "1.2.0.192.origin.asn.cymru.com.",
+                Duration::from_secs(60),
+                &[
+                    "64500 64501 | 192.0.2.0/24 | US | arin | 2010-01-01",
+                    "64500 | 192.0.0.0/16 | US | arin | 2010-01-01",
+                ],
+            )
+            .answer(
+                "AS64500.asn.cymru.com.",
+                Duration::from_secs(60),
+                &["64500 | US | arin | 2010-01-01 | EXAMPLE-A"],
+            )
+            .answer(
+                "AS64501.asn.cymru.com.",
+                Duration::from_secs(60),
+                &["64501 | US | arin | 2010-01-01 | EXAMPLE-B"],
+            );
+        let ip: IpAddr = "192.0.2.1".parse().unwrap();
+        let origins = |dedup| {
+            let client = CymruClient::with_provider(mock.clone())
+                .with_dedup(dedup)
+                .with_asn_memo(false);
+            let pairs = |results: &[CymruIP2ASN]| -> Vec<(String, AsNumber)> {
+                results
+                    .iter()
+                    .map(|r| (r.bgp_prefix.clone(), r.as_number))
+                    .collect()
+            };
+            let (results, stats) = client.ip2asn_with_stats(ip).unwrap();
+            let many = client.ip2asn_many(&[ip]).remove(0).unwrap();
+            assert_eq!(pairs(&many), pairs(&results));
+            (pairs(&results), stats.round_trips())
+        };
+        let pair = |prefix: &str, asn| (prefix.to_string(), asn);
+
+        assert_eq!(
+            origins(DedupPolicy::ByAsn),
+            (
+                vec![pair("192.0.2.0/24", 64500), pair("192.0.2.0/24", 64501)],
+                3
+            )
+        );
+        assert_eq!(
+            origins(DedupPolicy::ByPrefix),
+            (
+                vec![pair("192.0.2.0/24", 64500), pair("192.0.0.0/16", 64500)],
+                2
+            )
+        );
+        assert_eq!(
+            origins(DedupPolicy::None),
+            (
+                vec![
+                    pair("192.0.2.0/24", 64500),
+                    pair("192.0.2.0/24", 64501),
+                    pair("192.0.0.0/16", 64500),
+                ],
+                3
+            )
+        );
+    }
+
+    #[test]
+    fn test_query_truncation_and_asn_details() {
+        let mock = MockProvider::new()
+            .answer(
+                "0.2.0.192.origin.asn.cymru.com.",
+                Duration::from_secs(60),
+                &["64500 | 192.0.2.0/24 | US | arin | 2010-01-01"],
+            )
+            .answer(
+                "0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.8.b.d.0.1.0.0.2.origin6.asn.cymru.com.",
+                Duration::from_secs(60),
+                &["64500 | 2001:db8::/32 | US | arin | 2010-01-01"],
+            );
+        let client = CymruClient::with_provider(mock)
+            .with_prefilter(false)
+            .with_query_truncation(true)
+            .with_asn_details(false);
+
+        for ip in ["192.0.2.1", "2001:db8:0:1234::1"] {
+            let ip: IpAddr = ip.parse().unwrap();
+            let (results, stats) = client.ip2asn_with_stats(ip).unwrap();
+            assert_eq!(stats.round_trips(), 1);
+            assert_eq!(results[0].ip_addr, ip);
+            assert_eq!(results[0].as_number, 64500);
+            assert_eq!(results[0].as_name, "");
+            assert_eq!(
+                client.ip2asn_many(&[ip])[0].as_ref().unwrap()[0].as_name,
+                ""
+            );
+        }
+    }
+
+    #[test]
+    fn test_ascii_as_names() {
+        let mock = MockProvider::new().answer(
+            "AS64502.asn.cymru.com.",
+            Duration::from_secs(60),
+            &["64502 | FI | ripencc | 2010-01-01 | EXAMPLE - Esimerkki \u{e4}\u{202e}, FI"],
+        );
+        let client = CymruClient::with_provider(mock).with_ascii_as_names(true);
+        assert_eq!(
+            client.asn(64502u32).unwrap()[0].as_name,
+            "EXAMPLE - Esimerkki \\u{e4}\\u{202e}, FI"
+        );
+    }
+
+    #[test]
+    fn test_asn_memo() {
+        let client = CymruClient::with_provider(crate::fixtures::mock_provider());
+        let (first, stats) = client.asn_with_stats(15169u32).unwrap();
+        assert_eq!((stats.round_trips(), stats.cache_hits), (1, 0));
+        let (second, stats) = client.asn_with_stats(15169u32).unwrap();
+        assert_eq!((stats.round_trips(), stats.cache_hits), (0, 1));
+        assert_eq!(first, second);
+
+        let ip = crate::fixtures::GOOGLE_IP.parse().unwrap();
+        let (_, stats) = client.ip2asn_with_stats(ip).unwrap();
+        assert_eq!((stats.round_trips(), stats.cache_hits), (1, 1));
+
+        let client =
+            CymruClient::with_provider(crate::fixtures::mock_provider()).with_asn_memo(false);
+        client.asn(15169u32).unwrap();
+        let (_, stats) = client.asn_with_stats(15169u32).unwrap();
+        assert_eq!((stats.round_trips(), stats.cache_hits), (1, 0));
+    }
+
+    #[test]
+    fn test_response_limits() {
+        let mock = MockProvider::new().answer(
+            "AS64500.asn.cymru.com.",
+            Duration::from_secs(60),
+            &[
+                "64500 | US | arin | 2010-01-01 | EXAMPLE-A",
+                "64500 | US | arin | 2010-01-01 | EXAMPLE-B",
+            ],
+        );
+        let client = CymruClient::with_provider(mock).with_response_limits(ResponseLimits {
+            max_records: 1,
+            ..ResponseLimits::default()
+        });
+        match client.asn(64500u32) {
+            Err(Error::ResponseTooLarge {
+                limit, size, max, ..
+            }) => assert_eq!((limit, size, max), ("records", 2, 1)),
+            other => panic!("unexpected {:?}", other),
+        }
+
+        let client = CymruClient::with_provider(crate::fixtures::mock_provider())
+            .with_response_limits(ResponseLimits {
+                max_record_len: 16,
+                ..ResponseLimits::default()
+            });
+        assert!(matches!(
+            client.asn(15169u32),
+            Err(Error::ResponseTooLarge {
+                limit: "record length",
+                ..
+            })
+        ));
+
+        let client = CymruClient::with_provider(crate::fixtures::mock_provider())
+            .with_response_limits(ResponseLimits {
+                max_results: 1,
+                ..ResponseLimits::default()
+            });
+        let err = client
+            .ip2asn(crate::fixtures::MULTI_ORIGIN_IP.parse().unwrap())
+            .unwrap_err();
+        assert_eq!(err.stage(), Some(Stage::Origin));
+        assert!(matches!(
+            err,
+            Error::ResponseTooLarge {
+                limit: "results",
+                size: 2,
+                ..
+            }
+        ));
+        assert!(client
+            .ip2asn(crate::fixtures::GOOGLE_IP.parse().unwrap())
+            .is_ok());
+    }
+
+    #[test]
+    fn test_result_ttl() {
+        let ttl = ResultTtl {
+            scale: 2.0,
+            min: Duration::from_secs(3600),
+            max: Duration::from_secs(86400),
+        };
+        assert_eq!(
+            ttl.apply(Duration::from_secs(60)),
+            Duration::from_secs(3600)
+        );
+        assert_eq!(
+            ttl.apply(Duration::from_secs(7200)),
+            Duration::from_secs(14400)
+        );
+        assert_eq!(
+            ttl.apply(Duration::from_secs(86400)),
+            Duration::from_secs(86400)
+        );
+        let unchanged = ResultTtl::default();
+        assert_eq!(
+            unchanged.apply(Duration::from_secs(60)),
+            Duration::from_secs(60)
+        );
+
+        let client =
+            CymruClient::with_provider(crate::fixtures::mock_provider()).with_result_ttl(ttl);
+        let before = SystemTime::now();
+        let expires = client.asn(15169u32).unwrap()[0].expires;
+        assert!(expires >= before + Duration::from_secs(7200));
+        assert!(expires <= SystemTime::now() + Duration::from_secs(7200));
+    }
+
+    #[test]
+    fn test_backoff_remaining() {
+        let refusing = crate::fault::FaultInjector::new(MockProvider::new()).refused(1.0);
+        let client = CymruClient::with_provider(refusing);
+        assert_eq!(client.backoff_remaining(), None);
+
+        assert!(matches!(
+            client.asn(64500u32),
+            Err(Error::RateLimited { .. })
+        ));
+        let remaining = client.backoff_remaining().unwrap();
+        assert!(remaining > Duration::from_secs(0));
+        assert!(remaining <= crate::RATE_LIMIT_BACKOFF);
+    }
+
+    #[test]
+    fn test_offline() {
+        let client = CymruClient::with_resolver(unreachable_resolver()).with_offline(true);
+        match client.asn(23028u32) {
+            Err(Error::Offline { query, stage }) => {
+                assert_eq!(query, "AS23028.asn.cymru.com.");
+                assert_eq!(stage, Stage::Asn);
+            }
+            other => panic!("unexpected {:?}", other.map(|_| ())),
+        }
+
+        let mock = MockProvider::new().answer(
+            "AS64500.asn.cymru.com.",
+            Duration::from_secs(60),
+            &["64500 | US | arin | 2010-01-01 | EXAMPLE-A"],
+        );
+        let client = CymruClient::with_provider(mock).with_offline(true);
+        assert_eq!(client.asn(64500u32).unwrap()[0].as_name, "EXAMPLE-A");
+    }
+
+    #[test]
+    fn test_prefilter() {
+        let client = CymruClient::with_resolver(unreachable_resolver());
+        match client.ip2asn("10.1.2.3".parse().unwrap()) {
+            Err(Error::NonRoutableInput { ip, purpose }) => {
+                assert_eq!(ip, "10.1.2.3".parse::<std::net::IpAddr>().unwrap());
+                assert_eq!(purpose.rfc, "RFC 1918");
+            }
+            other => panic!("unexpected {:?}", other.map(|_| ())),
+        }
+
+        let client = client.with_prefilter(false);
+        assert!(matches!(
+            client.ip2asn("10.1.2.3".parse().unwrap()),
+            Err(Error::Resolver { .. })
+        ));
+    }
+
+    #[test]
+    fn test_asn_bulk() {
+        let in_flight = Arc::new(InFlight::default());
+        let provider = Overlapping {
+            inner: crate::fixtures::mock_provider(),
+            in_flight: Arc::clone(&in_flight),
+        };
+        let client = CymruClient::with_provider(provider);
+
+        let results = client.asn_bulk(vec![15169, 13335, 15169, 23028, 64511]);
+        assert!(in_flight.peak() > 1);
+
+        let asns: Vec<AsNumber> = results.keys().copied().collect();
+        assert_eq!(asns, vec![13335, 15169, 23028, 64511]);
+        assert_eq!(results[&15169].as_ref().unwrap()[0].as_name, "GOOGLE, US");
+        assert!(matches!(results[&64511], Err(Error::NoResultsFound { .. })));
+        assert!(client.asn_bulk(Vec::<AsNumber>::new()).is_empty());
+    }
+
+    #[test]
+    fn test_ip2asn_many() {
+        let mock = crate::fixtures::mock_provider().fail("AS13335.asn.cymru.com.", "SERVFAIL");
+        let client = CymruClient::with_provider(mock);
+        let ips: Vec<IpAddr> = ["8.8.8.8", "1.1.1.1", "8.8.8.8", "198.51.100.7", "9.9.9.9"]
+            .iter()
+            .map(|ip| ip.parse().unwrap())
+            .collect();
+
+        let results = client.ip2asn_many(&ips);
+        assert_eq!(results.len(), 5);
+        assert_eq!(results[0].as_ref().unwrap()[0].as_name, "GOOGLE, US");
+        assert_eq!(results[0].as_ref().unwrap(), results[2].as_ref().unwrap());
+        assert!(matches!(
+            results[1],
+            Err(Error::Resolver {
+                stage: Stage::Asn,
+                ..
+            })
+        ));
+        assert_eq!(results[3].as_ref().unwrap().len(), 2);
+        assert!(matches!(
+            results[4],
+            Err(Error::NoResultsFound {
+                stage: Stage::Origin,
+                ..
+            })
+        ));
+    }
+
+    #[test]
+    fn test_ip2asn_many_network_sharing() {
+        let ttl = Duration::from_secs(60);
+        let mock = MockProvider::new()
+            .answer(
+                "1.2.0.192.origin.asn.cymru.com.",
+                ttl,
+                &["64500 | 192.0.2.0/24 | US | arin | 2010-01-01"],
+            )
+            .answer(
+                "200.2.0.192.origin.asn.cymru.com.",
+                ttl,
+                &[
+                    "64500 | 192.0.2.0/24 | US | arin | 2010-01-01",
+                    "64504 | 192.0.2.128/25 | US | arin | 2010-01-01",
+                ],
+            )
+            .answer(
+                "1.100.51.198.origin.asn.cymru.com.",
+                ttl,
+                &["64501 | 198.51.100.0/25 | US | arin | 2010-01-01"],
+            )
+            .answer(
+                "200.100.51.198.origin.asn.cymru.com.",
+                ttl,
+                &["64502 | 198.51.100.128/25 | US | arin | 2010-01-01"],
+            )
+            .answer(
+                "2.113.0.203.origin.asn.cymru.com.",
+                ttl,
+                &["64503 | 203.0.113.0/24 | US | arin | 2010-01-01"],
+            );
+        let mock = [64500, 64501, 64502, 64503, 64504]
+            .iter()
+            .fold(mock, |mock, asn| {
+                let record = format!("{} | US | arin | 2010-01-01 | EXAMPLE", asn);
+                mock.answer(&format!("AS{}.asn.cymru.com.", asn), ttl, &[&record])
+            });
+        let ips: Vec<IpAddr> = [
+            "192.0.2.99",
+            "192.0.2.1",
+            "192.0.2.200",
+            "198.51.100.1",
+            "198.51.100.200",
+            "203.0.113.1",
+            "203.0.113.2",
+        ]
+        .iter()
+        .map(|ip| ip.parse().unwrap())
+        .collect();
+        let origins = |client: &CymruClient| -> Vec<Option<Vec<AsNumber>>> {
+            client
+                .ip2asn_many(&ips)
+                .iter()
+                .zip(&ips)
+                .map(|(r, ip)| {
+                    let results = r.as_ref().ok()?;
+                    assert!(results.iter().all(|result| result.ip_addr == *ip));
+                    let mut asns: Vec<AsNumber> = results.iter().map(|r| r.as_number).collect();
+                    asns.sort_unstable();
+                    Some(asns)
+                })
+                .collect()
+        };
+
+        // Every address gets its own answer by default, including the one
+        // inside the /25 announced within 192.0.2.0/24
+        let client = CymruClient::with_provider(mock);
+        assert_eq!(
+            origins(&client),
+            vec![
+                None,
+                Some(vec![64500]),
+                Some(vec![64500, 64504]),
+                Some(vec![64501]),
+                Some(vec![64502]),
+                None,
+                Some(vec![64503]),
+            ]
+        );
+
+        let client = client.with_network_sharing(true);
+        assert_eq!(
+            origins(&client),
+            vec![
+                // Shares the answer of 192.0.2.1, not having one of its own
+                Some(vec![64500]),
+                Some(vec![64500]),
+                // Misses the /25 not covering 192.0.2.1, as documented
+                Some(vec![64500]),
+                // The /25s don't cover the /24, so both are queried
+                Some(vec![64501]),
+                Some(vec![64502]),
+                // Failures aren't shared either
+                None,
+                Some(vec![64503]),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_ip2asn_stream() {
+        let client = CymruClient::with_provider(crate::fixtures::mock_provider());
+        let ips: Vec<IpAddr> = ["8.8.8.8", "1.1.1.1", "192.0.2.1", "8.8.8.8", "1.1.1.1"]
+            .iter()
+            .map(|ip| ip.parse().unwrap())
+            .collect();
+        let pulled = Cell::new(0);
+        let input = ips.iter().inspect(|_| pulled.set(pulled.get() + 1));
+
+        let mut stream = client.ip2asn_stream(input.copied(), 2);
+        let (ip, result) = stream.next().unwrap();
+        assert_eq!(ip, ips[0]);
+        assert_eq!(result.unwrap()[0].as_number, 15169);
+        assert_eq!(pulled.get(), 2);
+
+        let rest: Vec<(IpAddr, bool)> = stream.map(|(ip, r)| (ip, r.is_ok())).collect();
+        assert_eq!(
+            rest,
+            vec![
+                (ips[1], true),
+                (ips[2], false),
+                (ips[3], true),
+                (ips[4], true),
+            ]
+        );
+        assert_eq!(client.ip2asn_stream(Vec::new(), 0).count(), 0);
+    }
+
+    #[test]
+    fn test_race_provider() {
+        use crate::cassette::ReplayProvider;
+        use crate::fixtures::{GOOGLE_ASN_TXT, GOOGLE_ORIGIN_TXT};
+        use crate::provider::RaceProvider;
+        use crate::Source;
+
+        let origin = "8.8.8.8.origin.asn.cymru.com.";
+        let cassette = format!(
+            "{}\t60\t{}\nAS15169.asn.cymru.com.\t60\t{}\n",
+            origin, GOOGLE_ORIGIN_TXT, GOOGLE_ASN_TXT
+        );
+        let replay = ReplayProvider::from_reader(cassette.as_bytes()).unwrap();
+        let (release, held) = std::sync::mpsc::channel();
+        let mock = crate::fixtures::mock_provider().hold(origin, held);
+        let client = CymruClient::with_provider(RaceProvider::new(mock).against(replay))
+            .with_asn_memo(false);
+
+        let (results, stats) = client
+            .ip2asn_with_stats("8.8.8.8".parse().unwrap())
+            .unwrap();
+        assert_eq!(results[0].as_name, "GOOGLE, US");
+        assert_eq!(results[0].source, Source::Replay);
+        assert_eq!(stats.queries[0].backend, "replay");
+        drop(release);
+
+        // Only the mock knows Cloudflare, so it wins by default
+        let results = client.ip2asn("1.1.1.1".parse().unwrap()).unwrap();
+        assert_eq!(results[0].source, Source::Mock);
+        let results = &client.ip2asn_many(&["1.1.1.1".parse().unwrap()])[0];
+        assert_eq!(results.as_ref().unwrap()[0].source, Source::Mock);
+    }
+
+    #[test]
+    fn test_window() {
+        let window = super::Window::new();
+        let limit = || window.state.lock().unwrap().limit;
+        let started: Vec<usize> = (0..BULK_WORKERS).map(|_| window.acquire()).collect();
+
+        // One burst of failures halves the limit only once
+        window.release(started[0], true);
+        window.release(started[1], true);
+        assert_eq!(limit(), BULK_WORKERS / 2);
+        for &generation in &started[2..] {
+            window.release(generation, false);
+        }
+        assert_eq!(limit(), BULK_WORKERS / 2 + 1);
+
+        let generation = window.acquire();
+        window.release(generation, true);
+        assert_eq!(limit(), 2);
+
+        // Each run of as many successes as the limit raises it by one
+        for _ in 0..(2..BULK_WORKERS).sum::<usize>() {
+            let generation = window.acquire();
+            window.release(generation, false);
+        }
+        assert_eq!(limit(), BULK_WORKERS);
+        assert_eq!(window.state.lock().unwrap().in_flight, 0);
+    }
+
+    #[test]
+    fn test_error_budget() {
+        let mut mock = crate::fixtures::mock_provider();
+        let asns: Vec<AsNumber> = (64500..64540).collect();
+        for asn in &asns {
+            mock = mock.fail(&format!("AS{}.asn.cymru.com.", asn), "SERVFAIL");
+        }
+        let client = CymruClient::with_provider(mock);
+
+        let results = client.asn_bulk(asns.clone());
+        assert!(results
+            .values()
+            .all(|result| matches!(result, Err(Error::Resolver { .. }))));
+
+        let client = client.with_error_budget(ErrorBudget {
+            max_errors: 5,
+            max_error_ratio: 1.0,
+            min_lookups: 0,
+        });
+        let results = client.asn_bulk(asns.clone());
+        let skipped = results
+            .values()
+            .filter(|result| matches!(result, Err(Error::BudgetExhausted { .. })))
+            .count();
+        assert!(
+            skipped >= asns.len() - 6 - super::BULK_WORKERS,
+            "{}",
+            skipped
+        );
+
+        let client = client.with_error_budget(ErrorBudget {
+            max_errors: usize::MAX,
+            max_error_ratio: 0.5,
+            min_lookups: 4,
+        });
+        let mut ips: Vec<IpAddr> = vec!["8.8.8.8".parse().unwrap()];
+        ips.extend((1..=20).map(|i| IpAddr::from([192, 0, 2, i])));
+        let results = client.ip2asn_many(&ips);
+        assert!(results[0].is_ok());
+        assert!(results
+            .iter()
+            .all(|result| !matches!(result, Err(Error::BudgetExhausted { .. }))));
+    }
+
+    #[test]
+    fn test_ip_info_reverse_dns() {
+        let mock =
+            crate::fixtures::mock_provider().hostname("8.8.8.8.in-addr.arpa.", "dns.google.");
+        let client = CymruClient::with_provider(mock);
+
+        let info = client.ip_info("8.8.8.8".parse().unwrap()).unwrap();
+        assert_eq!(info.results[0].as_number, 15169);
+        assert_eq!(info.hostname, None);
+
+        let client = client.with_reverse_dns(true);
+        let info = client.ip_info("8.8.8.8".parse().unwrap()).unwrap();
+        assert_eq!(info.hostname.as_deref(), Some("dns.google"));
+        let info = client.ip_info("1.1.1.1".parse().unwrap()).unwrap();
+        assert_eq!(info.hostname, None);
+    }
+}
@@ -0,0 +1,290 @@
+//! Compare two sets of IP-to-ASN results
+//!
+//! Looking up the same addresses periodically and diffing the results is
+//! a simple way to notice renumbering, prefix transfers or, potentially,
+//! hijacks. [`diff`](fn.diff.html) keys both result sets by IP address and
+//! reports every address whose origin AS numbers or BGP prefixes changed.
+//!
+//! To compare single results the same way, ignoring AS names and expiry
+//! times, see [`same_origin`](fn.same_origin.html) and
+//! [`covers`](fn.covers.html).
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt;
+use std::net::IpAddr;
+
+use crate::special::contains;
+use crate::{AsNumber, AsnInfo, CymruIP2ASN};
+
+/// Origin AS number and BGP prefix of an address
+///
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Route {
+    /// BGP Origin's Autonomous System (AS) number
+    pub as_number: AsNumber,
+    /// BGP prefix
+    pub bgp_prefix: String,
+}
+
+/// Difference in the results of one IP address
+///
+/// Routes are sorted by AS number, then prefix.
+///
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Change {
+    /// Address only appears in the new results
+    Added {
+        /// IP address
+        ip: IpAddr,
+        /// Routes in the new results
+        new: Vec<Route>,
+    },
+    /// Address only appears in the old results
+    Removed {
+        /// IP address
+        ip: IpAddr,
+        /// Routes in the old results
+        old: Vec<Route>,
+    },
+    /// Set of origin AS numbers changed
+    Origin {
+        /// IP address
+        ip: IpAddr,
+        /// Routes in the old results
+        old: Vec<Route>,
+        /// Routes in the new results
+        new: Vec<Route>,
+    },
+    /// Same origin AS numbers, but different BGP prefixes
+    Prefix {
+        /// IP address
+        ip: IpAddr,
+        /// Routes in the old results
+        old: Vec<Route>,
+        /// Routes in the new results
+        new: Vec<Route>,
+    },
+}
+
+impl Change {
+    /// IP address the change concerns
+    ///
+    pub fn ip(&self) -> IpAddr {
+        match self {
+            Change::Added { ip, .. }
+            | Change::Removed { ip, .. }
+            | Change::Origin { ip, .. }
+            | Change::Prefix { ip, .. } => *ip,
+        }
+    }
+}
+
+/// Compare `old` and `new` results, keyed by IP address
+///
+/// Returns changes sorted by IP address. Addresses with identical origins
+/// and prefixes in both sets are left out, even if other fields such as AS
+/// names changed.
+///
+pub fn diff<'a, I, J>(old: I, new: J) -> Vec<Change>
+where
+    I: IntoIterator<Item = &'a CymruIP2ASN>,
+    J: IntoIterator<Item = &'a CymruIP2ASN>,
+{
+    let old = routes(old);
+    let mut new = routes(new);
+    let mut changes = Vec::new();
+
+    for (ip, old) in old {
+        let new = match new.remove(&ip) {
+            Some(new) => new,
+            None => {
+                changes.push(Change::Removed {
+                    ip,
+                    old: old.into_iter().collect(),
+                });
+                continue;
+            }
+        };
+        if old == new {
+            continue;
+        }
+
+        let origins = |routes: &BTreeSet<Route>| -> BTreeSet<AsNumber> {
+            routes.iter().map(|route| route.as_number).collect()
+        };
+        let origin_changed = origins(&old) != origins(&new);
+        let old = old.into_iter().collect();
+        let new = new.into_iter().collect();
+        changes.push(if origin_changed {
+            Change::Origin { ip, old, new }
+        } else {
+            Change::Prefix { ip, old, new }
+        });
+    }
+
+    changes.extend(new.into_iter().map(|(ip, new)| Change::Added {
+        ip,
+        new: new.into_iter().collect(),
+    }));
+    changes.sort_by_key(Change::ip);
+    changes
+}
+
+/// Whether results `a` and `b` have the same origin AS and BGP prefix
+///
+/// Unlike `==`, this ignores AS names, registry details and `expires`, so
+/// results of two lookups done at different times compare equal if the
+/// routing didn't change. Results without a prefix never compare equal.
+///
+/// ```
+/// use cymrust::diff::same_origin;
+/// use cymrust::fixtures;
+///
+/// let mut later = fixtures::google();
+/// later.expires += std::time::Duration::from_secs(60);
+/// assert!(same_origin(&fixtures::google(), &later));
+/// assert!(!same_origin(&fixtures::google(), &fixtures::google_ipv6()));
+/// ```
+///
+pub fn same_origin<A, B>(a: &A, b: &B) -> bool
+where
+    A: AsnInfo + ?Sized,
+    B: AsnInfo + ?Sized,
+{
+    a.as_number() == b.as_number()
+        && match (a.bgp_prefix(), b.bgp_prefix()) {
+            (Some(a), Some(b)) => a == b,
+            _ => false,
+        }
+}
+
+/// Whether `ip` is inside the BGP prefix of `result`
+///
+/// ```
+/// use cymrust::diff::covers;
+/// use cymrust::fixtures;
+///
+/// assert!(covers(&fixtures::google(), "8.8.8.4".parse().unwrap()));
+/// assert!(!covers(&fixtures::google(), "8.8.4.4".parse().unwrap()));
+/// ```
+///
+pub fn covers<A: AsnInfo + ?Sized>(result: &A, ip: IpAddr) -> bool {
+    let prefix = match result.bgp_prefix().and_then(|p| p.split_once('/')) {
+        Some(prefix) => prefix,
+        None => return false,
+    };
+    match (prefix.0.parse(), prefix.1.parse::<u8>()) {
+        (Ok(IpAddr::V4(net)), Ok(len)) if len <= 32 => contains(IpAddr::V4(net), len, ip),
+        (Ok(IpAddr::V6(net)), Ok(len)) if len <= 128 => contains(IpAddr::V6(net), len, ip),
+        _ => false,
+    }
+}
+
+fn routes<'a, I>(results: I) -> BTreeMap<IpAddr, BTreeSet<Route>>
+where
+    I: IntoIterator<Item = &'a CymruIP2ASN>,
+{
+    let mut routes: BTreeMap<IpAddr, BTreeSet<Route>> = BTreeMap::new();
+    for result in results {
+        routes.entry(result.ip_addr).or_default().insert(Route {
+            as_number: result.as_number,
+            bgp_prefix: result.bgp_prefix.clone(),
+        });
+    }
+    routes
+}
+
+struct Routes<'a>(&'a [Route]);
+
+impl fmt::Display for Routes<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (i, route) in self.0.iter().enumerate() {
+            if i > 0 {
+                write!(f, ", ")?;
+            }
+            write!(f, "AS{} {}", route.as_number, route.bgp_prefix)?;
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Display for Change {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Change::Added { ip, new } => write!(f, "+ {} {}", ip, Routes(new)),
+            Change::Removed { ip, old } => write!(f, "- {} {}", ip, Routes(old)),
+            Change::Origin { ip, old, new } => {
+                write!(f, "! {} origin {} -> {}", ip, Routes(old), Routes(new))
+            }
+            Change::Prefix { ip, old, new } => {
+                write!(f, "~ {} prefix {} -> {}", ip, Routes(old), Routes(new))
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{covers, diff, same_origin, Change};
+    use crate::fixtures;
+
+    #[test]
+    fn test_diff() {
+        let old = vec![
+            fixtures::cloudflare(),
+            fixtures::google(),
+            fixtures::team_cymru(),
+        ];
+
+        let mut moved = fixtures::cloudflare();
+        moved.as_number = 64500;
+        let mut renumbered = fixtures::google();
+        renumbered.bgp_prefix = "8.8.0.0/16".to_string();
+        let mut renamed = fixtures::team_cymru();
+        renamed.as_name = "RENAMED".to_string();
+        let new = vec![moved, renumbered, renamed, fixtures::google_ipv6()];
+
+        let changes = diff(&old, &new);
+        let lines: Vec<String> = changes.iter().map(ToString::to_string).collect();
+        assert_eq!(
+            lines,
+            vec![
+                "! 1.1.1.1 origin AS13335 1.1.1.0/24 -> AS64500 1.1.1.0/24",
+                "~ 8.8.8.8 prefix AS15169 8.8.8.0/24 -> AS15169 8.8.0.0/16",
+                "+ 2001:4860:4860::8888 AS15169 2001:4860::/32",
+            ]
+        );
+        assert!(matches!(changes[2], Change::Added { .. }));
+
+        let changes = diff(&new[..1], &old[..0]);
+        assert!(matches!(changes[..], [Change::Removed { .. }]));
+    }
+
+    #[test]
+    fn test_same_origin_and_covers() {
+        let google = fixtures::google();
+        let mut renamed = fixtures::google();
+        renamed.as_name = "RENAMED".to_string();
+        assert!(same_origin(&google, &renamed));
+        assert!(!same_origin(&google, &fixtures::google_asn()));
+
+        let origin = crate::CymruOrigin {
+            as_number: google.as_number,
+            bgp_prefix: google.bgp_prefix.clone(),
+            country_code: String::new(),
+            registry: String::new(),
+            allocated: None,
+            expires: google.expires,
+        };
+        assert!(same_origin(&google, &origin));
+
+        assert!(covers(
+            &fixtures::google_ipv6(),
+            "2001:4860::1".parse().unwrap()
+        ));
+        assert!(!covers(&google, "2001:4860::1".parse().unwrap()));
+        assert!(!covers(&fixtures::google_asn(), "8.8.8.8".parse().unwrap()));
+        renamed.bgp_prefix = "8.8.8.0/33".to_string();
+        assert!(!covers(&renamed, "8.8.8.8".parse().unwrap()));
+    }
+}
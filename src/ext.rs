@@ -0,0 +1,246 @@
+//! Extension traits for IP addresses, AS numbers and iterators
+
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::{AsNumber, CymruClient, CymruIP2ASN, CymruOrigin, Error};
+
+/// Lookups as methods of IP address types
+///
+/// ```no_run
+/// use std::net::IpAddr;
+/// use cymrust::{CymruClient, IpAsnExt};
+///
+/// let client = CymruClient::new().unwrap();
+/// let ip: IpAddr = "8.8.8.8".parse().unwrap();
+/// let info = ip.asn_info(&client).unwrap();
+/// println!("{} is originated by AS{}", ip, info[0].as_number);
+/// ```
+///
+pub trait IpAsnExt {
+    /// Same as [`CymruClient::ip2asn`](struct.CymruClient.html#method.ip2asn)
+    fn asn_info(&self, client: &CymruClient) -> Result<Vec<CymruIP2ASN>, Error>;
+
+    /// Same as [`CymruClient::origin`](struct.CymruClient.html#method.origin)
+    fn origin(&self, client: &CymruClient) -> Result<Vec<CymruOrigin>, Error>;
+}
+
+impl IpAsnExt for IpAddr {
+    fn asn_info(&self, client: &CymruClient) -> Result<Vec<CymruIP2ASN>, Error> {
+        client.ip2asn(*self)
+    }
+
+    fn origin(&self, client: &CymruClient) -> Result<Vec<CymruOrigin>, Error> {
+        client.origin(*self)
+    }
+}
+
+impl IpAsnExt for Ipv4Addr {
+    fn asn_info(&self, client: &CymruClient) -> Result<Vec<CymruIP2ASN>, Error> {
+        client.ip2asn(IpAddr::V4(*self))
+    }
+
+    fn origin(&self, client: &CymruClient) -> Result<Vec<CymruOrigin>, Error> {
+        client.origin(IpAddr::V4(*self))
+    }
+}
+
+impl IpAsnExt for Ipv6Addr {
+    fn asn_info(&self, client: &CymruClient) -> Result<Vec<CymruIP2ASN>, Error> {
+        client.ip2asn(IpAddr::V6(*self))
+    }
+
+    fn origin(&self, client: &CymruClient) -> Result<Vec<CymruOrigin>, Error> {
+        client.origin(IpAddr::V6(*self))
+    }
+}
+
+/// Registry classification of AS number ranges
+///
+/// See [IANA's AS number registry](https://www.iana.org/assignments/as-numbers/)
+/// and RFCs 5398, 6793, 6996 and 7300.
+///
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum AsRange {
+    /// Reserved AS numbers 0, 65535, 65552-131071 and 4294967295
+    Reserved,
+    /// AS 23456, which 2-byte speakers use in place of 4-byte AS numbers
+    AsTrans,
+    /// Documentation AS numbers 64496-64511 and 65536-65551
+    Documentation,
+    /// Private use AS numbers 64512-65534 and 4200000000-4294967294
+    Private,
+    /// Any other, globally assignable AS number
+    Public,
+}
+
+/// Classification helpers for AS numbers
+///
+/// ```
+/// use cymrust::{AsNumberExt, AsRange};
+///
+/// assert!(23028.is_16bit());
+/// assert!(4_200_000_000.is_32bit());
+/// assert_eq!(64512.range(), AsRange::Private);
+/// ```
+///
+pub trait AsNumberExt {
+    /// Whether AS number fits in the original 2-byte AS number space
+    fn is_16bit(&self) -> bool;
+
+    /// Whether AS number needs 4 bytes, i.e. is above 65535
+    fn is_32bit(&self) -> bool;
+
+    /// Registry range the AS number belongs to
+    fn range(&self) -> AsRange;
+}
+
+impl AsNumberExt for AsNumber {
+    fn is_16bit(&self) -> bool {
+        *self <= 0xffff
+    }
+
+    fn is_32bit(&self) -> bool {
+        !self.is_16bit()
+    }
+
+    fn range(&self) -> AsRange {
+        match *self {
+            0 | 65535 | 65552..=131_071 | 4_294_967_295 => AsRange::Reserved,
+            23456 => AsRange::AsTrans,
+            64496..=64511 | 65536..=65551 => AsRange::Documentation,
+            64512..=65534 | 4_200_000_000..=4_294_967_294 => AsRange::Private,
+            _ => AsRange::Public,
+        }
+    }
+}
+
+/// Iterator yielding items of another no faster than one per interval
+///
+/// Created with [`PacedExt::paced`](trait.PacedExt.html#method.paced).
+///
+pub struct Paced<'a, I> {
+    inner: I,
+    interval: Duration,
+    next_at: Option<Instant>,
+    client: Option<&'a CymruClient>,
+}
+
+impl<'a, I> Paced<'a, I> {
+    /// Also hold off while `client` backs off after being rate limited
+    ///
+    /// See [`CymruClient::backoff_remaining`](struct.CymruClient.html#method.backoff_remaining).
+    ///
+    pub fn with_client(mut self, client: &'a CymruClient) -> Self {
+        self.client = Some(client);
+        self
+    }
+}
+
+impl<I: Iterator> Iterator for Paced<'_, I> {
+    type Item = I::Item;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let item = self.inner.next()?;
+
+        let now = Instant::now();
+        let mut wait = self.next_at.map_or(Duration::from_secs(0), |at| {
+            at.saturating_duration_since(now)
+        });
+        if let Some(backoff) = self.client.and_then(CymruClient::backoff_remaining) {
+            wait = wait.max(backoff);
+        }
+        if wait > Duration::from_secs(0) {
+            thread::sleep(wait);
+        }
+
+        self.next_at = Some(Instant::now() + self.interval);
+        Some(item)
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.inner.size_hint()
+    }
+}
+
+/// Pacing for hand-written lookup loops
+///
+/// ```
+/// use std::time::Duration;
+/// use cymrust::{fixtures, CymruClient, PacedExt};
+///
+/// let client = CymruClient::with_provider(fixtures::mock_provider());
+/// let ips = ["8.8.8.8", "1.1.1.1"];
+/// for ip in ips.iter().paced(Duration::from_millis(10)).with_client(&client) {
+///     client.ip2asn(ip.parse().unwrap()).unwrap();
+/// }
+/// ```
+///
+pub trait PacedExt: Iterator + Sized {
+    /// Yield items no faster than one per `interval`
+    ///
+    /// The first item is yielded right away; each one after it only once
+    /// `interval` has passed since the previous one.
+    ///
+    fn paced<'a>(self, interval: Duration) -> Paced<'a, Self> {
+        Paced {
+            inner: self,
+            interval,
+            next_at: None,
+            client: None,
+        }
+    }
+}
+
+impl<I: Iterator> PacedExt for I {}
+
+#[cfg(test)]
+mod tests {
+    use std::net::{Ipv4Addr, Ipv6Addr};
+
+    use std::time::{Duration, Instant};
+
+    use super::{AsNumberExt, AsRange, IpAsnExt, PacedExt};
+    use crate::{fixtures, CymruClient};
+
+    #[test]
+    fn test_as_range() {
+        assert!(65535.is_16bit());
+        assert!(65536.is_32bit());
+        assert_eq!(0.range(), AsRange::Reserved);
+        assert_eq!(13335.range(), AsRange::Public);
+        assert_eq!(23456.range(), AsRange::AsTrans);
+        assert_eq!(64500.range(), AsRange::Documentation);
+        assert_eq!(65534.range(), AsRange::Private);
+        assert_eq!(65551.range(), AsRange::Documentation);
+        assert_eq!(100_000.range(), AsRange::Reserved);
+        assert_eq!(397_000.range(), AsRange::Public);
+        assert_eq!(4_294_967_295.range(), AsRange::Reserved);
+    }
+
+    #[test]
+    fn test_ext() {
+        let client = CymruClient::with_provider(fixtures::mock_provider());
+
+        let ip: Ipv4Addr = fixtures::MULTI_ORIGIN_IP.parse().unwrap();
+        let origins = ip.origin(&client).unwrap();
+        assert_eq!(origins.len(), 2);
+        assert_eq!(origins[1].as_number, 64497);
+        assert_eq!(origins[1].bgp_prefix, "198.51.100.0/24");
+
+        let ip: Ipv6Addr = fixtures::GOOGLE_IPV6.parse().unwrap();
+        let info = ip.asn_info(&client).unwrap();
+        assert_eq!(info[0].as_name, fixtures::google_ipv6().as_name);
+    }
+
+    #[test]
+    fn test_paced() {
+        let started = Instant::now();
+        let items: Vec<u32> = (0..3).paced(Duration::from_millis(20)).collect();
+        assert_eq!(items, vec![0, 1, 2]);
+        assert!(started.elapsed() >= Duration::from_millis(40));
+
+        assert_eq!((0..1).paced(Duration::from_secs(10)).count(), 1);
+    }
+}
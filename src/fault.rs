@@ -0,0 +1,301 @@
+//! Fault injection for testing resilience of code using this crate
+//!
+//! [`FaultInjector`](struct.FaultInjector.html) wraps another provider and,
+//! at configurable probabilities, slows answers down, times them out, fails
+//! them with SERVFAIL or replaces them with garbage records. This way
+//! applications can exercise their retry and fallback handling against the
+//! failure modes seen in the wild, without needing a misbehaving network.
+//!
+//! ```
+//! use cymrust::fault::FaultInjector;
+//! use cymrust::provider::MockProvider;
+//! use cymrust::CymruClient;
+//!
+//! let faulty = FaultInjector::new(MockProvider::new())
+//!     .seed(42)
+//!     .servfail(1.0);
+//! let client = CymruClient::with_provider(faulty);
+//! assert!(client.asn(64500u32).is_err());
+//! ```
+
+use std::net::IpAddr;
+use std::str::FromStr;
+use std::sync::Mutex;
+use std::thread;
+use std::time::Duration;
+
+use trust_dns_resolver::error::{ResolveError, ResolveErrorKind};
+use trust_dns_resolver::proto::op::{Query, ResponseCode};
+use trust_dns_resolver::proto::rr::{Name, RecordType};
+
+use crate::provider::{Provider, TxtAnswer};
+use crate::{Error, Stage};
+
+/// Malformed TXT records served in place of real answers
+///
+const GARBAGE: &[&str] = &[
+    "",
+    "garbage",
+    "| | | |",
+    "AS-NaN | 999.0.0.0/33 | ?? | nowhere | 2001-13-32",
+    "\u{fffd}\u{fffd}\u{fffd}",
+];
+
+/// Provider injecting faults into answers of another provider
+///
+/// Each query draws independently for each configured fault, in this order:
+/// latency, timeout, SERVFAIL, REFUSED and garbage. Probabilities are clamped to
+/// `0.0..=1.0`; all default to zero, so a fresh injector is transparent.
+///
+pub struct FaultInjector<P> {
+    inner: P,
+    latency: Option<(Duration, f64)>,
+    timeout: Option<(Duration, f64)>,
+    servfail: f64,
+    refused: f64,
+    garbage: f64,
+    rng: Mutex<u64>,
+}
+
+impl<P: Provider> FaultInjector<P> {
+    /// Wrap `inner` without injecting any faults yet
+    ///
+    pub fn new(inner: P) -> Self {
+        FaultInjector {
+            inner,
+            latency: None,
+            timeout: None,
+            servfail: 0.0,
+            refused: 0.0,
+            garbage: 0.0,
+            rng: Mutex::new(0x853c_49e6_748f_ea9b),
+        }
+    }
+
+    /// Seed the random number generator, for reproducible fault sequences
+    ///
+    pub fn seed(mut self, seed: u64) -> Self {
+        let state = match self.rng.get_mut() {
+            Ok(state) => state,
+            Err(poisoned) => poisoned.into_inner(),
+        };
+        // Xorshift gets stuck at zero
+        *state = seed | 1;
+        self
+    }
+
+    /// Delay answers by `delay` with `probability`
+    ///
+    pub fn latency(mut self, delay: Duration, probability: f64) -> Self {
+        self.latency = Some((delay, probability));
+        self
+    }
+
+    /// Fail queries with a timeout error after `after` with `probability`
+    ///
+    pub fn timeout(mut self, after: Duration, probability: f64) -> Self {
+        self.timeout = Some((after, probability));
+        self
+    }
+
+    /// Fail queries with SERVFAIL response with `probability`
+    ///
+    pub fn servfail(mut self, probability: f64) -> Self {
+        self.servfail = probability;
+        self
+    }
+
+    /// Fail queries with REFUSED response, as rate limiting upstreams do,
+    /// with `probability`
+    ///
+    pub fn refused(mut self, probability: f64) -> Self {
+        self.refused = probability;
+        self
+    }
+
+    /// Replace records of successful answers with garbage with `probability`
+    ///
+    pub fn garbage(mut self, probability: f64) -> Self {
+        self.garbage = probability;
+        self
+    }
+
+    /// Next pseudo-random number, using xorshift64
+    ///
+    fn next(&self) -> u64 {
+        let mut state = match self.rng.lock() {
+            Ok(state) => state,
+            Err(poisoned) => poisoned.into_inner(),
+        };
+        *state ^= *state << 13;
+        *state ^= *state >> 7;
+        *state ^= *state << 17;
+        *state
+    }
+
+    /// Return true with `probability`
+    ///
+    fn chance(&self, probability: f64) -> bool {
+        probability > 0.0 && ((self.next() >> 11) as f64 / (1u64 << 53) as f64) < probability
+    }
+
+    fn resolver_error(query: &str, stage: Stage, kind: ResolveErrorKind) -> Error {
+        Error::resolver(query, stage, ResolveError::from(kind))
+    }
+
+    fn response_error(query: &str, stage: Stage, response_code: ResponseCode) -> Error {
+        let name = Name::from_str(query).unwrap_or_default();
+        let kind = ResolveErrorKind::NoRecordsFound {
+            query: Query::query(name, RecordType::TXT),
+            soa: None,
+            negative_ttl: None,
+            response_code,
+            trusted: false,
+        };
+        Self::resolver_error(query, stage, kind)
+    }
+}
+
+impl<P: Provider> Provider for FaultInjector<P> {
+    fn name(&self) -> &'static str {
+        self.inner.name()
+    }
+
+    fn is_network(&self) -> bool {
+        self.inner.is_network()
+    }
+
+    /// Reverse lookups are passed through without faults
+    fn ptr(&self, query: &str, stage: Stage) -> Result<Vec<String>, Error> {
+        self.inner.ptr(query, stage)
+    }
+
+    /// Address lookups are passed through without faults
+    fn addrs(&self, query: &str, stage: Stage) -> Result<Vec<IpAddr>, Error> {
+        self.inner.addrs(query, stage)
+    }
+
+    fn txt(&self, query: &str, stage: Stage) -> Result<TxtAnswer, Error> {
+        if let Some((delay, probability)) = self.latency {
+            if self.chance(probability) {
+                debug!("Injecting {:?} latency into {}", delay, query);
+                thread::sleep(delay);
+            }
+        }
+
+        if let Some((after, probability)) = self.timeout {
+            if self.chance(probability) {
+                debug!("Injecting timeout into {}", query);
+                thread::sleep(after);
+                return Err(Self::resolver_error(
+                    query,
+                    stage,
+                    ResolveErrorKind::Timeout,
+                ));
+            }
+        }
+
+        if self.chance(self.servfail) {
+            debug!("Injecting SERVFAIL into {}", query);
+            return Err(Self::response_error(query, stage, ResponseCode::ServFail));
+        }
+
+        if self.chance(self.refused) {
+            debug!("Injecting REFUSED into {}", query);
+            return Err(Self::response_error(query, stage, ResponseCode::Refused));
+        }
+
+        let mut answer = self.inner.txt(query, stage)?;
+        if self.chance(self.garbage) {
+            debug!("Injecting garbage records into {}", query);
+            answer.records = (0..answer.records.len())
+                .map(|_| GARBAGE[(self.next() % GARBAGE.len() as u64) as usize])
+                .collect();
+        }
+
+        Ok(answer)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, Instant};
+
+    use trust_dns_resolver::error::ResolveErrorKind;
+    use trust_dns_resolver::proto::op::ResponseCode;
+
+    use super::FaultInjector;
+    use crate::provider::{MockProvider, Provider};
+    use crate::{Error, Stage};
+
+    const QUERY: &str = "AS64500.asn.cymru.com.";
+
+    fn mock() -> MockProvider {
+        MockProvider::new().answer(
+            QUERY,
+            Duration::from_secs(60),
+            &["64500 | US | arin | 2010-01-01 | EXAMPLE"],
+        )
+    }
+
+    #[test]
+    fn test_transparent_by_default() {
+        let faulty = FaultInjector::new(mock());
+        let answer = faulty.txt(QUERY, Stage::Asn).unwrap();
+        assert_eq!(answer, mock().txt(QUERY, Stage::Asn).unwrap());
+    }
+
+    #[test]
+    fn test_faults() {
+        let faulty = FaultInjector::new(mock()).servfail(1.0);
+        match faulty.txt(QUERY, Stage::Asn) {
+            Err(Error::Resolver { source, .. }) => match source.kind() {
+                ResolveErrorKind::NoRecordsFound { response_code, .. } => {
+                    assert_eq!(*response_code, ResponseCode::ServFail)
+                }
+                other => panic!("unexpected {:?}", other),
+            },
+            other => panic!("unexpected {:?}", other),
+        }
+
+        let faulty = FaultInjector::new(mock()).timeout(Duration::from_millis(20), 1.0);
+        let started = Instant::now();
+        match faulty.txt(QUERY, Stage::Asn) {
+            Err(Error::Resolver { source, .. }) => {
+                assert!(matches!(source.kind(), ResolveErrorKind::Timeout))
+            }
+            other => panic!("unexpected {:?}", other),
+        }
+        assert!(started.elapsed() >= Duration::from_millis(20));
+
+        let faulty = FaultInjector::new(mock()).refused(1.0);
+        match faulty.txt(QUERY, Stage::Asn) {
+            Err(Error::RateLimited { query, backoff, .. }) => {
+                assert_eq!(query, QUERY);
+                assert!(backoff > Duration::from_secs(0));
+            }
+            other => panic!("unexpected {:?}", other),
+        }
+
+        let faulty = FaultInjector::new(mock()).garbage(1.0);
+        let answer = faulty.txt(QUERY, Stage::Asn).unwrap();
+        assert_ne!(
+            answer.records,
+            mock().txt(QUERY, Stage::Asn).unwrap().records
+        );
+    }
+
+    #[test]
+    fn test_probability_is_seeded() {
+        let failures = |seed| {
+            let faulty = FaultInjector::new(mock()).seed(seed).servfail(0.5);
+            (0..200)
+                .map(|_| faulty.txt(QUERY, Stage::Asn).is_err())
+                .collect::<Vec<_>>()
+        };
+        let run = failures(7);
+        assert_eq!(run, failures(7));
+        let count = run.iter().filter(|&&failed| failed).count();
+        assert!(count > 60 && count < 140, "{}", count);
+    }
+}
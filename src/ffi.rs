@@ -0,0 +1,211 @@
+//! C interface
+//!
+//! Available with the `ffi` feature. Build a static or shared library for
+//! linking from C or C++ with e.g.
+//!
+//! ```text
+//! cargo rustc --release --features ffi --crate-type staticlib
+//! ```
+//!
+//! and include `include/cymrust.h` from the source distribution:
+//!
+//! ```c
+//! CymrustResults *results = cymrust_ip2asn("8.8.8.8");
+//! if (results->error) {
+//!     fprintf(stderr, "lookup failed: %s\n", results->error);
+//! } else {
+//!     for (size_t i = 0; i < results->len; i++) {
+//!         printf("AS%u %s\n", results->items[i].as_number, results->items[i].as_name);
+//!     }
+//! }
+//! cymrust_results_free(results);
+//! ```
+//!
+//! All strings are NUL terminated UTF-8 and owned by the results; they stay
+//! valid until `cymrust_results_free()` is called.
+
+use std::ffi::{CStr, CString};
+use std::os::raw::c_char;
+use std::ptr;
+use std::time::UNIX_EPOCH;
+
+use crate::{cymru_ip2asn, CymruIP2ASN, Error};
+
+/// Flattened `CymruIP2ASN` result
+///
+#[repr(C)]
+#[derive(Debug)]
+pub struct CymrustIp2Asn {
+    /// Queried IP address
+    pub ip_addr: *mut c_char,
+    /// BGP prefix containing the address
+    pub bgp_prefix: *mut c_char,
+    /// Origin AS number
+    pub as_number: u32,
+    /// AS name
+    pub as_name: *mut c_char,
+    /// Country code
+    pub country_code: *mut c_char,
+    /// Regional registry
+    pub registry: *mut c_char,
+    /// Allocation date, or NULL if not known
+    pub allocated: *mut c_char,
+    /// Expiry time as seconds since the Unix epoch
+    pub expires: u64,
+}
+
+/// Results of a lookup, or error message if it failed
+///
+#[repr(C)]
+#[derive(Debug)]
+pub struct CymrustResults {
+    /// Array of `len` results
+    pub items: *mut CymrustIp2Asn,
+    /// Number of results in `items`
+    pub len: usize,
+    /// Error message, or NULL if lookup succeeded
+    pub error: *mut c_char,
+}
+
+/// Query Cymru's IP-to-ASN mapping for IP address given as string
+///
+/// Returns results which must be released with `cymrust_results_free()`.
+/// Never returns NULL; failures are reported through the `error` field.
+///
+/// # Safety
+///
+/// `ip` must be NULL or a valid pointer to a NUL terminated string.
+///
+#[no_mangle]
+pub unsafe extern "C" fn cymrust_ip2asn(ip: *const c_char) -> *mut CymrustResults {
+    let result = if ip.is_null() {
+        Err("IP address is NULL".to_string())
+    } else {
+        match CStr::from_ptr(ip).to_str().map(str::parse) {
+            Ok(Ok(ip)) => cymru_ip2asn(ip).map_err(|err: Error| err.to_string()),
+            _ => Err("invalid IP address".to_string()),
+        }
+    };
+    Box::into_raw(Box::new(results(result)))
+}
+
+/// Release results returned by `cymrust_ip2asn()`
+///
+/// # Safety
+///
+/// `results` must be NULL or a pointer returned by `cymrust_ip2asn()` which
+/// has not been freed yet.
+///
+#[no_mangle]
+pub unsafe extern "C" fn cymrust_results_free(results: *mut CymrustResults) {
+    if results.is_null() {
+        return;
+    }
+    let results = Box::from_raw(results);
+    free_string(results.error);
+    if results.items.is_null() {
+        return;
+    }
+    let items = Box::from_raw(ptr::slice_from_raw_parts_mut(results.items, results.len));
+    for item in items.iter() {
+        free_string(item.ip_addr);
+        free_string(item.bgp_prefix);
+        free_string(item.as_name);
+        free_string(item.country_code);
+        free_string(item.registry);
+        free_string(item.allocated);
+    }
+}
+
+/// Flatten lookup `result` into C representation
+///
+fn results(result: Result<Vec<CymruIP2ASN>, String>) -> CymrustResults {
+    match result {
+        Ok(found) => {
+            let items: Box<[CymrustIp2Asn]> = found.iter().map(flatten).collect();
+            let len = items.len();
+            CymrustResults {
+                items: Box::into_raw(items) as *mut CymrustIp2Asn,
+                len,
+                error: ptr::null_mut(),
+            }
+        }
+        Err(message) => CymrustResults {
+            items: ptr::null_mut(),
+            len: 0,
+            error: c_string(&message),
+        },
+    }
+}
+
+fn flatten(result: &CymruIP2ASN) -> CymrustIp2Asn {
+    CymrustIp2Asn {
+        ip_addr: c_string(&result.ip_addr.to_string()),
+        bgp_prefix: c_string(&result.bgp_prefix),
+        as_number: result.as_number,
+        as_name: c_string(&result.as_name),
+        country_code: c_string(&result.country_code),
+        registry: c_string(&result.registry),
+        allocated: result
+            .allocated
+            .as_deref()
+            .map_or(ptr::null_mut(), c_string),
+        expires: result
+            .expires
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |d| d.as_secs()),
+    }
+}
+
+/// Copy `s` into newly allocated C string, dropping any NUL bytes
+///
+fn c_string(s: &str) -> *mut c_char {
+    let bytes: Vec<u8> = s.bytes().filter(|&b| b != 0).collect();
+    CString::new(bytes).unwrap_or_default().into_raw()
+}
+
+unsafe fn free_string(s: *mut c_char) {
+    if !s.is_null() {
+        drop(CString::from_raw(s));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::ffi::CStr;
+
+    use super::{cymrust_ip2asn, cymrust_results_free, results};
+    use crate::fixtures;
+
+    #[test]
+    fn test_results() {
+        let results = Box::into_raw(Box::new(results(Ok(fixtures::multi_origin()))));
+        unsafe {
+            assert!((*results).error.is_null());
+            assert_eq!((*results).len, 2);
+            let second = &*(*results).items.add(1);
+            assert_eq!(second.as_number, 64497);
+            assert_eq!(
+                CStr::from_ptr(second.bgp_prefix).to_str(),
+                Ok("198.51.100.0/24")
+            );
+            assert_eq!(CStr::from_ptr(second.allocated).to_str(), Ok("2006-02-17"));
+            assert_eq!(second.expires, 1_700_000_000);
+            cymrust_results_free(results);
+        }
+    }
+
+    #[test]
+    fn test_invalid_ip() {
+        unsafe {
+            let results = cymrust_ip2asn(b"not an ip\0".as_ptr() as *const _);
+            assert_eq!((*results).len, 0);
+            assert_eq!(
+                CStr::from_ptr((*results).error).to_str(),
+                Ok("invalid IP address")
+            );
+            cymrust_results_free(results);
+            cymrust_results_free(std::ptr::null_mut());
+        }
+    }
+}
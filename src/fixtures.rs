@@ -0,0 +1,279 @@
+//! Realistic sample data for tests and documentation
+//!
+//! Available with the `fixtures` feature. Raw TXT strings are in the exact
+//! format Cymru serves them, and the matching `CymruIP2ASN` and `CymruASN`
+//! values are what this crate parses out of them. All values expire at the
+//! fixed [`expires()`](fn.expires.html) time so that tests comparing them stay
+//! deterministic. IP-to-ASN results are attributed to `Source::Mock`.
+//!
+//! [`mock_provider()`](fn.mock_provider.html) serves all the fixtures, so
+//! client code can be tested end-to-end without network access:
+//!
+//! ```
+//! use cymrust::{fixtures, CymruClient};
+//!
+//! let client = CymruClient::with_provider(fixtures::mock_provider());
+//! let results = client.ip2asn(fixtures::CLOUDFLARE_IP.parse().unwrap()).unwrap();
+//! assert_eq!(results[0].as_number, fixtures::cloudflare().as_number);
+//! ```
+
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use chrono::NaiveDate;
+
+use crate::provider::MockProvider;
+use crate::{CymruASN, CymruIP2ASN, OriginAnomaly, Source};
+
+/// Cloudflare's public resolver address
+pub const CLOUDFLARE_IP: &str = "1.1.1.1";
+/// Origin TXT answer for `CLOUDFLARE_IP`
+pub const CLOUDFLARE_ORIGIN_TXT: &str = "13335 | 1.1.1.0/24 | AU | apnic | 2011-08-11";
+/// ASN TXT answer for AS13335
+pub const CLOUDFLARE_ASN_TXT: &str = "13335 | US | arin | 2010-07-14 | CLOUDFLARENET, US";
+
+/// Google's public resolver address
+pub const GOOGLE_IP: &str = "8.8.8.8";
+/// Origin TXT answer for `GOOGLE_IP`
+pub const GOOGLE_ORIGIN_TXT: &str = "15169 | 8.8.8.0/24 | US | arin | 1992-12-01";
+/// ASN TXT answer for AS15169
+pub const GOOGLE_ASN_TXT: &str = "15169 | US | arin | 2000-03-30 | GOOGLE, US";
+
+/// Google's public resolver IPv6 address
+pub const GOOGLE_IPV6: &str = "2001:4860:4860::8888";
+/// Origin TXT answer for `GOOGLE_IPV6`
+pub const GOOGLE_ORIGIN6_TXT: &str = "15169 | 2001:4860::/32 | US | arin | 2005-03-14";
+
+/// Address inside Team Cymru's own sample prefix
+pub const TEAM_CYMRU_IP: &str = "216.90.108.31";
+/// Origin TXT answer for `TEAM_CYMRU_IP`, as in Cymru's documentation
+pub const TEAM_CYMRU_ORIGIN_TXT: &str = "23028 | 216.90.108.0/24 | US | arin | 1998-09-25";
+/// ASN TXT answer for AS23028, as in Cymru's documentation
+pub const TEAM_CYMRU_ASN_TXT: &str =
+    "23028 | US | arin | 2002-01-04 | TEAM-CYMRU - Team Cymru Inc., US";
+
+/// Address in a prefix originated by two AS numbers (MOAS)
+pub const MULTI_ORIGIN_IP: &str = "198.51.100.7";
+/// Origin TXT answer for `MULTI_ORIGIN_IP`, listing both origins in one record
+pub const MULTI_ORIGIN_TXT: &str = "64496 64497 | 198.51.100.0/24 | US | arin | 2006-02-17";
+/// ASN TXT answer for AS64496
+pub const MULTI_ORIGIN_ASN1_TXT: &str =
+    "64496 | US | arin | 2006-02-17 | EXAMPLE-ONE - Example One Inc., US";
+/// ASN TXT answer for AS64497
+pub const MULTI_ORIGIN_ASN2_TXT: &str =
+    "64497 | US | arin | 2006-02-17 | EXAMPLE-TWO - Example Two Inc., US";
+
+/// TTL served by `mock_provider()`
+pub const TTL: Duration = Duration::from_secs(3600);
+
+/// Fixed expiry time of all fixture values
+///
+pub fn expires() -> SystemTime {
+    UNIX_EPOCH + Duration::from_secs(1_700_000_000)
+}
+
+fn date(date: &str) -> Option<NaiveDate> {
+    NaiveDate::parse_from_str(date, "%Y-%m-%d").ok()
+}
+
+fn ip2asn(
+    ip: &str,
+    prefix: &str,
+    asn: &CymruASN,
+    cc: &str,
+    reg: &str,
+    allocated: &str,
+) -> CymruIP2ASN {
+    CymruIP2ASN {
+        ip_addr: ip.parse().unwrap(),
+        bgp_prefix: prefix.to_string(),
+        as_number: asn.as_number,
+        as_name: asn.as_name.clone(),
+        country_code: cc.to_string(),
+        registry: reg.to_string(),
+        allocated: Some(allocated.to_string()),
+        expires: expires(),
+        anomalous_origins: None,
+        source: Source::Mock,
+    }
+}
+
+fn asn(as_number: u32, cc: &str, reg: &str, allocated: &str, name: &str) -> CymruASN {
+    CymruASN {
+        as_number,
+        country_code: cc.to_string(),
+        registry: reg.to_string(),
+        allocated: date(allocated),
+        as_name: name.to_string(),
+        expires: expires(),
+    }
+}
+
+/// AS13335 (Cloudflare) as parsed from `CLOUDFLARE_ASN_TXT`
+pub fn cloudflare_asn() -> CymruASN {
+    asn(13335, "US", "arin", "2010-07-14", "CLOUDFLARENET, US")
+}
+
+/// IP-to-ASN result for `CLOUDFLARE_IP`
+pub fn cloudflare() -> CymruIP2ASN {
+    ip2asn(
+        CLOUDFLARE_IP,
+        "1.1.1.0/24",
+        &cloudflare_asn(),
+        "AU",
+        "apnic",
+        "2011-08-11",
+    )
+}
+
+/// AS15169 (Google) as parsed from `GOOGLE_ASN_TXT`
+pub fn google_asn() -> CymruASN {
+    asn(15169, "US", "arin", "2000-03-30", "GOOGLE, US")
+}
+
+/// IP-to-ASN result for `GOOGLE_IP`
+pub fn google() -> CymruIP2ASN {
+    ip2asn(
+        GOOGLE_IP,
+        "8.8.8.0/24",
+        &google_asn(),
+        "US",
+        "arin",
+        "1992-12-01",
+    )
+}
+
+/// IP-to-ASN result for `GOOGLE_IPV6`
+pub fn google_ipv6() -> CymruIP2ASN {
+    ip2asn(
+        GOOGLE_IPV6,
+        "2001:4860::/32",
+        &google_asn(),
+        "US",
+        "arin",
+        "2005-03-14",
+    )
+}
+
+/// AS23028 (Team Cymru) as parsed from `TEAM_CYMRU_ASN_TXT`
+pub fn team_cymru_asn() -> CymruASN {
+    asn(
+        23028,
+        "US",
+        "arin",
+        "2002-01-04",
+        "TEAM-CYMRU - Team Cymru Inc., US",
+    )
+}
+
+/// IP-to-ASN result for `TEAM_CYMRU_IP`
+pub fn team_cymru() -> CymruIP2ASN {
+    ip2asn(
+        TEAM_CYMRU_IP,
+        "216.90.108.0/24",
+        &team_cymru_asn(),
+        "US",
+        "arin",
+        "1998-09-25",
+    )
+}
+
+/// IP-to-ASN results for `MULTI_ORIGIN_IP`, one per origin AS, flagged as
+/// anomalous
+pub fn multi_origin() -> Vec<CymruIP2ASN> {
+    [
+        asn(
+            64496,
+            "US",
+            "arin",
+            "2006-02-17",
+            "EXAMPLE-ONE - Example One Inc., US",
+        ),
+        asn(
+            64497,
+            "US",
+            "arin",
+            "2006-02-17",
+            "EXAMPLE-TWO - Example Two Inc., US",
+        ),
+    ]
+    .iter()
+    .map(|a| {
+        let mut result = ip2asn(
+            MULTI_ORIGIN_IP,
+            "198.51.100.0/24",
+            a,
+            "US",
+            "arin",
+            "2006-02-17",
+        );
+        result.anomalous_origins = Some(OriginAnomaly {
+            moas: true,
+            conflicting_prefixes: false,
+            origins: vec![
+                ("198.51.100.0/24".to_string(), 64496),
+                ("198.51.100.0/24".to_string(), 64497),
+            ],
+        });
+        result
+    })
+    .collect()
+}
+
+/// `MockProvider` answering queries for all fixture addresses and AS numbers
+///
+pub fn mock_provider() -> MockProvider {
+    MockProvider::new()
+        .answer("1.1.1.1.origin.asn.cymru.com.", TTL, &[CLOUDFLARE_ORIGIN_TXT])
+        .answer("AS13335.asn.cymru.com.", TTL, &[CLOUDFLARE_ASN_TXT])
+        .answer("8.8.8.8.origin.asn.cymru.com.", TTL, &[GOOGLE_ORIGIN_TXT])
+        .answer(
+            "8.8.8.8.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.6.8.4.0.6.8.4.1.0.0.2.origin6.asn.cymru.com.",
+            TTL,
+            &[GOOGLE_ORIGIN6_TXT],
+        )
+        .answer("AS15169.asn.cymru.com.", TTL, &[GOOGLE_ASN_TXT])
+        .answer("31.108.90.216.origin.asn.cymru.com.", TTL, &[TEAM_CYMRU_ORIGIN_TXT])
+        .answer("AS23028.asn.cymru.com.", TTL, &[TEAM_CYMRU_ASN_TXT])
+        .answer("7.100.51.198.origin.asn.cymru.com.", TTL, &[MULTI_ORIGIN_TXT])
+        .answer("AS64496.asn.cymru.com.", TTL, &[MULTI_ORIGIN_ASN1_TXT])
+        .answer("AS64497.asn.cymru.com.", TTL, &[MULTI_ORIGIN_ASN2_TXT])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{parse_cymru_asn, CymruClient, Strictness};
+
+    /// Fixture values must be exactly what parsing their TXT strings yields
+    #[test]
+    fn test_fixtures_match_parser() {
+        let asns = parse_cymru_asn(
+            &[CLOUDFLARE_ASN_TXT, GOOGLE_ASN_TXT, TEAM_CYMRU_ASN_TXT]
+                .iter()
+                .collect(),
+            expires(),
+            Strictness::Lenient,
+        )
+        .unwrap();
+        assert_eq!(asns, vec![cloudflare_asn(), google_asn(), team_cymru_asn()]);
+    }
+
+    #[test]
+    fn test_mock_provider_serves_fixtures() {
+        let client = CymruClient::with_provider(mock_provider());
+        let lookups = vec![
+            (CLOUDFLARE_IP, vec![cloudflare()]),
+            (GOOGLE_IP, vec![google()]),
+            (GOOGLE_IPV6, vec![google_ipv6()]),
+            (TEAM_CYMRU_IP, vec![team_cymru()]),
+            (MULTI_ORIGIN_IP, multi_origin()),
+        ];
+        for (ip, expected) in lookups {
+            let mut results = client.ip2asn(ip.parse().unwrap()).unwrap();
+            for result in &mut results {
+                result.expires = expires();
+            }
+            assert_eq!(results, expected, "{}", ip);
+        }
+    }
+}
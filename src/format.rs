@@ -0,0 +1,91 @@
+//! Text output in the layout of Cymru's whois service
+//!
+//! Tools which used to shell out to `whois -h whois.cymru.com` and pass the
+//! output along can switch to the library and keep their output unchanged:
+//! [`whois`](fn.whois.html) matches a plain query and
+//! [`whois_verbose`](fn.whois_verbose.html) a verbose (`-v`) one.
+//!
+//! ```
+//! use cymrust::{fixtures, format};
+//!
+//! let results = vec![fixtures::google()];
+//! assert_eq!(
+//!     format::whois(&results),
+//!     "AS      | IP               | AS Name\n\
+//!      15169   | 8.8.8.8          | GOOGLE, US\n"
+//! );
+//! ```
+
+use std::fmt::Write;
+
+use crate::CymruIP2ASN;
+
+/// Format `results` like a plain whois query
+///
+/// Every line, including the last one, ends in a newline. Values too long
+/// for their column push the rest of the line right, keeping a space before
+/// the next separator, as the service does.
+///
+pub fn whois(results: &[CymruIP2ASN]) -> String {
+    let mut out = String::from("AS      | IP               | AS Name\n");
+    for result in results {
+        let _ = writeln!(
+            out,
+            "{:<7} | {:<16} | {}",
+            result.as_number, result.ip_addr, result.as_name
+        );
+    }
+    out
+}
+
+/// Format `results` like a verbose (`-v`) whois query
+///
+/// Unknown allocation dates are left blank, as the service does.
+///
+pub fn whois_verbose(results: &[CymruIP2ASN]) -> String {
+    let mut out = String::from(
+        "AS      | IP               | BGP Prefix          | CC | Registry | Allocated  | AS Name\n",
+    );
+    for result in results {
+        let _ = writeln!(
+            out,
+            "{:<7} | {:<16} | {:<19} | {:<2} | {:<8} | {:<10} | {}",
+            result.as_number,
+            result.ip_addr,
+            result.bgp_prefix,
+            result.country_code,
+            result.registry,
+            result.allocated.as_deref().unwrap_or(""),
+            result.as_name
+        );
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{whois, whois_verbose};
+    use crate::fixtures;
+
+    #[test]
+    fn test_whois_verbose() {
+        let mut unallocated = fixtures::cloudflare();
+        unallocated.allocated = None;
+        let results = vec![fixtures::google(), fixtures::google_ipv6(), unallocated];
+
+        let lines: Vec<String> = whois_verbose(&results)
+            .lines()
+            .map(ToString::to_string)
+            .collect();
+        assert_eq!(
+            lines,
+            vec![
+                "AS      | IP               | BGP Prefix          | CC | Registry | Allocated  | AS Name",
+                "15169   | 8.8.8.8          | 8.8.8.0/24          | US | arin     | 1992-12-01 | GOOGLE, US",
+                "15169   | 2001:4860:4860::8888 | 2001:4860::/32      | US | arin     | 2005-03-14 | GOOGLE, US",
+                "13335   | 1.1.1.1          | 1.1.1.0/24          | AU | apnic    |            | CLOUDFLARENET, US",
+            ]
+        );
+        assert!(whois(&[]).ends_with("AS Name\n"));
+    }
+}
@@ -0,0 +1,65 @@
+//! Opt-in geolocation enrichment, kept apart from Cymru's data
+//!
+//! Available with the `geo` feature. Team Cymru explicitly warns against
+//! using their mapping as a Geo-IP service: the `country_code` of results is
+//! the country the prefix or AS was registered in, which often is not where
+//! the address is actually used. This module never touches that field.
+//!
+//! Instead, applications plug a geolocation database of their choice, e.g.
+//! a MaxMind GeoLite2 reader, in as a [`GeoLookup`](trait.GeoLookup.html).
+//! Its answers end up in the separate
+//! [`IpInfo::geo`](../struct.IpInfo.html#structfield.geo) field, together
+//! with the name of the database they came from:
+//!
+//! ```
+//! use std::net::IpAddr;
+//! use cymrust::geo::Geo;
+//! use cymrust::{fixtures, CymruClient};
+//!
+//! let client = CymruClient::with_provider(fixtures::mock_provider()).with_geo(|_ip: IpAddr| {
+//!     Some(Geo {
+//!         source: "example-db".to_string(),
+//!         country_code: Some("SE".to_string()),
+//!         ..Geo::default()
+//!     })
+//! });
+//!
+//! let info = client.ip_info(fixtures::CLOUDFLARE_IP.parse().unwrap()).unwrap();
+//! assert_eq!(info.results[0].country_code, "AU"); // registry country
+//! assert_eq!(info.geo.unwrap().country_code.as_deref(), Some("SE")); // location
+//! ```
+
+use std::net::IpAddr;
+
+/// Geolocation of an IP address according to a Geo-IP database
+///
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Geo {
+    /// Database the location came from, e.g. name and build date
+    pub source: String,
+    /// ISO 3166-1 country code of the location
+    pub country_code: Option<String>,
+    /// Region or subdivision name
+    pub region: Option<String>,
+    /// City name
+    pub city: Option<String>,
+}
+
+/// Geo-IP database used for enrichment
+///
+/// Implemented for closures taking an `IpAddr`, so wrapping an existing
+/// database reader takes only a few lines.
+///
+pub trait GeoLookup: Send + Sync {
+    /// Locate `ip`, or return `None` if the database doesn't know it
+    fn locate(&self, ip: IpAddr) -> Option<Geo>;
+}
+
+impl<F> GeoLookup for F
+where
+    F: Fn(IpAddr) -> Option<Geo> + Send + Sync,
+{
+    fn locate(&self, ip: IpAddr) -> Option<Geo> {
+        self(ip)
+    }
+}
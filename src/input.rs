@@ -0,0 +1,175 @@
+//! Reading IP addresses from messy bulk input files
+//!
+//! Address lists exported from logs, firewalls and spreadsheets rarely come
+//! as one clean address per line. [`IpReader`](struct.IpReader.html) copes
+//! with what they tend to contain instead:
+//!
+//! - `\n`, `\r\n` and `\r` line endings, even mixed in one file
+//! - surrounding whitespace, blank lines and `#` comment lines
+//! - ports, as in `192.0.2.1:80` and `[2001:db8::1]:443`
+//! - brackets without a port, as in `[2001:db8::1]`
+//! - IPv6 zone IDs, as in `fe80::1%eth0`
+//! - the same address more than once
+//!
+//! ```
+//! use cymrust::input::IpReader;
+//!
+//! let input = "192.0.2.1:80\r\n[2001:db8::1]:443\rfe80::1%eth0\n192.0.2.1\n";
+//! let ips: Vec<_> = IpReader::new(input.as_bytes())
+//!     .collect::<Result<_, _>>()
+//!     .unwrap();
+//! assert_eq!(ips.len(), 3);
+//! ```
+
+use std::collections::{HashSet, VecDeque};
+use std::io::{self, BufRead};
+use std::net::{IpAddr, SocketAddr};
+
+/// Iterator over IP addresses read from `reader`, one per line
+///
+/// Lines which don't contain an address are skipped and counted, see
+/// [`skipped`](#method.skipped). Duplicate addresses are yielded only once,
+/// unless [`keep_duplicates`](#method.keep_duplicates) is set.
+///
+pub struct IpReader<R> {
+    reader: R,
+    pending: VecDeque<String>,
+    seen: HashSet<IpAddr>,
+    keep_duplicates: bool,
+    skipped: usize,
+}
+
+impl<R: BufRead> IpReader<R> {
+    /// Read addresses from `reader`
+    ///
+    pub fn new(reader: R) -> Self {
+        IpReader {
+            reader,
+            pending: VecDeque::new(),
+            seen: HashSet::new(),
+            keep_duplicates: false,
+            skipped: 0,
+        }
+    }
+
+    /// Yield every occurrence of an address when `keep` is true
+    ///
+    pub fn keep_duplicates(mut self, keep: bool) -> Self {
+        self.keep_duplicates = keep;
+        self
+    }
+
+    /// Number of non-blank, non-comment lines skipped so far for not
+    /// containing an IP address
+    ///
+    pub fn skipped(&self) -> usize {
+        self.skipped
+    }
+
+    /// Next line, splitting `\n` terminated chunks further at `\r`
+    ///
+    fn next_line(&mut self) -> io::Result<Option<String>> {
+        while self.pending.is_empty() {
+            let mut chunk = Vec::new();
+            if self.reader.read_until(b'\n', &mut chunk)? == 0 {
+                return Ok(None);
+            }
+            let chunk = String::from_utf8_lossy(&chunk);
+            self.pending
+                .extend(chunk.split(['\n', '\r']).map(str::to_string));
+        }
+        Ok(self.pending.pop_front())
+    }
+}
+
+impl<R: BufRead> Iterator for IpReader<R> {
+    type Item = io::Result<IpAddr>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            let line = match self.next_line() {
+                Ok(Some(line)) => line,
+                Ok(None) => return None,
+                Err(err) => return Some(Err(err)),
+            };
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            match parse_ip(line) {
+                Some(ip) if self.keep_duplicates || self.seen.insert(ip) => return Some(Ok(ip)),
+                Some(_) => {}
+                None => {
+                    warn!("Skipping input line without an IP address: {:?}", line);
+                    self.skipped += 1;
+                }
+            }
+        }
+    }
+}
+
+/// Parse IP address from `s`, ignoring any port, brackets or zone ID
+///
+/// ```
+/// use cymrust::input::parse_ip;
+///
+/// assert_eq!(parse_ip("[2001:db8::1]:443"), "2001:db8::1".parse().ok());
+/// assert_eq!(parse_ip(" 192.0.2.1:80 "), "192.0.2.1".parse().ok());
+/// assert_eq!(parse_ip("fe80::1%eth0"), "fe80::1".parse().ok());
+/// assert_eq!(parse_ip("example.com"), None);
+/// ```
+///
+pub fn parse_ip(s: &str) -> Option<IpAddr> {
+    let s = s.trim();
+    if let Ok(ip) = s.parse() {
+        return Some(ip);
+    }
+    if let Ok(addr) = s.parse::<SocketAddr>() {
+        return Some(addr.ip());
+    }
+
+    let s = match s.strip_prefix('[') {
+        // Bracketed IPv6 address, maybe with a zone ID or a port
+        Some(rest) => rest.split(']').next()?,
+        None => s,
+    };
+    let s = s.split('%').next()?;
+    s.parse().ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{parse_ip, IpReader};
+
+    #[test]
+    fn test_ip_reader() {
+        let input = "# exported 2021-03-01\n\
+                     192.0.2.1\r\n\
+                     \r\n\
+                     192.0.2.2:8080\r\
+                     [2001:db8::1]\n\
+                     [fe80::1%eth0]:22\n\
+                     not an address\n\
+                     192.0.2.1";
+        let mut reader = IpReader::new(input.as_bytes());
+        let ips: Vec<String> = reader.by_ref().map(|ip| ip.unwrap().to_string()).collect();
+        assert_eq!(
+            ips,
+            vec!["192.0.2.1", "192.0.2.2", "2001:db8::1", "fe80::1"]
+        );
+        assert_eq!(reader.skipped(), 1);
+
+        let all = IpReader::new(input.as_bytes())
+            .keep_duplicates(true)
+            .count();
+        assert_eq!(all, 5);
+    }
+
+    #[test]
+    fn test_parse_ip() {
+        assert_eq!(parse_ip("[::1]"), "::1".parse().ok());
+        assert_eq!(parse_ip("[fe80::1%eth0]"), "fe80::1".parse().ok());
+        assert_eq!(parse_ip("192.0.2.1:x"), None);
+        assert_eq!(parse_ip(""), None);
+    }
+}
@@ -6,18 +6,121 @@
 //! warns not to use their mapping as Geo-IP service.
 //!
 //! For easiest IP-to-ASN mapping, see [`cymru_ip2asn`](fn.cymru_ip2asn.html)
-//! function. To query only information about AS Number, see
-//! [`cymru_asn`](fn.cymru_asn.html).
+//! function, or [`cymru_ip2asn_with`](fn.cymru_ip2asn_with.html) to tune a
+//! single lookup. To query only information about AS Number, see
+//! [`cymru_asn`](fn.cymru_asn.html). To look up an IP address, AS number or
+//! hostname given as a string, e.g. on a command line, see
+//! [`cymru_lookup`](fn.cymru_lookup.html). To see which DNS names a lookup
+//! would query, without querying them, see [`query_plan`](fn.query_plan.html).
+//!
+//! When doing many queries, [`CymruClient`](struct.CymruClient.html) reuses a
+//! single DNS resolver and lets applications hook into its activity through
+//! the [`Metrics`](trait.Metrics.html) trait.
+//!
+//! To turn traceroute hops into an AS path, see the [`path`](path/index.html)
+//! module.
+//!
+//! To compare two sets of results, e.g. from periodic runs, see the
+//! [`diff`](diff/index.html) module.
+//!
+//! To summarize many results per AS number, country and registry, see the
+//! [`report`](report/index.html) module.
+//!
+//! To read addresses for bulk lookups from messy input files, see the
+//! [`input`](input/index.html) module.
+//!
+//! To keep a record of every DNS name queried, e.g. for compliance, see the
+//! [`audit`](audit/index.html) module.
+//!
+//! To print results exactly like `whois -h whois.cymru.com` does, see the
+//! [`format`](format/index.html) module. To write them as JSON Lines, CSV or
+//! a format of your own, see the [`sink`](sink/index.html) module.
+//!
+//! # Optional features
+//!
+//! - `tracing`: emit [tracing](https://docs.rs/tracing) spans for every DNS
+//!   query (query name, backend, duration and outcome) and parse step.
+//! - `otel`: like `tracing`, but also annotate query spans with
+//!   [OpenTelemetry](https://opentelemetry.io/) semantic convention fields
+//!   (`otel.kind`, `otel.status_code`, `rpc.system`, `dns.question.name`) for
+//!   export through `tracing-opentelemetry`.
+//! - `metrics`: report client activity through the
+//!   [metrics](https://docs.rs/metrics) crate facade with
+//!   [`metrics_rs::MetricsRs`](metrics_rs/struct.MetricsRs.html).
+//! - `serde`: `Serialize` and `Deserialize` for result types.
+//! - `msgpack`: compact binary encoding of results with MessagePack, for IPC
+//!   and spilling to disk, see [`msgpack`](msgpack/index.html). Implies
+//!   `serde`.
+//! - `schemars`: JSON Schema of the serialized result types through
+//!   [schemars](https://docs.rs/schemars), e.g.
+//!   `schemars::schema_for!(Vec<CymruIP2ASN>)`. Implies `serde`.
+//! - `ipnet`: look up [ipnet](https://docs.rs/ipnet) networks by their
+//!   network address and merge result prefixes per AS, see
+//!   [`net`](net/index.html).
+//! - `geo`: plug in a Geo-IP database to locate addresses, kept apart from
+//!   Cymru's registry country, see [`geo`](geo/index.html).
+//! - `fixtures`: realistic sample results and raw TXT answers for tests, see
+//!   [`fixtures`](fixtures/index.html).
+//! - `test-server`: tiny in-process DNS server for testing lookups
+//!   end-to-end, see [`test_server`](test_server/index.html).
+//! - `ffi`: C interface for linking from C and C++ tooling, see
+//!   [`ffi`](ffi/index.html).
+//! - `log`: emit [log](https://docs.rs/log) records for failed lookups,
+//!   negative results and malformed or discarded TXT records.
+
+#[macro_use]
+mod trace;
+
+pub mod audit;
+pub mod cassette;
+mod client;
+pub mod diff;
+mod ext;
+pub mod fault;
+#[cfg(feature = "ffi")]
+pub mod ffi;
+#[cfg(feature = "fixtures")]
+pub mod fixtures;
+pub mod format;
+#[cfg(feature = "geo")]
+pub mod geo;
+pub mod input;
+#[cfg(feature = "metrics")]
+pub mod metrics_rs;
+#[cfg(feature = "msgpack")]
+pub mod msgpack;
+#[cfg(feature = "ipnet")]
+pub mod net;
+pub mod path;
+pub mod provider;
+pub mod report;
+pub mod sink;
+pub mod special;
+mod target;
+#[cfg(feature = "test-server")]
+pub mod test_server;
 
-use std::cmp;
+pub use crate::client::{
+    BackendStats, CymruClient, ErrorBudget, Ip2AsnStream, IpInfo, LookupStats, Metrics, QueryStats,
+    RawResponse, ResponseLimits, ResultTtl,
+};
+pub use crate::ext::{AsNumberExt, AsRange, IpAsnExt, Paced, PacedExt};
+pub use crate::target::{ParseTargetError, Target, TargetLookup, TargetResults};
+
+use std::cmp::{Ordering, Reverse};
+use std::collections::BTreeMap;
 use std::fmt;
 use std::io;
 use std::net::{IpAddr, Ipv6Addr};
 use std::time::{Duration, Instant, SystemTime};
 
 use chrono::NaiveDate;
-use trust_dns_resolver::error::ResolveError;
-use trust_dns_resolver::Resolver;
+use trust_dns_resolver::error::{ResolveError, ResolveErrorKind};
+use trust_dns_resolver::proto::op::ResponseCode;
+use unicode_normalization::UnicodeNormalization;
+
+use crate::provider::{DnsProvider, TxtRecords};
+use crate::special::SpecialPurpose;
 
 /// `AsNumber` type to abstract away the fact that AS Number is (currently) 32
 /// bit unsigned integer.
@@ -25,7 +128,12 @@ pub type AsNumber = u32;
 
 /// IP-to-ASN mapping information
 ///
-#[derive(Debug, PartialEq, PartialOrd, Eq, Ord)]
+/// Results are ordered by IP address, then most specific BGP prefix first,
+/// then by AS number. Lookups return them in this order.
+///
+#[derive(Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "schemars", derive(schemars::JsonSchema))]
 pub struct CymruIP2ASN {
     /// IP Address used in query
     pub ip_addr: IpAddr,
@@ -43,11 +151,19 @@ pub struct CymruIP2ASN {
     pub allocated: Option<String>,
     /// When information contained in this struct expires
     pub expires: SystemTime,
+    /// Set when the address is originated by more than one AS
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub anomalous_origins: Option<OriginAnomaly>,
+    /// Where the mapping came from
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub source: Source,
 }
 
 /// ASN information
 ///
-#[derive(Debug, PartialEq, PartialOrd, Eq, Ord)]
+#[derive(Debug, Clone, PartialEq, PartialOrd, Eq, Ord)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "schemars", derive(schemars::JsonSchema))]
 pub struct CymruASN {
     /// BGP Origin's Autonomous System (AS) number
     pub as_number: AsNumber,
@@ -57,22 +173,369 @@ pub struct CymruASN {
     pub registry: String,
     /// BGP prefix allocation date
     pub allocated: Option<NaiveDate>,
-    /// Autonomous System (AS) description
+    /// Autonomous System (AS) description, in Unicode NFC and without
+    /// control characters
     pub as_name: String,
     /// When information contained in this struct expires
     pub expires: SystemTime,
 }
 
-#[derive(Debug, PartialEq, PartialOrd, Eq, Ord)]
-struct CymruOrigin {
+/// Origin information of an IP address, without AS details
+///
+/// Returned by [`CymruClient::origin`](struct.CymruClient.html#method.origin),
+/// which needs only one query instead of one per origin AS.
+///
+/// Origins are ordered most specific BGP prefix first, then by AS number.
+/// Lookups return them in this order.
+///
+#[derive(Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "schemars", derive(schemars::JsonSchema))]
+pub struct CymruOrigin {
+    /// BGP Origin's Autonomous System (AS) number
     pub as_number: AsNumber,
+    /// BGP prefix
     pub bgp_prefix: String,
+    /// Country code
     pub country_code: String,
+    /// Regional registrar name
     pub registry: String,
+    /// BGP prefix allocation date
     pub allocated: Option<NaiveDate>,
+    /// When information contained in this struct expires
     pub expires: SystemTime,
 }
 
+/// Origin information of a BGP prefix, with all of its origin AS numbers
+///
+/// Cymru lists every AS originating a prefix in one TXT record. Unlike
+/// [`CymruOrigin`](struct.CymruOrigin.html), which gets one struct per AS,
+/// this keeps them grouped, as needed for multiple origin AS (MOAS)
+/// analysis. Returned by
+/// [`CymruClient::prefix_origins`](struct.CymruClient.html#method.prefix_origins).
+///
+/// Prefixes are ordered most specific first, then by AS numbers. Lookups
+/// return them in this order.
+///
+#[derive(Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "schemars", derive(schemars::JsonSchema))]
+pub struct CymruPrefixOrigins {
+    /// BGP prefix
+    pub bgp_prefix: String,
+    /// Autonomous System (AS) numbers originating the prefix, in ascending
+    /// order
+    pub as_numbers: Vec<AsNumber>,
+    /// Country code
+    pub country_code: String,
+    /// Regional registrar name
+    pub registry: String,
+    /// BGP prefix allocation date
+    pub allocated: Option<NaiveDate>,
+    /// When information contained in this struct expires
+    pub expires: SystemTime,
+}
+
+/// Signs of an IP address being originated by more than one AS
+///
+/// Anycast and some multihomed networks look like this legitimately, but so
+/// does a hijacked prefix, which makes these results worth a review.
+///
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "schemars", derive(schemars::JsonSchema))]
+pub struct OriginAnomaly {
+    /// Most specific prefix is originated by more than one AS (MOAS)
+    pub moas: bool,
+    /// Overlapping prefixes covering the address have different origins
+    pub conflicting_prefixes: bool,
+    /// Every BGP prefix and origin AS of the address, most specific first
+    pub origins: Vec<(String, AsNumber)>,
+}
+
+/// Results expiring at their `expires` time
+///
+/// `expires` is wall-clock time, so comparing it with
+/// `SystemTime::now()` gives wrong answers whenever the clock is stepped,
+/// e.g. by NTP corrections. Long-running processes should instead convert
+/// it to an `Instant` with [`valid_until`](#method.valid_until) right after
+/// the lookup, and judge freshness by that:
+///
+/// ```
+/// use std::time::Instant;
+/// use cymrust::{fixtures, CymruClient, Expiry};
+///
+/// let client = CymruClient::with_provider(fixtures::mock_provider());
+/// let result = &client.ip2asn(fixtures::GOOGLE_IP.parse().unwrap()).unwrap()[0];
+/// let valid_until = result.valid_until();
+/// assert!(valid_until > Instant::now());
+/// ```
+///
+pub trait Expiry {
+    /// When information contained in this result expires
+    fn expires(&self) -> SystemTime;
+
+    /// Monotonic time until which this result is valid
+    ///
+    /// Measured from the current wall-clock time, so call this once, soon
+    /// after the lookup, and keep the `Instant`. Already expired results
+    /// give the current time.
+    ///
+    fn valid_until(&self) -> Instant {
+        let now = Instant::now();
+        match self.expires().duration_since(SystemTime::now()) {
+            Ok(remaining) => now.checked_add(remaining).unwrap_or(now),
+            Err(_) => now,
+        }
+    }
+}
+
+impl Expiry for CymruIP2ASN {
+    fn expires(&self) -> SystemTime {
+        self.expires
+    }
+}
+
+impl Expiry for CymruASN {
+    fn expires(&self) -> SystemTime {
+        self.expires
+    }
+}
+
+impl Expiry for CymruOrigin {
+    fn expires(&self) -> SystemTime {
+        self.expires
+    }
+}
+
+impl Expiry for CymruPrefixOrigins {
+    fn expires(&self) -> SystemTime {
+        self.expires
+    }
+}
+
+/// Fields shared by results describing a single origin AS
+///
+/// Lets application code work with any of them, e.g. to show results of
+/// [`ip2asn`](fn.cymru_ip2asn.html) and cheaper
+/// [`origin`](struct.CymruClient.html#method.origin) lookups alike. Fields a
+/// result type doesn't have are `None`. The validity window comes from
+/// [`Expiry`](trait.Expiry.html).
+///
+/// ```
+/// use cymrust::{fixtures, AsnInfo};
+///
+/// fn describe(info: &dyn AsnInfo) -> String {
+///     format!("AS{} {}", info.as_number(), info.as_name().unwrap_or("?"))
+/// }
+///
+/// assert_eq!(describe(&fixtures::google()), "AS15169 GOOGLE, US");
+/// assert_eq!(describe(&fixtures::google_asn()), "AS15169 GOOGLE, US");
+/// ```
+///
+pub trait AsnInfo: Expiry {
+    /// Autonomous System (AS) number
+    fn as_number(&self) -> AsNumber;
+    /// BGP prefix, if the result is about one
+    fn bgp_prefix(&self) -> Option<&str>;
+    /// Autonomous System (AS) description, if known
+    fn as_name(&self) -> Option<&str>;
+    /// Country code
+    fn country_code(&self) -> &str;
+    /// Regional registrar name
+    fn registry(&self) -> &str;
+}
+
+impl AsnInfo for CymruIP2ASN {
+    fn as_number(&self) -> AsNumber {
+        self.as_number
+    }
+
+    fn bgp_prefix(&self) -> Option<&str> {
+        Some(&self.bgp_prefix)
+    }
+
+    fn as_name(&self) -> Option<&str> {
+        Some(&self.as_name)
+    }
+
+    fn country_code(&self) -> &str {
+        &self.country_code
+    }
+
+    fn registry(&self) -> &str {
+        &self.registry
+    }
+}
+
+impl AsnInfo for CymruASN {
+    fn as_number(&self) -> AsNumber {
+        self.as_number
+    }
+
+    fn bgp_prefix(&self) -> Option<&str> {
+        None
+    }
+
+    fn as_name(&self) -> Option<&str> {
+        Some(&self.as_name)
+    }
+
+    fn country_code(&self) -> &str {
+        &self.country_code
+    }
+
+    fn registry(&self) -> &str {
+        &self.registry
+    }
+}
+
+impl AsnInfo for CymruOrigin {
+    fn as_number(&self) -> AsNumber {
+        self.as_number
+    }
+
+    fn bgp_prefix(&self) -> Option<&str> {
+        Some(&self.bgp_prefix)
+    }
+
+    fn as_name(&self) -> Option<&str> {
+        None
+    }
+
+    fn country_code(&self) -> &str {
+        &self.country_code
+    }
+
+    fn registry(&self) -> &str {
+        &self.registry
+    }
+}
+
+/// Where an IP-to-ASN mapping came from
+///
+/// Lets consumers and auditors tell results served by Cymru apart from
+/// recorded or mock answers, e.g. after merging result sets. The mapping is
+/// attributed to the [`Provider`](provider/trait.Provider.html) which
+/// answered the origin query, by its name.
+///
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "kebab-case"))]
+#[cfg_attr(feature = "schemars", derive(schemars::JsonSchema))]
+pub enum Source {
+    /// Not known, e.g. for results saved before sources were recorded
+    #[default]
+    Unknown,
+    /// Cymru's DNS service
+    CymruDns,
+    /// Answers recorded earlier, replayed by
+    /// [`ReplayProvider`](cassette/struct.ReplayProvider.html)
+    Replay,
+    /// Canned answers of [`MockProvider`](provider/struct.MockProvider.html)
+    Mock,
+    /// Any other provider, by its name
+    Other(String),
+}
+
+impl Source {
+    /// Source of answers from backend `name`, as named by
+    /// [`Provider::name`](provider/trait.Provider.html#tymethod.name)
+    ///
+    pub fn from_backend(name: &str) -> Source {
+        match name {
+            "cymru-dns" => Source::CymruDns,
+            "replay" => Source::Replay,
+            "mock" => Source::Mock,
+            other => Source::Other(other.to_string()),
+        }
+    }
+}
+
+impl fmt::Display for Source {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Source::Unknown => write!(f, "unknown"),
+            Source::CymruDns => write!(f, "cymru-dns"),
+            Source::Replay => write!(f, "replay"),
+            Source::Mock => write!(f, "mock"),
+            Source::Other(name) => write!(f, "{}", name),
+        }
+    }
+}
+
+impl CymruPrefixOrigins {
+    /// Whether the prefix is originated by more than one AS
+    ///
+    pub fn is_moas(&self) -> bool {
+        self.as_numbers.len() > 1
+    }
+}
+
+impl Ord for CymruIP2ASN {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.ip_addr
+            .cmp(&other.ip_addr)
+            .then_with(|| cmp_prefix(&self.bgp_prefix, &other.bgp_prefix))
+            .then_with(|| self.as_number.cmp(&other.as_number))
+            .then_with(|| self.as_name.cmp(&other.as_name))
+            .then_with(|| self.country_code.cmp(&other.country_code))
+            .then_with(|| self.registry.cmp(&other.registry))
+            .then_with(|| self.allocated.cmp(&other.allocated))
+            .then_with(|| self.expires.cmp(&other.expires))
+            .then_with(|| self.source.cmp(&other.source))
+    }
+}
+
+impl PartialOrd for CymruIP2ASN {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for CymruOrigin {
+    fn cmp(&self, other: &Self) -> Ordering {
+        cmp_prefix(&self.bgp_prefix, &other.bgp_prefix)
+            .then_with(|| self.as_number.cmp(&other.as_number))
+            .then_with(|| self.country_code.cmp(&other.country_code))
+            .then_with(|| self.registry.cmp(&other.registry))
+            .then_with(|| self.allocated.cmp(&other.allocated))
+            .then_with(|| self.expires.cmp(&other.expires))
+    }
+}
+
+impl PartialOrd for CymruOrigin {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for CymruPrefixOrigins {
+    fn cmp(&self, other: &Self) -> Ordering {
+        cmp_prefix(&self.bgp_prefix, &other.bgp_prefix)
+            .then_with(|| self.as_numbers.cmp(&other.as_numbers))
+            .then_with(|| self.country_code.cmp(&other.country_code))
+            .then_with(|| self.registry.cmp(&other.registry))
+            .then_with(|| self.allocated.cmp(&other.allocated))
+            .then_with(|| self.expires.cmp(&other.expires))
+    }
+}
+
+impl PartialOrd for CymruPrefixOrigins {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+/// Order BGP prefixes by length, longest first, then textually
+///
+/// Prefixes without a parseable length sort last.
+///
+fn cmp_prefix(a: &str, b: &str) -> Ordering {
+    let len = |prefix: &str| -> Option<u8> { prefix.rsplit_once('/')?.1.parse().ok() };
+    Reverse(len(a)).cmp(&Reverse(len(b))).then_with(|| a.cmp(b))
+}
+
 /// Query Cymru's IP-to-ASN mapping service using DNS
 ///
 /// This function first queries [Cymru's
@@ -81,46 +544,84 @@ struct CymruOrigin {
 /// does a new query to get ASN information. The returned `CymruIP2ASN` is union
 /// of IP-to-ASN mapping and ASN query information.
 ///
-/// No caching is performed by this function.
+/// There's one result per origin AS. Use
+/// [`CymruClient::with_dedup`](struct.CymruClient.html#method.with_dedup) for
+/// one per BGP prefix, or one per every prefix and AS pair, instead.
+///
+/// AS details are remembered across calls for the TTL of their answers, but
+/// every call sets up a new DNS resolver. Use
+/// [`CymruClient`](struct.CymruClient.html) to reuse one resolver for many
+/// queries.
 ///
 /// # Errors
 ///
-/// If DNS resolver fails or there's error in DNS query, the error is returned
-/// as String
+/// If DNS resolver fails or there's error in DNS query, the returned `Error`
+/// tells which query failed and at which [`Stage`](enum.Stage.html)
 ///
 pub fn cymru_ip2asn(ip: IpAddr) -> Result<Vec<CymruIP2ASN>, Error> {
-    let origins: Vec<CymruOrigin> = cymru_origin(ip)?;
-    let mut results: Vec<CymruIP2ASN> = Vec::with_capacity(origins.len());
-
-    'origins: for origin in origins {
-        for result in &results {
-            if origin.as_number == result.as_number {
-                // Skip AS numbers we already know about
-                continue 'origins;
-            }
-        }
-
-        let asn: Vec<CymruASN> = cymru_asn(origin.as_number)?;
-
-        let result = CymruIP2ASN {
-            ip_addr: ip,
-            bgp_prefix: origin.bgp_prefix,
-            as_number: origin.as_number,
-            as_name: asn[0].as_name.to_string(),
-            country_code: origin.country_code,
-            registry: origin.registry,
-            allocated: origin.allocated.map(|s| s.to_string()),
-            expires: cmp::min(origin.expires, asn[0].expires),
-        };
+    CymruClient::shared()?.ip2asn(ip)
+}
 
-        results.push(result);
-    }
+/// Settings for [`cymru_ip2asn_with`](fn.cymru_ip2asn_with.html)
+///
+/// The defaults look addresses up just like
+/// [`cymru_ip2asn`](fn.cymru_ip2asn.html) does.
+///
+/// ```no_run
+/// use std::time::Duration;
+/// use cymrust::{cymru_ip2asn_with, DedupPolicy, LookupOptions};
+///
+/// let options = LookupOptions {
+///     timeout: Some(Duration::from_secs(2)),
+///     dedup: DedupPolicy::ByPrefix,
+///     ..LookupOptions::default()
+/// };
+/// let results = cymru_ip2asn_with("8.8.8.8".parse().unwrap(), &options).unwrap();
+/// ```
+///
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct LookupOptions {
+    /// Timeout of each DNS query, instead of the system resolver's
+    pub timeout: Option<Duration>,
+    /// Which origins get a result of their own, see
+    /// [`CymruClient::with_dedup`](struct.CymruClient.html#method.with_dedup)
+    pub dedup: DedupPolicy,
+    /// Leave AS names out, saving a query per origin AS, see
+    /// [`CymruClient::with_asn_details`](struct.CymruClient.html#method.with_asn_details)
+    pub skip_asn_details: bool,
+    /// Query the /24 or /48 network of the address instead of the address
+    /// itself, see
+    /// [`CymruClient::with_query_truncation`](struct.CymruClient.html#method.with_query_truncation)
+    pub truncate_queries: bool,
+}
 
-    if results.is_empty() {
-        return Err(Error::NoResultsFound);
+impl LookupOptions {
+    /// Client for a single lookup with these options
+    ///
+    fn client(&self) -> Result<CymruClient, Error> {
+        let provider = match self.timeout {
+            Some(timeout) => DnsProvider::with_timeout(timeout)?,
+            None => DnsProvider::new()?,
+        };
+        Ok(CymruClient::with_provider(provider)
+            .with_shared_asn_memo()
+            .with_dedup(self.dedup)
+            .with_asn_details(!self.skip_asn_details)
+            .with_query_truncation(self.truncate_queries))
     }
+}
 
-    Ok(results)
+/// Query Cymru's IP-to-ASN mapping for IP address, with given `options`
+///
+/// Like [`cymru_ip2asn`](fn.cymru_ip2asn.html), but tunable without setting
+/// up a [`CymruClient`](struct.CymruClient.html).
+///
+/// # Errors
+///
+/// Like [`cymru_ip2asn`](fn.cymru_ip2asn.html).
+///
+pub fn cymru_ip2asn_with(ip: IpAddr, options: &LookupOptions) -> Result<Vec<CymruIP2ASN>, Error> {
+    options.client()?.ip2asn(ip)
 }
 
 /// Resolve information about AS number using DNS
@@ -129,50 +630,121 @@ pub fn cymru_ip2asn(ip: IpAddr) -> Result<Vec<CymruIP2ASN>, Error> {
 /// IP-to-ASN](https://www.team-cymru.org/IP-ASN-mapping.html) service and
 /// returns information Cymru knows about given AS number.
 ///
-/// No caching is performed by this function.
+/// Answers are remembered across calls for their TTL, like with
+/// [`cymru_ip2asn`](fn.cymru_ip2asn.html).
 ///
 /// # Errors
 ///
-/// If DNS resolver fails or there's error in DNS query, the error is returned
-/// as String
+/// If DNS resolver fails or there's error in DNS query, the returned `Error`
+/// tells which query failed and at which [`Stage`](enum.Stage.html)
 ///
 pub fn cymru_asn<I: Into<AsNumber>>(asn: I) -> Result<Vec<CymruASN>, Error> {
-    let query = format!("AS{}.asn.cymru.com.", asn.into());
+    CymruClient::shared()?.asn(asn)
+}
 
-    let (ttl, records) = resolve_txt(&query)?;
-    let now = SystemTime::now();
-    let cache_until: SystemTime = now + ttl;
+/// Query Cymru's IP-to-ASN mapping for many IP addresses
+///
+/// Returns results or error for every address of `ips`, in the same order.
+/// All queries share one DNS resolver and run a few at a time, and repeated
+/// addresses as well as AS numbers originating several of the addresses are
+/// queried only once for the whole batch. This is much faster than calling
+/// [`cymru_ip2asn`](fn.cymru_ip2asn.html) in a loop. When queries start
+/// failing with SERVFAIL, timeouts or rate limiting, fewer of them run at
+/// once until the upstream recovers.
+///
+/// Every distinct address gets its own origin query. See
+/// [`CymruClient::with_network_sharing`](struct.CymruClient.html#method.with_network_sharing)
+/// for sharing answers within networks instead, and its caveats.
+///
+/// # Errors
+///
+/// Fails only if the system's resolver configuration can't be read; lookup
+/// errors are reported per address.
+///
+pub fn cymru_ip2asn_many(ips: &[IpAddr]) -> Result<Vec<Result<Vec<CymruIP2ASN>, Error>>, Error> {
+    Ok(CymruClient::shared()?.ip2asn_many(ips))
+}
 
-    let results = parse_cymru_asn(records, cache_until);
-    if results.is_empty() {
-        return Err(Error::NoResultsFound);
-    }
-    Ok(results)
+/// Query information about many AS numbers concurrently
+///
+/// Each distinct AS number is queried once, by a few threads sharing one DNS
+/// resolver, which is much faster than calling
+/// [`cymru_asn`](fn.cymru_asn.html) in a loop e.g. for describing all origins
+/// found by a sweep. Results and errors are returned per AS number.
+///
+/// # Errors
+///
+/// Fails only if the system's resolver configuration can't be read; lookup
+/// errors are reported per AS number.
+///
+pub fn cymru_asn_bulk<I>(asns: I) -> Result<BTreeMap<AsNumber, Result<Vec<CymruASN>, Error>>, Error>
+where
+    I: IntoIterator<Item = AsNumber>,
+{
+    Ok(CymruClient::shared()?.asn_bulk(asns))
 }
 
-/// Resolve information about IP address
+/// Look up an IP address, AS number or hostname given as a string
 ///
-fn cymru_origin(ip: IpAddr) -> Result<Vec<CymruOrigin>, Error> {
-    let query = match ip {
-        IpAddr::V4(ipv4) => {
-            let o = ipv4.octets();
-            format!("{}.{}.{}.{}.origin.asn.cymru.com.", o[3], o[2], o[1], o[0])
-        }
-        IpAddr::V6(ipv6) => {
-            let nibbles = ipv6_nibbles(ipv6);
-            format!("{}.origin6.asn.cymru.com.", nibbles)
-        }
-    };
+/// `target` is parsed as a [`Target`](enum.Target.html): IP addresses are
+/// mapped like [`cymru_ip2asn`](fn.cymru_ip2asn.html) does, `AS12345` like
+/// [`cymru_asn`](fn.cymru_asn.html), and hostnames are resolved to their
+/// addresses which are then mapped in turn.
+///
+/// Only AS details are remembered across calls, like with
+/// [`cymru_ip2asn`](fn.cymru_ip2asn.html).
+///
+/// # Errors
+///
+/// Returns `Error::InvalidTarget` if `target` can't be parsed, otherwise
+/// like the lookup functions above.
+///
+pub fn cymru_lookup(target: &str) -> Result<TargetResults, Error> {
+    let target: Target = target.parse()?;
+    CymruClient::shared()?.lookup(&target)
+}
 
-    let (ttl, records) = resolve_txt(&query)?;
-    let now = SystemTime::now();
-    let cache_until: SystemTime = now + ttl;
+/// DNS names queried when looking up an IP address
+///
+/// Returned by [`query_plan`](fn.query_plan.html).
+///
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct QueryPlan {
+    /// Origin TXT query, under `origin.asn.cymru.com.` for IPv4 and
+    /// `origin6.asn.cymru.com.` for IPv6 addresses
+    pub origin: String,
+    /// Reverse DNS (PTR) query, sent only by lookups asking for the hostname
+    pub ptr: String,
+}
 
-    let results = parse_cymru_origin(records, cache_until);
-    if results.is_empty() {
-        return Err(Error::NoResultsFound);
+impl QueryPlan {
+    /// ASN TXT query sent for `asn`
+    ///
+    /// Which AS numbers get queried depends on the origin answer, so these
+    /// names can't be listed up front; one is sent for each origin AS.
+    ///
+    pub fn asn(&self, asn: AsNumber) -> String {
+        asn_query(asn)
+    }
+}
+
+/// DNS names that looking up `ip` would query, without querying anything
+///
+/// Useful for auditing what gets sent to Cymru, debugging IPv6 nibble
+/// formatting, or feeding the names to other DNS tooling.
+///
+/// ```
+/// let plan = cymrust::query_plan("192.0.2.1".parse().unwrap());
+/// assert_eq!(plan.origin, "1.2.0.192.origin.asn.cymru.com.");
+/// assert_eq!(plan.ptr, "1.2.0.192.in-addr.arpa.");
+/// assert_eq!(plan.asn(64500), "AS64500.asn.cymru.com.");
+/// ```
+///
+pub fn query_plan(ip: IpAddr) -> QueryPlan {
+    QueryPlan {
+        origin: origin_query(ip),
+        ptr: ptr_query(ip),
     }
-    Ok(results)
 }
 
 /// Parse Cymru's ASN query result string into a struct
@@ -183,32 +755,52 @@ fn cymru_origin(ip: IpAddr) -> Result<Vec<CymruOrigin>, Error> {
 ///
 /// taken from https://www.team-cymru.org/IP-ASN-mapping.html#dns
 ///
-fn parse_cymru_asn(records: Vec<String>, cache_until: SystemTime) -> Vec<CymruASN> {
+/// Malformed records are discarded, or fail the whole answer when
+/// `strictness` is `Strict`.
+///
+fn parse_cymru_asn(
+    records: &TxtRecords,
+    cache_until: SystemTime,
+    strictness: Strictness,
+) -> Result<Vec<CymruASN>, MalformedRecord> {
+    let _span = trace::parse_span("asn", records.len());
     let mut results = Vec::with_capacity(records.len());
 
     for record in records {
-        let fields: Vec<&str> = record.split('|').map(str::trim).collect();
-        let as_number: AsNumber = match fields[0].parse() {
-            Err(_) => continue,
-            Ok(n) => n,
-        };
+        match parse_asn_record(record, cache_until, strictness) {
+            Ok(result) => results.push(result),
+            Err(reason) if strictness == Strictness::Strict => {
+                return Err(MalformedRecord::new(record, reason))
+            }
+            Err(reason) => warn!("Discarding ASN record {:?}: {}", record, reason),
+        }
+    }
 
-        let result = CymruASN {
-            as_number,
-            country_code: fields[1].to_string(),
-            registry: fields[2].to_string(),
-            allocated: parse_date(fields[3]),
-            as_name: fields[4].to_string(),
-            expires: cache_until,
-        };
+    Ok(results)
+}
 
-        results.push(result);
+fn parse_asn_record(
+    record: &str,
+    cache_until: SystemTime,
+    strictness: Strictness,
+) -> Result<CymruASN, &'static str> {
+    let fields = split_fields(record, strictness)?;
+    let as_number: AsNumber = fields[0].parse().map_err(|_| "invalid AS number")?;
+    if strictness == Strictness::Strict {
+        check_country_code(fields[1])?;
     }
 
-    results
+    Ok(CymruASN {
+        as_number,
+        country_code: fields[1].to_string(),
+        registry: fields[2].to_string(),
+        allocated: parse_allocated(fields[3], strictness)?,
+        as_name: normalize_as_name(fields[4]),
+        expires: cache_until,
+    })
 }
 
-/// Parse Cymru's Origin query result string into a structs
+/// Parse Cymru's Origin query result string into per-prefix structs
 ///
 /// Sample DNS TXT response we try to parse:
 ///
@@ -216,57 +808,207 @@ fn parse_cymru_asn(records: Vec<String>, cache_until: SystemTime) -> Vec<CymruAS
 ///
 /// taken from https://www.team-cymru.org/IP-ASN-mapping.html#dns
 ///
-fn parse_cymru_origin(records: Vec<String>, cache_until: SystemTime) -> Vec<CymruOrigin> {
+/// Leniently, invalid AS numbers are skipped and records without any valid
+/// AS number are discarded. Strictly, any malformed record fails the whole
+/// answer.
+///
+fn parse_cymru_prefix_origins(
+    records: &TxtRecords,
+    cache_until: SystemTime,
+    strictness: Strictness,
+) -> Result<Vec<CymruPrefixOrigins>, MalformedRecord> {
+    let _span = trace::parse_span("origin", records.len());
     let mut results = Vec::with_capacity(records.len());
 
     for record in records {
-        let fields: Vec<&str> = record.split('|').map(str::trim).collect();
+        match parse_origin_record(record, cache_until, strictness) {
+            Ok(result) => results.push(result),
+            Err(reason) if strictness == Strictness::Strict => {
+                return Err(MalformedRecord::new(record, reason))
+            }
+            Err(reason) => warn!("Discarding origin record {:?}: {}", record, reason),
+        }
+    }
 
-        let as_numbers: Vec<&str> = fields[0].split(' ').map(str::trim).collect();
+    results.sort();
+    Ok(results)
+}
 
-        for asn in as_numbers {
-            let as_number: AsNumber = match asn.parse() {
-                Err(_) => continue,
-                Ok(n) => n,
-            };
+fn parse_origin_record(
+    record: &str,
+    cache_until: SystemTime,
+    strictness: Strictness,
+) -> Result<CymruPrefixOrigins, &'static str> {
+    let fields = split_fields(record, strictness)?;
 
-            let result = CymruOrigin {
-                as_number,
-                bgp_prefix: fields[1].to_string(),
-                country_code: fields[2].to_string(),
-                registry: fields[3].to_string(),
-                allocated: parse_date(fields[4]),
-                expires: cache_until,
-            };
-            results.push(result);
+    let mut as_numbers = Vec::new();
+    for asn in fields[0].split_whitespace() {
+        match asn.parse() {
+            Ok(as_number) => as_numbers.push(as_number),
+            Err(_) if strictness == Strictness::Strict => return Err("invalid AS number"),
+            Err(_) => warn!("Skipping invalid AS number {:?} in {:?}", asn, record),
         }
     }
+    if as_numbers.is_empty() {
+        return Err("no valid AS number");
+    }
+    as_numbers.sort_unstable();
+    as_numbers.dedup();
+    if strictness == Strictness::Strict {
+        check_prefix(fields[1])?;
+        check_country_code(fields[2])?;
+    }
+
+    Ok(CymruPrefixOrigins {
+        bgp_prefix: fields[1].to_string(),
+        as_numbers,
+        country_code: fields[2].to_string(),
+        registry: fields[3].to_string(),
+        allocated: parse_allocated(fields[4], strictness)?,
+        expires: cache_until,
+    })
+}
 
+/// Split per-prefix origins into one `CymruOrigin` per origin AS
+///
+fn flatten_origins(prefixes: Vec<CymruPrefixOrigins>) -> Vec<CymruOrigin> {
+    let mut results = Vec::with_capacity(prefixes.len());
+    for prefix in prefixes {
+        for &as_number in &prefix.as_numbers {
+            results.push(CymruOrigin {
+                as_number,
+                bgp_prefix: prefix.bgp_prefix.clone(),
+                country_code: prefix.country_code.clone(),
+                registry: prefix.registry.clone(),
+                allocated: prefix.allocated,
+                expires: prefix.expires,
+            });
+        }
+    }
+    results.sort();
     results
 }
 
-/// Resolve TXT record
+/// Check `origins` of a single address for more than one origin AS
 ///
-/// This is used to talk with Cymru. We expect them to provide us with ASCII
-/// strings which is safe to decode into UTF-8 Strings. TXT records which are
-/// not valid UTF-8 are silently discarded.
+/// `origins` must be ordered most specific prefix first, as
+/// `flatten_origins` returns them.
 ///
-fn resolve_txt(name: &str) -> Result<(Duration, Vec<String>), Error> {
-    let mut txts: Vec<String> = Vec::new();
-    let resolver = Resolver::from_system_conf()?;
-    let response = resolver.txt_lookup(name)?;
-    let valid_until = response.valid_until();
-    let ttl = valid_until - Instant::now();
+fn origin_anomaly(origins: &[CymruOrigin]) -> Option<OriginAnomaly> {
+    let most_specific = &origins.first()?.bgp_prefix;
+    let moas = origins
+        .iter()
+        .filter(|origin| origin.bgp_prefix == *most_specific)
+        .count()
+        > 1;
+    let conflicting_prefixes = origins.iter().any(|a| {
+        origins
+            .iter()
+            .any(|b| a.bgp_prefix != b.bgp_prefix && a.as_number != b.as_number)
+    });
+    if !moas && !conflicting_prefixes {
+        return None;
+    }
 
-    for record in response.iter() {
-        for data in record.txt_data() {
-            if let Ok(txt) = std::str::from_utf8(data) {
-                txts.push(txt.to_owned());
-            }
+    Some(OriginAnomaly {
+        moas,
+        conflicting_prefixes,
+        origins: origins
+            .iter()
+            .map(|origin| (origin.bgp_prefix.clone(), origin.as_number))
+            .collect(),
+    })
+}
+
+/// Split TXT record into its first five `|` separated, trimmed fields
+///
+/// Fails if the record has fewer than five fields. Any further fields are
+/// ignored, unless `strictness` is `Strict`.
+///
+fn split_fields(record: &str, strictness: Strictness) -> Result<[&str; 5], &'static str> {
+    let mut fields = record.split('|').map(str::trim);
+    let mut next = || fields.next().ok_or("too few fields");
+    let split = [next()?, next()?, next()?, next()?, next()?];
+    if strictness == Strictness::Strict && fields.next().is_some() {
+        return Err("too many fields");
+    }
+    Ok(split)
+}
+
+/// Parse allocation date, which Cymru leaves empty when it's unknown
+///
+/// Unparseable dates count as unknown, unless `strictness` is `Strict`.
+///
+fn parse_allocated(date: &str, strictness: Strictness) -> Result<Option<NaiveDate>, &'static str> {
+    match parse_date(date) {
+        None if strictness == Strictness::Strict && !date.is_empty() => {
+            Err("invalid allocation date")
         }
+        parsed => Ok(parsed),
     }
+}
 
-    Ok((ttl, txts))
+/// Check that `country_code` is empty or two ASCII capital letters
+///
+fn check_country_code(country_code: &str) -> Result<(), &'static str> {
+    if country_code.is_empty()
+        || (country_code.len() == 2 && country_code.bytes().all(|b| b.is_ascii_uppercase()))
+    {
+        Ok(())
+    } else {
+        Err("invalid country code")
+    }
+}
+
+/// Check that `prefix` is an IP network in CIDR notation
+///
+fn check_prefix(prefix: &str) -> Result<(), &'static str> {
+    let valid = match prefix.split_once('/') {
+        Some((addr, len)) => match (addr.parse::<IpAddr>(), len.parse::<u8>()) {
+            (Ok(IpAddr::V4(_)), Ok(len)) => len <= 32,
+            (Ok(IpAddr::V6(_)), Ok(len)) => len <= 128,
+            _ => false,
+        },
+        None => false,
+    };
+    if valid {
+        Ok(())
+    } else {
+        Err("invalid BGP prefix")
+    }
+}
+
+/// DNS name for querying origin information about IP address
+///
+fn origin_query(ip: IpAddr) -> String {
+    match ip {
+        IpAddr::V4(ipv4) => {
+            let o = ipv4.octets();
+            format!("{}.{}.{}.{}.origin.asn.cymru.com.", o[3], o[2], o[1], o[0])
+        }
+        IpAddr::V6(ipv6) => {
+            let nibbles = ipv6_nibbles(ipv6);
+            format!("{}.origin6.asn.cymru.com.", nibbles)
+        }
+    }
+}
+
+/// DNS name for reverse DNS query of IP address
+///
+fn ptr_query(ip: IpAddr) -> String {
+    match ip {
+        IpAddr::V4(ipv4) => {
+            let o = ipv4.octets();
+            format!("{}.{}.{}.{}.in-addr.arpa.", o[3], o[2], o[1], o[0])
+        }
+        IpAddr::V6(ipv6) => format!("{}.ip6.arpa.", ipv6_nibbles(ipv6)),
+    }
+}
+
+/// DNS name for querying information about AS number
+///
+fn asn_query(asn: AsNumber) -> String {
+    format!("AS{}.asn.cymru.com.", asn)
 }
 
 /// Convert IPv6 address into nibble format string
@@ -283,30 +1025,396 @@ fn ipv6_nibbles(ip: Ipv6Addr) -> String {
     result.join(".")
 }
 
+/// Normalize AS name to NFC and strip control characters
+///
+/// AS names end up in logs and dashboards as they are, so line breaks,
+/// escape sequences and the like must not get through.
+///
+fn normalize_as_name(name: &str) -> String {
+    name.nfc().filter(|c| !c.is_control()).collect()
+}
+
+/// Escape all but printable ASCII characters of `name` as `\u{...}`
+///
+fn escape_as_name(name: &str) -> String {
+    let mut escaped = String::with_capacity(name.len());
+    for c in name.chars() {
+        if c == ' ' || c.is_ascii_graphic() {
+            escaped.push(c);
+        } else {
+            escaped.extend(c.escape_unicode());
+        }
+    }
+    escaped
+}
+
 /// Parse date in YYYY-MM-DD format ignoring timezones
 ///
 fn parse_date(date: &str) -> Option<NaiveDate> {
     NaiveDate::parse_from_str(date, "%Y-%m-%d").ok()
 }
 
+/// Which step of a lookup a query belongs to
+///
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Stage {
+    /// IP-to-ASN origin query (`origin.asn.cymru.com` or
+    /// `origin6.asn.cymru.com`)
+    Origin,
+    /// AS number details query (`asn.cymru.com`)
+    Asn,
+    /// Reverse DNS query of the looked up address (`in-addr.arpa` or
+    /// `ip6.arpa`)
+    Ptr,
+    /// Address query of a hostname given as lookup target
+    Host,
+}
+
+impl Stage {
+    /// DNS record type queried at this stage
+    ///
+    pub(crate) fn record_type(self) -> &'static str {
+        match self {
+            Stage::Origin | Stage::Asn => "TXT",
+            Stage::Ptr => "PTR",
+            Stage::Host => "A/AAAA",
+        }
+    }
+}
+
+impl fmt::Display for Stage {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Stage::Origin => write!(f, "origin"),
+            Stage::Asn => write!(f, "ASN details"),
+            Stage::Ptr => write!(f, "reverse DNS"),
+            Stage::Host => write!(f, "hostname"),
+        }
+    }
+}
+
+/// How forgiving parsing of Cymru's TXT records is
+///
+/// Set per client with
+/// [`CymruClient::with_strictness`](struct.CymruClient.html#method.with_strictness).
+///
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Strictness {
+    /// Salvage what can be parsed and discard the rest (the default)
+    #[default]
+    Lenient,
+    /// Fail with `Error::Parse` on any record with unexpected fields or
+    /// invalid values
+    Strict,
+}
+
+/// Which origins of an address get a `CymruIP2ASN` result of their own
+///
+/// An address covered by several BGP prefixes, or by a prefix with more than
+/// one origin AS, has several origins. Origins are considered most specific
+/// prefix first, then by AS number, and each one is kept unless it
+/// duplicates one kept before. Set per client with
+/// [`CymruClient::with_dedup`](struct.CymruClient.html#method.with_dedup).
+///
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum DedupPolicy {
+    /// One result per origin AS, with its most specific prefix (the default)
+    #[default]
+    ByAsn,
+    /// One result per BGP prefix, with its lowest origin AS number
+    ByPrefix,
+    /// One result per prefix and origin AS pair
+    None,
+}
+
+impl DedupPolicy {
+    /// Whether `origin` duplicates one of the `results` kept already
+    ///
+    fn is_duplicate(self, results: &[CymruIP2ASN], origin: &CymruOrigin) -> bool {
+        results.iter().any(|result| match self {
+            DedupPolicy::ByAsn => result.as_number == origin.as_number,
+            DedupPolicy::ByPrefix => result.bgp_prefix == origin.bgp_prefix,
+            DedupPolicy::None => {
+                result.as_number == origin.as_number && result.bgp_prefix == origin.bgp_prefix
+            }
+        })
+    }
+}
+
+/// Record failing strict parsing, and why
+///
+#[derive(Debug)]
+struct MalformedRecord {
+    record: String,
+    reason: &'static str,
+}
+
+impl MalformedRecord {
+    /// Copy of malformed `record`, which failed parsing for `reason`
+    ///
+    fn new(record: &str, reason: &'static str) -> Self {
+        MalformedRecord {
+            record: record.to_string(),
+            reason,
+        }
+    }
+
+    /// Error for malformed record in the answer to `query`
+    ///
+    fn into_error(self, query: &str, stage: Stage) -> Error {
+        Error::Parse {
+            query: query.to_string(),
+            stage,
+            record: self.record,
+            reason: self.reason,
+        }
+    }
+}
+
+/// Suggested pause after upstream refuses queries
+///
+const RATE_LIMIT_BACKOFF: Duration = Duration::from_secs(30);
+
 #[derive(Debug)]
 pub enum Error {
     /// Query found no results
-    NoResultsFound,
+    NoResultsFound {
+        /// DNS name queried
+        query: String,
+        /// Lookup step the query belongs to
+        stage: Stage,
+    },
+
+    /// Query would have needed network I/O, which offline client refuses
+    Offline {
+        /// DNS name queried
+        query: String,
+        /// Lookup step the query belongs to
+        stage: Stage,
+    },
+
+    /// Address is in a special-purpose block which is never routed, so it
+    /// was not queried at all
+    NonRoutableInput {
+        /// Address looked up
+        ip: IpAddr,
+        /// Special-purpose block the address belongs to
+        purpose: SpecialPurpose,
+    },
+
+    /// Lookup target is not an IP address, AS number, network or hostname
+    InvalidTarget(ParseTargetError),
+
+    /// Bulk lookup was aborted before this input was looked up, because the
+    /// job exceeded its [`ErrorBudget`](struct.ErrorBudget.html)
+    BudgetExhausted {
+        /// Lookups done by the job when this input was skipped
+        lookups: usize,
+        /// Failed lookups among them
+        errors: usize,
+    },
 
     /// I/O error
     Io(io::Error),
 
+    /// Upstream refused the query, most likely because of rate limiting
+    RateLimited {
+        /// DNS name queried
+        query: String,
+        /// Lookup step the query belongs to
+        stage: Stage,
+        /// Suggested time to pause before sending more queries
+        backoff: Duration,
+        /// Error returned by the resolver
+        source: Box<ResolveError>,
+    },
+
+    /// Answer contained a malformed record, and the client parses strictly
+    Parse {
+        /// DNS name queried
+        query: String,
+        /// Lookup step the query belongs to
+        stage: Stage,
+        /// Malformed TXT record
+        record: String,
+        /// What is wrong with `record`
+        reason: &'static str,
+    },
+
+    /// Answer exceeded one of the client's
+    /// [`ResponseLimits`](struct.ResponseLimits.html) and was rejected
+    ResponseTooLarge {
+        /// DNS name queried
+        query: String,
+        /// Lookup step the query belongs to
+        stage: Stage,
+        /// Which limit was exceeded
+        limit: &'static str,
+        /// Size of the answer in the units of `limit`
+        size: usize,
+        /// Configured maximum
+        max: usize,
+    },
+
     /// DNS Resolver error
-    Resolver(ResolveError),
+    Resolver {
+        /// DNS name queried
+        query: String,
+        /// Lookup step the query belongs to
+        stage: Stage,
+        /// Error returned by the resolver
+        source: Box<ResolveError>,
+    },
+}
+
+impl Error {
+    /// DNS name of the query that failed, if error was caused by a query
+    ///
+    pub fn query(&self) -> Option<&str> {
+        match self {
+            Error::NoResultsFound { query, .. }
+            | Error::Offline { query, .. }
+            | Error::RateLimited { query, .. }
+            | Error::Parse { query, .. }
+            | Error::ResponseTooLarge { query, .. }
+            | Error::Resolver { query, .. } => Some(query),
+            Error::NonRoutableInput { .. }
+            | Error::InvalidTarget(_)
+            | Error::BudgetExhausted { .. }
+            | Error::Io(_) => None,
+        }
+    }
+
+    /// Lookup step which failed, if error was caused by a query
+    ///
+    pub fn stage(&self) -> Option<Stage> {
+        match self {
+            Error::NoResultsFound { stage, .. }
+            | Error::Offline { stage, .. }
+            | Error::RateLimited { stage, .. }
+            | Error::Parse { stage, .. }
+            | Error::ResponseTooLarge { stage, .. }
+            | Error::Resolver { stage, .. } => Some(*stage),
+            Error::NonRoutableInput { .. }
+            | Error::InvalidTarget(_)
+            | Error::BudgetExhausted { .. }
+            | Error::Io(_) => None,
+        }
+    }
+
+    /// Error for a failed `query`, classifying refusals as rate limiting
+    ///
+    pub(crate) fn resolver(query: &str, stage: Stage, err: ResolveError) -> Error {
+        let refused = matches!(
+            err.kind(),
+            ResolveErrorKind::NoRecordsFound {
+                response_code: ResponseCode::Refused,
+                ..
+            }
+        );
+        if refused {
+            Error::RateLimited {
+                query: query.to_string(),
+                stage,
+                backoff: RATE_LIMIT_BACKOFF,
+                source: Box::new(err),
+            }
+        } else {
+            Error::Resolver {
+                query: query.to_string(),
+                stage,
+                source: Box::new(err),
+            }
+        }
+    }
+
+    /// Copy of the error for reporting it more than once
+    ///
+    /// I/O errors can't be cloned, so their copies only keep the kind and
+    /// message.
+    ///
+    pub(crate) fn duplicate(&self) -> Error {
+        match self {
+            Error::NoResultsFound { query, stage } => Error::NoResultsFound {
+                query: query.clone(),
+                stage: *stage,
+            },
+            Error::Offline { query, stage } => Error::Offline {
+                query: query.clone(),
+                stage: *stage,
+            },
+            Error::NonRoutableInput { ip, purpose } => Error::NonRoutableInput {
+                ip: *ip,
+                purpose: *purpose,
+            },
+            Error::InvalidTarget(err) => Error::InvalidTarget(err.clone()),
+            Error::BudgetExhausted { lookups, errors } => Error::BudgetExhausted {
+                lookups: *lookups,
+                errors: *errors,
+            },
+            Error::Io(err) => Error::Io(io::Error::new(err.kind(), err.to_string())),
+            Error::RateLimited {
+                query,
+                stage,
+                backoff,
+                source,
+            } => Error::RateLimited {
+                query: query.clone(),
+                stage: *stage,
+                backoff: *backoff,
+                source: source.clone(),
+            },
+            Error::Parse {
+                query,
+                stage,
+                record,
+                reason,
+            } => Error::Parse {
+                query: query.clone(),
+                stage: *stage,
+                record: record.clone(),
+                reason,
+            },
+            Error::ResponseTooLarge {
+                query,
+                stage,
+                limit,
+                size,
+                max,
+            } => Error::ResponseTooLarge {
+                query: query.clone(),
+                stage: *stage,
+                limit,
+                size: *size,
+                max: *max,
+            },
+            Error::Resolver {
+                query,
+                stage,
+                source,
+            } => Error::Resolver {
+                query: query.clone(),
+                stage: *stage,
+                source: source.clone(),
+            },
+        }
+    }
 }
 
 impl std::error::Error for Error {
     fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
         match self {
-            Error::NoResultsFound => None,
+            Error::NoResultsFound { .. }
+            | Error::Offline { .. }
+            | Error::NonRoutableInput { .. }
+            | Error::BudgetExhausted { .. }
+            | Error::Parse { .. }
+            | Error::ResponseTooLarge { .. } => None,
+            Error::InvalidTarget(err) => Some(err),
             Error::Io(err) => Some(err),
-            Error::Resolver(err) => Some(err),
+            Error::RateLimited { source, .. } | Error::Resolver { source, .. } => {
+                Some(source.as_ref())
+            }
         }
     }
 }
@@ -314,22 +1422,79 @@ impl std::error::Error for Error {
 impl fmt::Display for Error {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
-            Error::NoResultsFound => write!(f, "Query found no results"),
+            Error::NoResultsFound { query, stage } => {
+                write!(f, "Query found no results: {} ({} query)", query, stage)
+            }
+            Error::Offline { query, stage } => {
+                write!(
+                    f,
+                    "Refusing network query in offline mode: {} ({} query)",
+                    query, stage
+                )
+            }
+            Error::NonRoutableInput { ip, purpose } => write!(
+                f,
+                "Not querying non-routable address {} ({}, {})",
+                ip, purpose.name, purpose.rfc
+            ),
+            Error::InvalidTarget(err) => err.fmt(f),
+            Error::BudgetExhausted { lookups, errors } => write!(
+                f,
+                "Bulk lookup aborted after {} failures in {} lookups",
+                errors, lookups
+            ),
             Error::Io(err) => err.fmt(f),
-            Error::Resolver(err) => err.fmt(f),
+            Error::RateLimited {
+                query,
+                stage,
+                backoff,
+                ..
+            } => write!(
+                f,
+                "Query refused, likely rate limited (retry after {}s): {} ({} query)",
+                backoff.as_secs(),
+                query,
+                stage
+            ),
+            Error::Parse {
+                query,
+                stage,
+                record,
+                reason,
+            } => write!(
+                f,
+                "Malformed record {:?}, {}: {} ({} query)",
+                record, reason, query, stage
+            ),
+            Error::ResponseTooLarge {
+                query,
+                stage,
+                limit,
+                size,
+                max,
+            } => write!(
+                f,
+                "Answer exceeds limit on {} ({} > {}): {} ({} query)",
+                limit, size, max, query, stage
+            ),
+            Error::Resolver {
+                query,
+                stage,
+                source,
+            } => write!(f, "{}: {} ({} query)", source, query, stage),
         }
     }
 }
 
-impl From<io::Error> for Error {
-    fn from(err: io::Error) -> Self {
-        Error::Io(err)
+impl From<ParseTargetError> for Error {
+    fn from(err: ParseTargetError) -> Self {
+        Error::InvalidTarget(err)
     }
 }
 
-impl From<ResolveError> for Error {
-    fn from(err: ResolveError) -> Self {
-        Error::Resolver(err)
+impl From<io::Error> for Error {
+    fn from(err: io::Error) -> Self {
+        Error::Io(err)
     }
 }
 
@@ -337,6 +1502,22 @@ impl From<ResolveError> for Error {
 mod tests {
     use std::time::SystemTime;
 
+    use super::{Strictness, TxtRecords};
+
+    #[test]
+    fn test_query_plan() {
+        use super::query_plan;
+        let plan = query_plan("2001:db8::1".parse().unwrap());
+        assert_eq!(
+            plan.origin,
+            "1.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.8.b.d.0.1.0.0.2.origin6.asn.cymru.com."
+        );
+        assert_eq!(
+            plan.ptr,
+            "1.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.8.b.d.0.1.0.0.2.ip6.arpa."
+        );
+    }
+
     #[test]
     fn test_ipv6_nibbles() {
         use super::ipv6_nibbles;
@@ -349,9 +1530,11 @@ mod tests {
     #[test]
     fn test_parse_cymru_asn() {
         use super::{parse_cymru_asn, parse_date, CymruASN};
-        let vec = vec!["23028 | US | arin | 2002-01-04 | TEAMCYMRU - SAUNET".to_string()];
+        let records: TxtRecords = ["23028 | US | arin | 2002-01-04 | TEAMCYMRU - SAUNET"]
+            .iter()
+            .collect();
         let ttl = SystemTime::now();
-        let results: Vec<CymruASN> = parse_cymru_asn(vec, ttl);
+        let results: Vec<CymruASN> = parse_cymru_asn(&records, ttl, Strictness::Lenient).unwrap();
         assert_eq!(results.len(), 1);
         let first = results.first().unwrap();
         assert_eq!(first.as_number, 23028);
@@ -365,16 +1548,30 @@ mod tests {
     fn test_parse_cymru_asn_empty() {
         use super::{parse_cymru_asn, CymruASN};
         let ttl = SystemTime::now();
-        let results: Vec<CymruASN> = parse_cymru_asn(vec!["".to_string()], ttl);
+        let results: Vec<CymruASN> =
+            parse_cymru_asn(&[""].iter().collect(), ttl, Strictness::Lenient).unwrap();
+        assert_eq!(results.len(), 0);
+    }
+
+    #[test]
+    fn test_parse_cymru_asn_malformed() {
+        use super::{parse_cymru_asn, CymruASN};
+        let records: TxtRecords = ["23028 | US", "AS23028 | US | arin | | X"].iter().collect();
+        let ttl = SystemTime::now();
+        let results: Vec<CymruASN> = parse_cymru_asn(&records, ttl, Strictness::Lenient).unwrap();
         assert_eq!(results.len(), 0);
     }
 
     #[test]
     fn test_parse_cymru_origin() {
-        use super::{parse_cymru_origin, parse_date, CymruOrigin};
-        let vec = vec!["23028 | 216.90.108.0/24 | US | arin | 1998-09-25".to_string()];
+        use super::{flatten_origins, parse_cymru_prefix_origins, parse_date, CymruOrigin};
+        let records: TxtRecords = ["23028 | 216.90.108.0/24 | US | arin | 1998-09-25"]
+            .iter()
+            .collect();
         let ttl = SystemTime::now();
-        let results: Vec<CymruOrigin> = parse_cymru_origin(vec, ttl);
+        let results: Vec<CymruOrigin> = flatten_origins(
+            parse_cymru_prefix_origins(&records, ttl, Strictness::Lenient).unwrap(),
+        );
         assert_eq!(results.len(), 1);
         let first = results.first().unwrap();
         assert_eq!(first.as_number, 23028);
@@ -386,18 +1583,35 @@ mod tests {
 
     #[test]
     fn test_parse_cymru_origin_empty() {
-        use super::{parse_cymru_origin, CymruOrigin};
+        use super::{flatten_origins, parse_cymru_prefix_origins, CymruOrigin};
         let ttl = SystemTime::now();
-        let results: Vec<CymruOrigin> = parse_cymru_origin(vec!["".to_string()], ttl);
+        let results: Vec<CymruOrigin> = flatten_origins(
+            parse_cymru_prefix_origins(&[""].iter().collect(), ttl, Strictness::Lenient).unwrap(),
+        );
+        assert_eq!(results.len(), 0);
+    }
+
+    #[test]
+    fn test_parse_cymru_origin_malformed() {
+        use super::{flatten_origins, parse_cymru_prefix_origins, CymruOrigin};
+        let records: TxtRecords = ["23028 | 216.90.108.0/24"].iter().collect();
+        let ttl = SystemTime::now();
+        let results: Vec<CymruOrigin> = flatten_origins(
+            parse_cymru_prefix_origins(&records, ttl, Strictness::Lenient).unwrap(),
+        );
         assert_eq!(results.len(), 0);
     }
 
     #[test]
     fn test_parse_cymru_origin_multiple_asn() {
-        use super::{parse_cymru_origin, parse_date, CymruOrigin};
-        let vec = vec!["1 23 456 7890 | 203.0.113.0/24 | GB | ripencc | 2006-02-17".to_string()];
+        use super::{flatten_origins, parse_cymru_prefix_origins, parse_date, CymruOrigin};
+        let records: TxtRecords = ["1 23 456 7890 | 203.0.113.0/24 | GB | ripencc | 2006-02-17"]
+            .iter()
+            .collect();
         let ttl = SystemTime::now();
-        let results: Vec<CymruOrigin> = parse_cymru_origin(vec, ttl);
+        let results: Vec<CymruOrigin> = flatten_origins(
+            parse_cymru_prefix_origins(&records, ttl, Strictness::Lenient).unwrap(),
+        );
         assert_eq!(results.len(), 4);
         let asns = [1, 23, 456, 7890];
         for item in 0..3 {
@@ -408,4 +1622,187 @@ mod tests {
             assert_eq!(results[item].allocated, parse_date("2006-02-17"));
         }
     }
+
+    #[test]
+    fn test_parse_cymru_origin_order() {
+        use super::{flatten_origins, parse_cymru_prefix_origins};
+        let records = |order: &[usize]| -> TxtRecords {
+            let all = [
+                "64500 | 198.51.0.0/16 | US | arin | 2010-01-01",
+                "64497 64496 | 198.51.100.0/24 | US | arin | 2010-01-01",
+                "64499 | 198.51.100.0/22 | US | arin | 2010-01-01",
+            ];
+            order.iter().map(|&i| all[i]).collect()
+        };
+        let ttl = SystemTime::now();
+
+        let results = flatten_origins(
+            parse_cymru_prefix_origins(&records(&[0, 1, 2]), ttl, Strictness::Lenient).unwrap(),
+        );
+        let order: Vec<(&str, u32)> = results
+            .iter()
+            .map(|r| (r.bgp_prefix.as_str(), r.as_number))
+            .collect();
+        assert_eq!(
+            order,
+            vec![
+                ("198.51.100.0/24", 64496),
+                ("198.51.100.0/24", 64497),
+                ("198.51.100.0/22", 64499),
+                ("198.51.0.0/16", 64500),
+            ]
+        );
+        let shuffled = flatten_origins(
+            parse_cymru_prefix_origins(&records(&[2, 0, 1]), ttl, Strictness::Lenient).unwrap(),
+        );
+        assert_eq!(results, shuffled);
+    }
+
+    #[test]
+    fn test_parse_cymru_prefix_origins() {
+        use super::parse_cymru_prefix_origins;
+        let records: TxtRecords = [
+            "64496 64497 | 198.51.100.0/24 | US | arin | 2010-01-01",
+            "64500 | 198.51.0.0/16 | US | arin | ",
+            "x | 203.0.113.0/24 | GB | ripencc | 2006-02-17",
+        ]
+        .iter()
+        .collect();
+        let ttl = SystemTime::now();
+        let results = parse_cymru_prefix_origins(&records, ttl, Strictness::Lenient).unwrap();
+        assert_eq!(results.len(), 2);
+        assert_eq!(results[0].as_numbers, vec![64496, 64497]);
+        assert!(results[0].is_moas());
+        assert_eq!(results[1].bgp_prefix, "198.51.0.0/16");
+        assert!(!results[1].is_moas());
+    }
+
+    #[test]
+    fn test_as_name_normalization() {
+        use super::{escape_as_name, normalize_as_name, parse_cymru_asn};
+        let records: TxtRecords =
+            ["64500 | FI | ripencc | 2010-01-01 | Sa\u{308}a\u{7}\u{1b}[2J Oy, FI"]
+                .iter()
+                .collect();
+        let results = parse_cymru_asn(&records, SystemTime::now(), Strictness::Lenient).unwrap();
+        assert_eq!(results[0].as_name, "S\u{e4}a[2J Oy, FI");
+
+        assert_eq!(normalize_as_name("line\nbreak"), "linebreak");
+        assert_eq!(escape_as_name("S\u{e4}\u{202e}a"), "S\\u{e4}\\u{202e}a");
+    }
+
+    #[test]
+    fn test_source() {
+        use super::Source;
+
+        for name in &["cymru-dns", "replay", "mock", "fault-injector"] {
+            assert_eq!(Source::from_backend(name).to_string(), *name);
+        }
+        assert_eq!(
+            Source::from_backend("fault-injector"),
+            Source::Other("fault-injector".to_string())
+        );
+        assert_eq!(Source::default().to_string(), "unknown");
+    }
+
+    #[test]
+    fn test_valid_until() {
+        use super::{CymruASN, Expiry};
+        use std::time::{Duration, Instant};
+
+        let asn = |expires| CymruASN {
+            as_number: 64500,
+            country_code: "US".to_string(),
+            registry: "arin".to_string(),
+            allocated: None,
+            as_name: "EXAMPLE".to_string(),
+            expires,
+        };
+        let before = Instant::now();
+        let valid_until = asn(SystemTime::now() + Duration::from_secs(60)).valid_until();
+        assert!(valid_until > before + Duration::from_secs(59));
+        assert!(valid_until <= Instant::now() + Duration::from_secs(60));
+
+        let expired = asn(SystemTime::now() - Duration::from_secs(60)).valid_until();
+        assert!(expired >= before && expired <= Instant::now());
+    }
+
+    #[test]
+    fn test_origin_anomaly() {
+        use super::{flatten_origins, origin_anomaly, parse_cymru_prefix_origins};
+        let origins = |records: &[&str]| {
+            let records: TxtRecords = records.iter().collect();
+            flatten_origins(
+                parse_cymru_prefix_origins(&records, SystemTime::now(), Strictness::Lenient)
+                    .unwrap(),
+            )
+        };
+
+        let single = origins(&[
+            "64500 | 198.51.100.0/24 | US | arin | 2010-01-01",
+            "64500 | 198.51.0.0/16 | US | arin | 2010-01-01",
+        ]);
+        assert_eq!(origin_anomaly(&single), None);
+
+        let conflicting = origins(&[
+            "64500 | 198.51.100.0/24 | US | arin | 2010-01-01",
+            "64501 | 198.51.0.0/16 | US | arin | 2010-01-01",
+        ]);
+        let anomaly = origin_anomaly(&conflicting).unwrap();
+        assert!(!anomaly.moas);
+        assert!(anomaly.conflicting_prefixes);
+        assert_eq!(
+            anomaly.origins,
+            vec![
+                ("198.51.100.0/24".to_string(), 64500),
+                ("198.51.0.0/16".to_string(), 64501)
+            ]
+        );
+
+        let moas = origins(&["64500 64501 | 198.51.100.0/24 | US | arin | 2010-01-01"]);
+        let anomaly = origin_anomaly(&moas).unwrap();
+        assert!(anomaly.moas);
+        assert!(!anomaly.conflicting_prefixes);
+    }
+
+    #[test]
+    fn test_parse_strict() {
+        use super::{parse_cymru_asn, parse_cymru_prefix_origins};
+        let ttl = SystemTime::now();
+        let strict = |record: &str| {
+            parse_cymru_prefix_origins(&[record].iter().collect(), ttl, Strictness::Strict)
+                .map_err(|malformed| malformed.reason)
+        };
+
+        assert!(strict("64500 | 198.51.0.0/16 | US | arin | ").is_ok());
+        assert_eq!(
+            strict("64500 x | 198.51.0.0/16 | US | arin | 2010-01-01").unwrap_err(),
+            "invalid AS number"
+        );
+        assert_eq!(
+            strict("64500 | 198.51.0.0/33 | US | arin | 2010-01-01").unwrap_err(),
+            "invalid BGP prefix"
+        );
+        assert_eq!(
+            strict("64500 | 198.51.0.0/16 | us | arin | 2010-01-01").unwrap_err(),
+            "invalid country code"
+        );
+        assert_eq!(
+            strict("64500 | 198.51.0.0/16 | US | arin | 2010-13-01").unwrap_err(),
+            "invalid allocation date"
+        );
+        assert_eq!(
+            strict("64500 | 198.51.0.0/16 | US | arin | 2010-01-01 | x").unwrap_err(),
+            "too many fields"
+        );
+
+        let record = "23028 | US | arin | 2002-01-04".to_string();
+        let lenient =
+            parse_cymru_asn(&[&record].iter().collect(), ttl, Strictness::Lenient).unwrap();
+        assert!(lenient.is_empty());
+        let err =
+            parse_cymru_asn(&[&record].iter().collect(), ttl, Strictness::Strict).unwrap_err();
+        assert_eq!(err.record, record);
+        assert_eq!(err.reason, "too few fields");
+    }
 }
@@ -0,0 +1,72 @@
+//! Adapter reporting client activity through the [metrics](https://docs.rs/metrics)
+//! crate facade
+//!
+//! The `metrics` crate only defines a facade; install any compatible
+//! exporter (statsd, Prometheus, ...) in the application to collect what
+//! [`MetricsRs`](struct.MetricsRs.html) emits.
+//!
+//! Emitted metrics of queries, labeled with the `backend` queried:
+//!
+//! - `cymrust_queries_total` counter of DNS queries sent
+//! - `cymrust_query_errors_total` counter of failed DNS queries, also labeled
+//!   with `stage`
+//! - `cymrust_query_duration_seconds` histogram of DNS query durations
+//! - `cymrust_retries_total` counter of DNS queries sent again after failing
+//!
+//! and of the client's caches, labeled with the `cache`:
+//!
+//! - `cymrust_cache_hits_total` counter of answers found in the cache
+//! - `cymrust_cache_misses_total` counter of answers not found in the cache
+
+use std::time::Duration;
+
+use crate::{Error, Metrics};
+
+/// [`Metrics`](../trait.Metrics.html) implementation forwarding to the
+/// `metrics` crate facade
+///
+/// ```no_run
+/// use cymrust::metrics_rs::MetricsRs;
+/// use cymrust::CymruClient;
+///
+/// let client = CymruClient::new().unwrap().with_metrics(MetricsRs);
+/// ```
+///
+#[derive(Debug, Clone, Copy, Default)]
+pub struct MetricsRs;
+
+impl Metrics for MetricsRs {
+    fn query_start(&self, backend: &str, _query: &str) {
+        metrics::counter!("cymrust_queries_total", "backend" => backend.to_string()).increment(1);
+    }
+
+    fn query_finish(&self, backend: &str, _query: &str, elapsed: Duration) {
+        metrics::histogram!("cymrust_query_duration_seconds", "backend" => backend.to_string())
+            .record(elapsed.as_secs_f64());
+    }
+
+    fn error(&self, backend: &str, _query: &str, error: &Error) {
+        let stage = match error.stage() {
+            Some(stage) => stage.to_string(),
+            None => "none".to_string(),
+        };
+        metrics::counter!(
+            "cymrust_query_errors_total",
+            "backend" => backend.to_string(),
+            "stage" => stage
+        )
+        .increment(1);
+    }
+
+    fn retry(&self, backend: &str, _query: &str, _retry: usize) {
+        metrics::counter!("cymrust_retries_total", "backend" => backend.to_string()).increment(1);
+    }
+
+    fn cache_hit(&self, cache: &str, _query: &str) {
+        metrics::counter!("cymrust_cache_hits_total", "cache" => cache.to_string()).increment(1);
+    }
+
+    fn cache_miss(&self, cache: &str, _query: &str) {
+        metrics::counter!("cymrust_cache_misses_total", "cache" => cache.to_string()).increment(1);
+    }
+}
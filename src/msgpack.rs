@@ -0,0 +1,159 @@
+//! Compact binary serialization of results with
+//! [MessagePack](https://msgpack.org/)
+//!
+//! Available with the `msgpack` feature. Results are encoded through their
+//! `serde` implementations as maps with field names, so data written by one
+//! version of this crate stays readable by the next one even as fields are
+//! added. That makes it a cheaper alternative to JSON for handing results to
+//! other processes or spilling large result sets to disk.
+//!
+//! [`MsgpackSink`](struct.MsgpackSink.html) writes results back to back and
+//! [`MsgpackReader`](struct.MsgpackReader.html) reads them back:
+//!
+//! ```
+//! use cymrust::fixtures;
+//! use cymrust::msgpack::{MsgpackReader, MsgpackSink};
+//! use cymrust::sink::OutputSink;
+//! use cymrust::CymruIP2ASN;
+//!
+//! let mut sink = MsgpackSink::new(Vec::new());
+//! sink.write(&fixtures::google()).unwrap();
+//! sink.write(&fixtures::cloudflare()).unwrap();
+//! let bytes = sink.into_inner();
+//!
+//! let results: Vec<CymruIP2ASN> = MsgpackReader::new(&bytes[..])
+//!     .collect::<Result<_, _>>()
+//!     .unwrap();
+//! assert_eq!(results, vec![fixtures::google(), fixtures::cloudflare()]);
+//! ```
+
+use std::io::{self, BufRead, Write};
+use std::marker::PhantomData;
+
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+
+use crate::sink::OutputSink;
+use crate::CymruIP2ASN;
+
+pub use rmp_serde::decode::Error as DecodeError;
+pub use rmp_serde::encode::Error as EncodeError;
+
+/// Encode `value`, e.g. a single result or a whole `Vec` of them
+///
+pub fn to_vec<T: Serialize + ?Sized>(value: &T) -> Result<Vec<u8>, EncodeError> {
+    rmp_serde::to_vec_named(value)
+}
+
+/// Decode value encoded with [`to_vec`](fn.to_vec.html)
+///
+pub fn from_slice<T: DeserializeOwned>(bytes: &[u8]) -> Result<T, DecodeError> {
+    rmp_serde::from_slice(bytes)
+}
+
+/// Sink writing results as consecutive MessagePack values
+///
+pub struct MsgpackSink<W> {
+    writer: W,
+}
+
+impl<W: Write> MsgpackSink<W> {
+    /// Write MessagePack values to `writer`
+    ///
+    pub fn new(writer: W) -> Self {
+        MsgpackSink { writer }
+    }
+
+    /// Unwrap the underlying writer
+    ///
+    pub fn into_inner(self) -> W {
+        self.writer
+    }
+}
+
+impl<W: Write> OutputSink for MsgpackSink<W> {
+    fn write(&mut self, result: &CymruIP2ASN) -> io::Result<()> {
+        result
+            .serialize(&mut rmp_serde::Serializer::new(&mut self.writer).with_struct_map())
+            .map_err(io::Error::other)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.writer.flush()
+    }
+}
+
+/// Iterator over values read from consecutive MessagePack values in
+/// `reader`, such as written by [`MsgpackSink`](struct.MsgpackSink.html)
+///
+/// Iteration ends at the end of input, or after the first error.
+///
+pub struct MsgpackReader<R, T = CymruIP2ASN> {
+    reader: R,
+    failed: bool,
+    item: PhantomData<fn() -> T>,
+}
+
+impl<R: BufRead, T: DeserializeOwned> MsgpackReader<R, T> {
+    /// Read values from `reader`
+    ///
+    pub fn new(reader: R) -> Self {
+        MsgpackReader {
+            reader,
+            failed: false,
+            item: PhantomData,
+        }
+    }
+}
+
+impl<R: BufRead, T: DeserializeOwned> Iterator for MsgpackReader<R, T> {
+    type Item = Result<T, DecodeError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.failed {
+            return None;
+        }
+        match self.reader.fill_buf() {
+            Ok([]) => return None,
+            Ok(_) => {}
+            Err(err) => {
+                self.failed = true;
+                return Some(Err(DecodeError::InvalidDataRead(err)));
+            }
+        }
+        let result = T::deserialize(&mut rmp_serde::Deserializer::new(&mut self.reader));
+        self.failed = result.is_err();
+        Some(result)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{from_slice, to_vec, MsgpackReader, MsgpackSink};
+    use crate::sink::OutputSink;
+    use crate::{fixtures, CymruASN, CymruIP2ASN};
+
+    #[test]
+    fn test_msgpack_roundtrip() {
+        let results = fixtures::multi_origin();
+        let bytes = to_vec(&results).unwrap();
+        assert!(bytes.len() < serde_json::to_vec(&results).unwrap().len());
+        assert_eq!(from_slice::<Vec<CymruIP2ASN>>(&bytes).unwrap(), results);
+
+        let asn: CymruASN = from_slice(&to_vec(&fixtures::google_asn()).unwrap()).unwrap();
+        assert_eq!(asn, fixtures::google_asn());
+    }
+
+    #[test]
+    fn test_msgpack_reader_stops_at_error() {
+        let mut sink = MsgpackSink::new(Vec::new());
+        sink.write(&fixtures::team_cymru()).unwrap();
+        let mut bytes = sink.into_inner();
+        bytes.extend_from_slice(&[0xc1, 0x00]);
+
+        let mut reader = MsgpackReader::<_, CymruIP2ASN>::new(&bytes[..]);
+        assert_eq!(reader.next().unwrap().unwrap(), fixtures::team_cymru());
+        assert!(reader.next().unwrap().is_err());
+        assert!(reader.next().is_none());
+    }
+}
@@ -0,0 +1,269 @@
+//! Lookups for `ipnet` network types
+//!
+//! Available with the `ipnet` feature. Networks are looked up by their
+//! network address, and the results are tagged with the network given as
+//! input, so code already working with [ipnet](https://docs.rs/ipnet) types
+//! can use them directly:
+//!
+//! ```no_run
+//! use ipnet::Ipv4Net;
+//! use cymrust::CymruClient;
+//!
+//! let client = CymruClient::new().unwrap();
+//! let net: Ipv4Net = "8.8.8.0/24".parse().unwrap();
+//! let lookup = client.net2asn(net).unwrap();
+//! println!("{} is originated by AS{}", lookup.net, lookup.results[0].as_number);
+//! ```
+//!
+//! Cymru answers with the announced BGP prefix covering the network
+//! address, which may be larger or smaller than the network asked about.
+//! [`NetLookup::relation`](struct.NetLookup.html#method.relation) tells which.
+//!
+//! [`aggregate`](fn.aggregate.html) goes the other way and coalesces the BGP
+//! prefixes of many results into compact per-AS prefix lists, e.g. for
+//! generating firewall rules or allowlists.
+
+use std::collections::BTreeMap;
+
+use ipnet::IpNet;
+
+use crate::{AsNumber, CymruClient, CymruIP2ASN, Error};
+
+/// IP-to-ASN results of a network lookup
+///
+#[derive(Debug, PartialEq, Eq)]
+pub struct NetLookup {
+    /// Network given as input
+    pub net: IpNet,
+    /// Results for the network address of `net`
+    pub results: Vec<CymruIP2ASN>,
+}
+
+/// How an announced BGP prefix relates to the network looked up
+///
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum PrefixRelation {
+    /// Announced prefix is the network itself
+    Exact,
+    /// Announced prefix is larger and covers the whole network
+    Covering,
+    /// Announced prefix is smaller, so other parts of the network may be
+    /// announced separately or not at all
+    MoreSpecific,
+}
+
+impl NetLookup {
+    /// How the BGP prefix of `result` relates to `net`
+    ///
+    /// Returns `None` if the BGP prefix can't be parsed.
+    ///
+    pub fn relation(&self, result: &CymruIP2ASN) -> Option<PrefixRelation> {
+        let announced: IpNet = result.bgp_prefix.parse().ok()?;
+        let net = self.net.trunc();
+        Some(if announced.trunc() == net {
+            PrefixRelation::Exact
+        } else if announced.contains(&net) {
+            PrefixRelation::Covering
+        } else {
+            PrefixRelation::MoreSpecific
+        })
+    }
+
+    /// Whether any announced BGP prefix differs from `net`
+    ///
+    pub fn differs(&self) -> bool {
+        self.results
+            .iter()
+            .any(|result| self.relation(result) != Some(PrefixRelation::Exact))
+    }
+}
+
+impl CymruClient {
+    /// Query Cymru's IP-to-ASN mapping for the network address of `net`
+    ///
+    /// Note that the returned BGP prefixes may be larger or smaller than
+    /// `net` itself; only its network address is looked up.
+    ///
+    pub fn net2asn<N: Into<IpNet>>(&self, net: N) -> Result<NetLookup, Error> {
+        let net = net.into();
+        let results = self.ip2asn(net.network())?;
+        Ok(NetLookup { net, results })
+    }
+
+    /// Query Cymru's IP-to-ASN mapping for prefix `net`
+    ///
+    /// Like [`net2asn`](#method.net2asn), except that host bits of `net` are
+    /// cleared first, so that the results are always labeled with a proper
+    /// prefix, e.g. 192.0.2.0/24 for 192.0.2.1/24. Use
+    /// [`NetLookup::relation`](net/struct.NetLookup.html#method.relation) to
+    /// detect when the announced prefix differs from it.
+    ///
+    pub fn prefix<N: Into<IpNet>>(&self, net: N) -> Result<NetLookup, Error> {
+        self.net2asn(net.into().trunc())
+    }
+}
+
+/// Prefixes originated by one AS
+///
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Aggregate {
+    /// BGP Origin's Autonomous System (AS) number
+    pub as_number: AsNumber,
+    /// Merged prefixes, IPv4 before IPv6, sorted by address
+    pub prefixes: Vec<IpNet>,
+}
+
+/// Merge the BGP prefixes of `results` per origin AS
+///
+/// Duplicate and overlapping prefixes are reduced to the covering one, and
+/// adjacent prefixes are merged into larger ones where they align, e.g.
+/// 192.0.2.0/25 and 192.0.2.128/25 into 192.0.2.0/24. Prefixes of different
+/// AS numbers are never merged with each other. Aggregates are sorted by AS
+/// number, and results with unparseable BGP prefixes are skipped.
+///
+/// ```
+/// use cymrust::fixtures;
+/// use cymrust::net::aggregate;
+///
+/// let result = |prefix: &str| {
+///     let mut result = fixtures::google();
+///     result.bgp_prefix = prefix.to_string();
+///     result
+/// };
+/// let results = vec![result("192.0.2.0/25"), result("192.0.2.128/25")];
+/// let aggregates = aggregate(&results);
+/// assert_eq!(aggregates[0].prefixes, vec!["192.0.2.0/24".parse().unwrap()]);
+/// ```
+///
+pub fn aggregate<'a, I>(results: I) -> Vec<Aggregate>
+where
+    I: IntoIterator<Item = &'a CymruIP2ASN>,
+{
+    let mut prefixes: BTreeMap<AsNumber, Vec<IpNet>> = BTreeMap::new();
+    for result in results {
+        if let Ok(net) = result.bgp_prefix.parse::<IpNet>() {
+            prefixes.entry(result.as_number).or_default().push(net);
+        }
+    }
+
+    prefixes
+        .into_iter()
+        .map(|(as_number, prefixes)| Aggregate {
+            as_number,
+            prefixes: IpNet::aggregate(&prefixes),
+        })
+        .collect()
+}
+
+/// Query Cymru's IP-to-ASN mapping for the network address of `net`
+///
+/// See [`cymru_ip2asn`](../fn.cymru_ip2asn.html) for details.
+///
+pub fn cymru_net2asn<N: Into<IpNet>>(net: N) -> Result<NetLookup, Error> {
+    CymruClient::shared()?.net2asn(net)
+}
+
+/// Query Cymru's IP-to-ASN mapping for prefix `net`
+///
+/// See [`CymruClient::prefix`](../struct.CymruClient.html#method.prefix) for
+/// details.
+///
+pub fn cymru_prefix<N: Into<IpNet>>(net: N) -> Result<NetLookup, Error> {
+    CymruClient::shared()?.prefix(net)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use ipnet::{IpNet, Ipv4Net, Ipv6Net};
+
+    use super::{aggregate, PrefixRelation};
+    use crate::{fixtures, CymruClient};
+
+    #[test]
+    fn test_net2asn() {
+        let client = CymruClient::with_provider(fixtures::mock_provider());
+
+        let net: Ipv4Net = "8.8.8.8/32".parse().unwrap();
+        let lookup = client.net2asn(net).unwrap();
+        assert_eq!(lookup.net, net.into());
+        assert_eq!(lookup.results[0].bgp_prefix, "8.8.8.0/24");
+
+        let net: Ipv6Net = "2001:4860:4860::8888/128".parse().unwrap();
+        let lookup = client.net2asn(net).unwrap();
+        assert_eq!(lookup.results[0].as_number, 15169);
+
+        let net: Ipv4Net = "8.8.4.0/24".parse().unwrap();
+        assert!(client.net2asn(net).is_err());
+    }
+
+    #[test]
+    fn test_prefix() {
+        let mock = fixtures::mock_provider().answer(
+            "0.8.8.8.origin.asn.cymru.com.",
+            Duration::from_secs(60),
+            &[fixtures::GOOGLE_ORIGIN_TXT],
+        );
+        let client = CymruClient::with_provider(mock);
+
+        let net: Ipv4Net = "8.8.8.8/24".parse().unwrap();
+        let lookup = client.prefix(net).unwrap();
+        assert_eq!(lookup.net, "8.8.8.0/24".parse::<IpNet>().unwrap());
+        assert_eq!(
+            lookup.relation(&lookup.results[0]),
+            Some(PrefixRelation::Exact)
+        );
+        assert!(!lookup.differs());
+
+        let lookup = client
+            .prefix("8.8.8.8/32".parse::<IpNet>().unwrap())
+            .unwrap();
+        assert_eq!(
+            lookup.relation(&lookup.results[0]),
+            Some(PrefixRelation::Covering)
+        );
+        assert!(lookup.differs());
+
+        let lookup = client.prefix("8.8.4.4/22".parse::<IpNet>().unwrap());
+        assert!(lookup.is_err());
+
+        let mut lookup = client.prefix(net).unwrap();
+        lookup.net = "8.0.0.0/8".parse().unwrap();
+        assert_eq!(
+            lookup.relation(&lookup.results[0]),
+            Some(PrefixRelation::MoreSpecific)
+        );
+    }
+
+    #[test]
+    fn test_aggregate() {
+        let result = |asn, prefix: &str| {
+            let mut result = fixtures::google();
+            result.as_number = asn;
+            result.bgp_prefix = prefix.to_string();
+            result
+        };
+        let results = vec![
+            result(64500, "192.0.2.0/25"),
+            result(64500, "192.0.2.128/26"),
+            result(64500, "192.0.2.192/26"),
+            result(64500, "192.0.2.64/26"),
+            result(64500, "2001:db8::/32"),
+            result(64500, "198.51.100.0/24"),
+            result(64501, "198.51.101.0/24"),
+            result(64501, "not a prefix"),
+        ];
+        let nets =
+            |nets: &[&str]| -> Vec<IpNet> { nets.iter().map(|net| net.parse().unwrap()).collect() };
+
+        let aggregates = aggregate(&results);
+        assert_eq!(aggregates.len(), 2);
+        assert_eq!(aggregates[0].as_number, 64500);
+        assert_eq!(
+            aggregates[0].prefixes,
+            nets(&["192.0.2.0/24", "198.51.100.0/24", "2001:db8::/32"])
+        );
+        assert_eq!(aggregates[1].prefixes, nets(&["198.51.101.0/24"]));
+    }
+}
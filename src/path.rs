@@ -0,0 +1,207 @@
+//! Annotate traceroute hops with AS numbers
+//!
+//! [`annotate_path`](fn.annotate_path.html) turns an ordered list of hop
+//! addresses into an AS path: consecutive hops in the same AS collapse into
+//! one segment, and hops which didn't respond or use non-routable addresses
+//! are marked as such.
+//!
+//! ```
+//! use std::net::IpAddr;
+//! use cymrust::path::PathSegment;
+//! use cymrust::{fixtures, CymruClient};
+//!
+//! let client = CymruClient::with_provider(fixtures::mock_provider());
+//! let hops: Vec<Option<IpAddr>> = vec![
+//!     Some("192.168.1.1".parse().unwrap()),
+//!     None,
+//!     Some("8.8.8.8".parse().unwrap()),
+//! ];
+//! let path = client.annotate_path(&hops).unwrap();
+//! assert!(matches!(path[0], PathSegment::Private { .. }));
+//! assert!(matches!(path[1], PathSegment::Unresponsive { .. }));
+//! assert!(matches!(path[2], PathSegment::As { as_number: 15169, .. }));
+//! ```
+
+use std::collections::hash_map::{Entry, HashMap};
+use std::net::IpAddr;
+
+use crate::special::special_purpose;
+use crate::{AsNumber, CymruClient, Error};
+
+/// Run of consecutive hops in an annotated path
+///
+/// `hops` are indices into the hop list given to `annotate_path`.
+///
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PathSegment {
+    /// Hops originated by the same AS
+    As {
+        /// Autonomous System (AS) number
+        as_number: AsNumber,
+        /// Autonomous System (AS) description
+        as_name: String,
+        /// Indices of the hops
+        hops: Vec<usize>,
+    },
+    /// Hops using private or other non-routable addresses
+    Private {
+        /// Indices of the hops
+        hops: Vec<usize>,
+    },
+    /// Hops which did not respond
+    Unresponsive {
+        /// Indices of the hops
+        hops: Vec<usize>,
+    },
+    /// Hops with routable addresses which Cymru has no origin for
+    Unknown {
+        /// Indices of the hops
+        hops: Vec<usize>,
+    },
+}
+
+impl PathSegment {
+    fn hops_mut(&mut self) -> &mut Vec<usize> {
+        match self {
+            PathSegment::As { hops, .. }
+            | PathSegment::Private { hops }
+            | PathSegment::Unresponsive { hops }
+            | PathSegment::Unknown { hops } => hops,
+        }
+    }
+
+    /// Whether a hop of `other` kind continues this segment
+    fn continues(&self, other: &PathSegment) -> bool {
+        match (self, other) {
+            (PathSegment::As { as_number: a, .. }, PathSegment::As { as_number: b, .. }) => a == b,
+            (PathSegment::Private { .. }, PathSegment::Private { .. })
+            | (PathSegment::Unresponsive { .. }, PathSegment::Unresponsive { .. })
+            | (PathSegment::Unknown { .. }, PathSegment::Unknown { .. }) => true,
+            _ => false,
+        }
+    }
+}
+
+impl CymruClient {
+    /// Map ordered traceroute `hops` to an AS path
+    ///
+    /// Hops are either `IpAddr`s or `Option<IpAddr>`s with `None` for hops
+    /// that didn't respond. Each distinct address is looked up once. When an
+    /// address is originated by several AS numbers, the first one Cymru
+    /// lists is used.
+    ///
+    /// # Errors
+    ///
+    /// Returns the first lookup error other than finding no results.
+    ///
+    pub fn annotate_path<H>(&self, hops: &[H]) -> Result<Vec<PathSegment>, Error>
+    where
+        H: Into<Option<IpAddr>> + Copy,
+    {
+        let mut origins: HashMap<IpAddr, Option<(AsNumber, String)>> = HashMap::new();
+        let mut path: Vec<PathSegment> = Vec::new();
+
+        for (index, hop) in hops.iter().enumerate() {
+            let segment = match (*hop).into() {
+                None => PathSegment::Unresponsive { hops: vec![index] },
+                Some(ip) if special_purpose(ip).is_some_and(|p| !p.globally_reachable) => {
+                    PathSegment::Private { hops: vec![index] }
+                }
+                Some(ip) => {
+                    let origin = match origins.entry(ip) {
+                        Entry::Occupied(entry) => entry.into_mut(),
+                        Entry::Vacant(entry) => entry.insert(match self.ip2asn(ip) {
+                            Ok(results) => {
+                                results.into_iter().next().map(|r| (r.as_number, r.as_name))
+                            }
+                            Err(Error::NoResultsFound { .. }) => None,
+                            Err(err) => return Err(err),
+                        }),
+                    };
+                    match origin {
+                        Some((as_number, as_name)) => PathSegment::As {
+                            as_number: *as_number,
+                            as_name: as_name.clone(),
+                            hops: vec![index],
+                        },
+                        None => PathSegment::Unknown { hops: vec![index] },
+                    }
+                }
+            };
+
+            match path.last_mut() {
+                Some(last) if last.continues(&segment) => last.hops_mut().push(index),
+                _ => path.push(segment),
+            }
+        }
+
+        Ok(path)
+    }
+}
+
+/// Map ordered traceroute `hops` to an AS path
+///
+/// See [`CymruClient::annotate_path`](../struct.CymruClient.html#method.annotate_path)
+/// for details.
+///
+pub fn annotate_path<H>(hops: &[H]) -> Result<Vec<PathSegment>, Error>
+where
+    H: Into<Option<IpAddr>> + Copy,
+{
+    CymruClient::shared()?.annotate_path(hops)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::IpAddr;
+
+    use super::PathSegment;
+    use crate::{fixtures, CymruClient};
+
+    #[test]
+    fn test_annotate_path() {
+        let client = CymruClient::with_provider(fixtures::mock_provider());
+        let hops: Vec<Option<IpAddr>> = [
+            "10.0.0.1",
+            "100.64.0.1",
+            "*",
+            "*",
+            "8.8.8.8",
+            "8.8.8.8",
+            "1.1.1.1",
+            "9.9.9.9",
+            "1.1.1.1",
+        ]
+        .iter()
+        .map(|hop| hop.parse().ok())
+        .collect();
+
+        let path = client.annotate_path(&hops).unwrap();
+        assert_eq!(
+            path,
+            vec![
+                PathSegment::Private { hops: vec![0, 1] },
+                PathSegment::Unresponsive { hops: vec![2, 3] },
+                PathSegment::As {
+                    as_number: 15169,
+                    as_name: "GOOGLE, US".to_string(),
+                    hops: vec![4, 5],
+                },
+                PathSegment::As {
+                    as_number: 13335,
+                    as_name: "CLOUDFLARENET, US".to_string(),
+                    hops: vec![6],
+                },
+                PathSegment::Unknown { hops: vec![7] },
+                PathSegment::As {
+                    as_number: 13335,
+                    as_name: "CLOUDFLARENET, US".to_string(),
+                    hops: vec![8],
+                },
+            ]
+        );
+
+        let plain: Vec<IpAddr> = vec!["8.8.8.8".parse().unwrap()];
+        assert_eq!(client.annotate_path(&plain).unwrap().len(), 1);
+    }
+}
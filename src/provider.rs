@@ -0,0 +1,979 @@
+//! Sources of TXT answers for [`CymruClient`](../struct.CymruClient.html)
+//!
+//! [`CymruClient`](../struct.CymruClient.html) does not talk to DNS directly;
+//! it asks a [`Provider`](trait.Provider.html) for the TXT records of each
+//! query name and parses whatever comes back. [`DnsProvider`](struct.DnsProvider.html)
+//! is the default and queries Cymru over DNS. Other providers can replace or
+//! wrap it, e.g. [`MockProvider`](struct.MockProvider.html) for testing code
+//! built on top of this crate.
+
+use std::collections::HashMap;
+use std::io;
+use std::iter::FromIterator;
+use std::net::{IpAddr, SocketAddr};
+use std::str::FromStr;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{mpsc, Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use trust_dns_resolver::config::{NameServerConfigGroup, ResolverConfig, ResolverOpts};
+use trust_dns_resolver::error::ResolveError;
+use trust_dns_resolver::proto::rr::{Name, RData, Record, RecordType};
+use trust_dns_resolver::system_conf::read_system_conf;
+use trust_dns_resolver::Resolver;
+
+use crate::{Error, Stage};
+
+/// TXT answer for a single query name
+///
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TxtAnswer {
+    /// How long the answer may be cached
+    pub ttl: Duration,
+    /// TXT record strings
+    pub records: TxtRecords,
+    /// Number of received records left out of `records`, e.g. for not being
+    /// valid UTF-8
+    pub discarded: usize,
+    /// Name the records were found under, if the query name is an alias
+    /// (CNAME) of it
+    pub canonical_name: Option<String>,
+    /// Name of the backend which actually answered, if the provider asked
+    /// isn't it, e.g. the winner of a [`RaceProvider`](struct.RaceProvider.html)
+    pub answered_by: Option<&'static str>,
+}
+
+/// TXT record strings of an answer, stored back to back in one buffer
+///
+/// Answers commonly have a handful of short records. Keeping them in one
+/// `String` instead of a `String` each saves an allocation per record, and
+/// parsers read them in place.
+///
+/// ```
+/// use cymrust::provider::TxtRecords;
+///
+/// let records: TxtRecords = ["64500 | US", "64501 | FI"].iter().collect();
+/// assert_eq!(records.len(), 2);
+/// assert_eq!(records.get(1), Some("64501 | FI"));
+/// assert_eq!(records.iter().collect::<Vec<_>>(), ["64500 | US", "64501 | FI"]);
+/// ```
+///
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct TxtRecords {
+    text: String,
+    ends: Vec<usize>,
+}
+
+impl TxtRecords {
+    /// Create empty list of records
+    ///
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Append `record`
+    ///
+    pub fn push(&mut self, record: &str) {
+        self.text.push_str(record);
+        self.ends.push(self.text.len());
+    }
+
+    /// Append `data` if it is valid UTF-8, and tell whether it was
+    ///
+    fn push_utf8(&mut self, data: &[u8]) -> bool {
+        match std::str::from_utf8(data) {
+            Ok(record) => {
+                self.push(record);
+                true
+            }
+            Err(_) => false,
+        }
+    }
+
+    /// Number of records
+    ///
+    pub fn len(&self) -> usize {
+        self.ends.len()
+    }
+
+    /// Whether there are no records
+    ///
+    pub fn is_empty(&self) -> bool {
+        self.ends.is_empty()
+    }
+
+    /// Record at `index`, if there are that many
+    ///
+    pub fn get(&self, index: usize) -> Option<&str> {
+        let end = *self.ends.get(index)?;
+        let start = match index {
+            0 => 0,
+            _ => self.ends[index - 1],
+        };
+        Some(&self.text[start..end])
+    }
+
+    /// Iterate over records in order
+    ///
+    pub fn iter(&self) -> TxtRecordsIter<'_> {
+        TxtRecordsIter {
+            records: self,
+            index: 0,
+        }
+    }
+}
+
+impl<S: AsRef<str>> FromIterator<S> for TxtRecords {
+    fn from_iter<I: IntoIterator<Item = S>>(iter: I) -> Self {
+        let mut records = TxtRecords::new();
+        for record in iter {
+            records.push(record.as_ref());
+        }
+        records
+    }
+}
+
+impl<'a> IntoIterator for &'a TxtRecords {
+    type Item = &'a str;
+    type IntoIter = TxtRecordsIter<'a>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.iter()
+    }
+}
+
+/// Iterator over [`TxtRecords`](struct.TxtRecords.html)
+///
+#[derive(Debug, Clone)]
+pub struct TxtRecordsIter<'a> {
+    records: &'a TxtRecords,
+    index: usize,
+}
+
+impl<'a> Iterator for TxtRecordsIter<'a> {
+    type Item = &'a str;
+
+    fn next(&mut self) -> Option<&'a str> {
+        let record = self.records.get(self.index)?;
+        self.index += 1;
+        Some(record)
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        let remaining = self.records.len() - self.index;
+        (remaining, Some(remaining))
+    }
+}
+
+impl ExactSizeIterator for TxtRecordsIter<'_> {}
+
+/// Longest CNAME chain followed when working out how long an answer is valid
+///
+const MAX_CNAME_CHAIN: usize = 8;
+
+/// How the character-strings of one TXT record are made into a record string
+///
+/// A TXT record carries one or more character-strings of at most 255 bytes
+/// each. Longer texts, such as some of Cymru's AS names, are split over
+/// several strings which only make sense joined back together.
+///
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum TxtJoin {
+    /// Concatenate strings as they are, like SPF and DKIM do (the default)
+    #[default]
+    Concat,
+    /// Join strings with a single space in between
+    Space,
+    /// Keep every string as a record string of its own, as in 0.4 and older
+    Split,
+}
+
+impl TxtJoin {
+    /// Join character-strings of a single TXT record, passing each joined
+    /// record string to `each`
+    ///
+    /// Bytes are joined before UTF-8 decoding, so that a multi-byte character
+    /// split over two strings survives. Joining happens in `buf`, which is
+    /// reused from record to record.
+    ///
+    fn join<F: FnMut(&[u8])>(self, strings: &[Box<[u8]>], buf: &mut Vec<u8>, mut each: F) {
+        match (self, strings) {
+            (TxtJoin::Split, _) | (_, [_]) => strings.iter().for_each(|data| each(data)),
+            _ => {
+                buf.clear();
+                for (i, data) in strings.iter().enumerate() {
+                    if i > 0 && self == TxtJoin::Space {
+                        buf.push(b' ');
+                    }
+                    buf.extend_from_slice(data);
+                }
+                each(buf);
+            }
+        }
+    }
+}
+
+/// Backend answering TXT queries for the client
+///
+pub trait Provider: Send + Sync {
+    /// Short name of the backend, used in stats and instrumentation
+    fn name(&self) -> &'static str;
+
+    /// Whether answering queries involves network I/O
+    ///
+    /// Offline clients refuse to use providers returning `true`, which is
+    /// the default.
+    ///
+    fn is_network(&self) -> bool {
+        true
+    }
+
+    /// Look up TXT records for DNS name `query` belonging to lookup `stage`
+    ///
+    fn txt(&self, query: &str, stage: Stage) -> Result<TxtAnswer, Error>;
+
+    /// Look up PTR record names for reverse DNS name `query`
+    ///
+    /// Providers which can't do reverse lookups don't need to implement
+    /// this; by default every query fails with `Error::NoResultsFound`.
+    ///
+    fn ptr(&self, query: &str, stage: Stage) -> Result<Vec<String>, Error> {
+        Err(Error::NoResultsFound {
+            query: query.to_string(),
+            stage,
+        })
+    }
+
+    /// Look up IPv4 and IPv6 addresses of hostname `query`
+    ///
+    /// Like `ptr`, this fails with `Error::NoResultsFound` by default.
+    ///
+    fn addrs(&self, query: &str, stage: Stage) -> Result<Vec<IpAddr>, Error> {
+        Err(Error::NoResultsFound {
+            query: query.to_string(),
+            stage,
+        })
+    }
+}
+
+/// Provider querying Cymru over DNS using trust-dns resolver
+///
+/// With more than one resolver, e.g. one per nameserver, every query is
+/// raced: it's sent through all resolvers at once and the first successful
+/// answer wins. This cuts tail latency when one nameserver is intermittently
+/// slow, at the cost of multiplying the number of queries sent. Queries
+/// losing the race finish in the background, on threads of their own. While
+/// a provider has 64 such threads busy, further queries go to the first
+/// resolver alone.
+///
+pub struct DnsProvider {
+    resolvers: Vec<Arc<Resolver>>,
+    txt_join: TxtJoin,
+    race_threads: RaceThreads,
+}
+
+impl DnsProvider {
+    /// Create provider using the system's DNS resolver configuration
+    ///
+    /// # Errors
+    ///
+    /// Returns `Error::Io` if system's resolver configuration can't be read.
+    ///
+    pub fn new() -> Result<Self, Error> {
+        let resolver = Resolver::from_system_conf()?;
+        Ok(Self::with_resolver(resolver))
+    }
+
+    /// Create provider using the system's DNS resolver configuration, but
+    /// with `timeout` for each query
+    ///
+    /// # Errors
+    ///
+    /// Returns `Error::Io` if system's resolver configuration can't be read.
+    ///
+    pub fn with_timeout(timeout: Duration) -> Result<Self, Error> {
+        // Only a conversion on Windows, where this fails with ResolveError
+        #[allow(clippy::useless_conversion)]
+        let (config, mut opts) = read_system_conf().map_err(io::Error::from)?;
+        opts.timeout = timeout;
+        Ok(Self::with_resolver(Resolver::new(config, opts)?))
+    }
+
+    /// Create provider using given, already configured, DNS resolver
+    ///
+    pub fn with_resolver(resolver: Resolver) -> Self {
+        Self::with_resolvers(vec![resolver])
+    }
+
+    /// Create provider racing every query across given DNS resolvers
+    ///
+    /// # Panics
+    ///
+    /// Panics if `resolvers` is empty.
+    ///
+    pub fn with_resolvers(resolvers: Vec<Resolver>) -> Self {
+        assert!(!resolvers.is_empty(), "DnsProvider needs a resolver");
+        DnsProvider {
+            resolvers: resolvers.into_iter().map(Arc::new).collect(),
+            txt_join: TxtJoin::default(),
+            race_threads: RaceThreads::default(),
+        }
+    }
+
+    /// Join character-strings of TXT records according to `txt_join`
+    ///
+    pub fn with_txt_join(mut self, txt_join: TxtJoin) -> Self {
+        self.txt_join = txt_join;
+        self
+    }
+
+    /// Create provider racing every query across given nameservers
+    ///
+    /// Each nameserver gets a resolver of its own, with default options.
+    ///
+    /// # Errors
+    ///
+    /// Returns `Error::Io` if a resolver can't be set up.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `nameservers` is empty.
+    ///
+    pub fn with_nameservers(nameservers: &[SocketAddr]) -> Result<Self, Error> {
+        let mut resolvers = Vec::with_capacity(nameservers.len());
+        for addr in nameservers {
+            let servers = NameServerConfigGroup::from_ips_clear(&[addr.ip()], addr.port(), true);
+            let config = ResolverConfig::from_parts(None, vec![], servers);
+            resolvers.push(Resolver::new(config, ResolverOpts::default())?);
+        }
+        Ok(Self::with_resolvers(resolvers))
+    }
+}
+
+/// Most threads doing lookups of one provider's races at once, including
+/// those of lost races still finishing in the background
+///
+const MAX_RACE_THREADS: usize = 64;
+
+/// Count of threads doing lookups of one provider's races
+///
+#[derive(Debug, Default)]
+struct RaceThreads(Arc<AtomicUsize>);
+
+/// Releases a thread counted in `RaceThreads` when dropped, however the
+/// thread ends
+///
+struct RaceThread(Arc<AtomicUsize>);
+
+impl Drop for RaceThread {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+/// Do `lookup` with every one of `candidates` at once and return the first
+/// success
+///
+/// If all of them fail, returns the first error. Every candidate gets a
+/// thread of its own, counted in `threads`. Lookups losing the race go on in
+/// the background until they end, which the resolver's timeout bounds, and
+/// their answers are dropped. When racing would take the count past
+/// `MAX_RACE_THREADS`, e.g. while a slow candidate keeps losing many races
+/// in a bulk job, `lookup` is done with the first candidate alone instead.
+///
+/// # Panics
+///
+/// Panics if `candidates` is empty.
+///
+fn race<C, T, F>(candidates: &[Arc<C>], threads: &RaceThreads, lookup: F) -> Result<T, Error>
+where
+    C: ?Sized + Send + Sync + 'static,
+    T: Send + 'static,
+    F: Fn(&C) -> Result<T, Error> + Send + Sync + 'static,
+{
+    if let [candidate] = candidates {
+        return lookup(candidate);
+    }
+    let running = threads.0.fetch_add(candidates.len(), Ordering::SeqCst);
+    if running + candidates.len() > MAX_RACE_THREADS {
+        threads.0.fetch_sub(candidates.len(), Ordering::SeqCst);
+        debug!("{} race threads running, not racing", running);
+        return lookup(&candidates[0]);
+    }
+
+    let lookup = Arc::new(lookup);
+    let (sender, receiver) = mpsc::channel();
+    for candidate in candidates {
+        let thread = RaceThread(Arc::clone(&threads.0));
+        let candidate = Arc::clone(candidate);
+        let lookup = Arc::clone(&lookup);
+        let sender = sender.clone();
+        thread::spawn(move || {
+            let _ = sender.send(lookup(&candidate));
+            drop(thread);
+        });
+    }
+    drop(sender);
+
+    let mut first_err = None;
+    for result in receiver {
+        match result {
+            Ok(answer) => return Ok(answer),
+            Err(err) => {
+                first_err.get_or_insert(err);
+            }
+        }
+    }
+    Err(first_err.expect("at least one candidate to race"))
+}
+
+impl Provider for DnsProvider {
+    fn name(&self) -> &'static str {
+        "cymru-dns"
+    }
+
+    /// Resolve TXT record
+    ///
+    /// This is used to talk with Cymru. We expect them to provide us with
+    /// ASCII strings which is safe to decode into UTF-8 Strings. TXT records
+    /// which are not valid UTF-8 are discarded. Character-strings of a record
+    /// are joined as configured with `with_txt_join`.
+    ///
+    fn txt(&self, query: &str, stage: Stage) -> Result<TxtAnswer, Error> {
+        let query = query.to_string();
+        let txt_join = self.txt_join;
+        race(&self.resolvers, &self.race_threads, move |resolver| {
+            let mut records = TxtRecords::new();
+            let mut discarded = 0;
+            let mut buf = Vec::new();
+            let response = resolver
+                .txt_lookup(query.as_str())
+                .map_err(|err| Error::resolver(&query, stage, err))?;
+            let mut valid_until = response.valid_until();
+
+            let query_name = Name::from_str(&query).unwrap_or_default();
+            let canonical = canonical_name(response.as_lookup().record_iter(), &query_name);
+            if let Some(canonical) = &canonical {
+                debug!("{} is an alias of {}", query, canonical);
+                match cname_chain_valid_until(resolver, &query_name, canonical) {
+                    Some(chain_valid_until) => valid_until = valid_until.min(chain_valid_until),
+                    None => warn!("Could not follow CNAME chain of {}", query),
+                }
+            }
+            let ttl = valid_until.saturating_duration_since(Instant::now());
+
+            for record in response.iter() {
+                txt_join.join(record.txt_data(), &mut buf, |data| {
+                    if !records.push_utf8(data) {
+                        warn!("Discarding non UTF-8 TXT data for {}", query);
+                        discarded += 1;
+                    }
+                });
+            }
+
+            Ok(TxtAnswer {
+                ttl,
+                records,
+                discarded,
+                canonical_name: canonical.map(|name| name.to_utf8()),
+                answered_by: None,
+            })
+        })
+    }
+
+    fn ptr(&self, query: &str, stage: Stage) -> Result<Vec<String>, Error> {
+        let query = query.to_string();
+        race(&self.resolvers, &self.race_threads, move |resolver| {
+            let response = resolver
+                .lookup(query.as_str(), RecordType::PTR)
+                .map_err(|err| Error::resolver(&query, stage, err))?;
+
+            Ok(response
+                .iter()
+                .filter_map(|rdata| match rdata {
+                    RData::PTR(name) => Some(name.to_utf8()),
+                    _ => None,
+                })
+                .collect())
+        })
+    }
+
+    fn addrs(&self, query: &str, stage: Stage) -> Result<Vec<IpAddr>, Error> {
+        let query = query.to_string();
+        race(&self.resolvers, &self.race_threads, move |resolver| {
+            let response = resolver
+                .lookup_ip(query.as_str())
+                .map_err(|err| Error::resolver(&query, stage, err))?;
+
+            Ok(response.iter().collect())
+        })
+    }
+}
+
+/// Name the TXT `records` answering `query_name` belong to, if it's an alias
+///
+/// This is the owner of the TXT records themselves, as the CNAME records
+/// before them only lead there one link at a time.
+///
+fn canonical_name<'a, I>(records: I, query_name: &Name) -> Option<Name>
+where
+    I: IntoIterator<Item = &'a Record>,
+{
+    records
+        .into_iter()
+        .find(|record| record.record_type() == RecordType::TXT)
+        .map(Record::name)
+        .filter(|name| *name != query_name)
+        .cloned()
+}
+
+/// Follow CNAME chain from `alias` to `canonical` and return when its
+/// shortest-lived link expires
+///
+/// The resolver only accounts for the CNAME records' TTLs when they come in
+/// the same response as the final records. Returns `None` if the chain can't
+/// be followed.
+///
+fn cname_chain_valid_until(resolver: &Resolver, alias: &Name, canonical: &Name) -> Option<Instant> {
+    let mut name = alias.clone();
+    let mut valid_until: Option<Instant> = None;
+    for _ in 0..MAX_CNAME_CHAIN {
+        if name == *canonical {
+            return valid_until;
+        }
+        let lookup = resolver.lookup(name, RecordType::CNAME).ok()?;
+        let link_valid_until = lookup.valid_until();
+        valid_until = Some(valid_until.map_or(link_valid_until, |v| v.min(link_valid_until)));
+        name = lookup.iter().find_map(|rdata| match rdata {
+            RData::CNAME(target) => Some(target.clone()),
+            _ => None,
+        })?;
+    }
+    None
+}
+
+/// Programmed behaviour of `MockProvider` for a single query name
+///
+#[derive(Debug, Clone)]
+enum MockResponse {
+    Answer(TxtAnswer),
+    Failure(String),
+}
+
+/// Provider serving canned answers, for unit testing code using this crate
+///
+/// Queries are matched by exact DNS name, including the trailing dot, e.g.
+/// `"AS23028.asn.cymru.com."`. Names without a programmed response fail with
+/// `Error::NoResultsFound`.
+///
+/// ```
+/// use std::time::Duration;
+/// use cymrust::provider::MockProvider;
+/// use cymrust::CymruClient;
+///
+/// let mock = MockProvider::new()
+///     .answer(
+///         "1.2.0.192.origin.asn.cymru.com.",
+///         Duration::from_secs(60),
+///         &["64500 | 192.0.2.0/24 | US | arin | 2010-01-01"],
+///     )
+///     .answer(
+///         "AS64500.asn.cymru.com.",
+///         Duration::from_secs(60),
+///         &["64500 | US | arin | 2010-01-01 | EXAMPLE - Example Inc., US"],
+///     );
+/// let client = CymruClient::with_provider(mock);
+///
+/// let results = client.ip2asn("192.0.2.1".parse().unwrap()).unwrap();
+/// assert_eq!(results[0].as_number, 64500);
+/// assert_eq!(results[0].as_name, "EXAMPLE - Example Inc., US");
+/// ```
+///
+#[derive(Debug, Clone, Default)]
+pub struct MockProvider {
+    responses: HashMap<String, MockResponse>,
+    hostnames: HashMap<String, Vec<String>>,
+    addrs: HashMap<String, Vec<IpAddr>>,
+    delays: HashMap<String, Duration>,
+    holds: HashMap<String, Arc<Mutex<mpsc::Receiver<()>>>>,
+}
+
+impl MockProvider {
+    /// Create mock without any programmed responses
+    ///
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Answer `query` with given TXT `records` and `ttl`
+    ///
+    pub fn answer(mut self, query: &str, ttl: Duration, records: &[&str]) -> Self {
+        let answer = TxtAnswer {
+            ttl,
+            records: records.iter().collect(),
+            discarded: 0,
+            canonical_name: None,
+            answered_by: None,
+        };
+        self.responses
+            .insert(query.to_string(), MockResponse::Answer(answer));
+        self
+    }
+
+    /// Fail `query` with a resolver error carrying `message`
+    ///
+    pub fn fail(mut self, query: &str, message: &str) -> Self {
+        self.responses.insert(
+            query.to_string(),
+            MockResponse::Failure(message.to_string()),
+        );
+        self
+    }
+
+    /// Answer reverse DNS `query` with PTR record `name`
+    ///
+    /// Calling this several times for the same query adds more names.
+    ///
+    pub fn hostname(mut self, query: &str, name: &str) -> Self {
+        self.hostnames
+            .entry(query.to_string())
+            .or_default()
+            .push(name.to_string());
+        self
+    }
+
+    /// Answer address lookups of hostname `query` with `ip`
+    ///
+    /// Calling this several times for the same query adds more addresses.
+    ///
+    pub fn host(mut self, query: &str, ip: IpAddr) -> Self {
+        self.addrs.entry(query.to_string()).or_default().push(ip);
+        self
+    }
+
+    /// Sleep for `delay` before responding to `query`
+    ///
+    pub fn delay(mut self, query: &str, delay: Duration) -> Self {
+        self.delays.insert(query.to_string(), delay);
+        self
+    }
+
+    /// Hold each response to `query` until `release` receives a message, or
+    /// its sender is dropped
+    ///
+    /// This orders responses in tests without relying on timing, e.g. to
+    /// make sure a [`RaceProvider`](struct.RaceProvider.html) candidate
+    /// loses.
+    ///
+    pub fn hold(mut self, query: &str, release: mpsc::Receiver<()>) -> Self {
+        self.holds
+            .insert(query.to_string(), Arc::new(Mutex::new(release)));
+        self
+    }
+
+    /// Wait as programmed with `delay` and `hold` before responding to
+    /// `query`
+    ///
+    fn wait(&self, query: &str) {
+        if let Some(delay) = self.delays.get(query) {
+            thread::sleep(*delay);
+        }
+        if let Some(release) = self.holds.get(query) {
+            let release = match release.lock() {
+                Ok(release) => release,
+                Err(poisoned) => poisoned.into_inner(),
+            };
+            let _ = release.recv();
+        }
+    }
+}
+
+impl Provider for MockProvider {
+    fn name(&self) -> &'static str {
+        "mock"
+    }
+
+    fn is_network(&self) -> bool {
+        false
+    }
+
+    fn txt(&self, query: &str, stage: Stage) -> Result<TxtAnswer, Error> {
+        self.wait(query);
+
+        match self.responses.get(query) {
+            Some(MockResponse::Answer(answer)) => Ok(answer.clone()),
+            Some(MockResponse::Failure(message)) => Err(Error::Resolver {
+                query: query.to_string(),
+                stage,
+                source: Box::new(ResolveError::from(message.clone())),
+            }),
+            None => Err(Error::NoResultsFound {
+                query: query.to_string(),
+                stage,
+            }),
+        }
+    }
+
+    fn ptr(&self, query: &str, stage: Stage) -> Result<Vec<String>, Error> {
+        self.wait(query);
+
+        match self.hostnames.get(query) {
+            Some(names) => Ok(names.clone()),
+            None => Err(Error::NoResultsFound {
+                query: query.to_string(),
+                stage,
+            }),
+        }
+    }
+
+    fn addrs(&self, query: &str, stage: Stage) -> Result<Vec<IpAddr>, Error> {
+        self.wait(query);
+
+        match self.addrs.get(query) {
+            Some(addrs) => Ok(addrs.clone()),
+            None => Err(Error::NoResultsFound {
+                query: query.to_string(),
+                stage,
+            }),
+        }
+    }
+}
+
+/// Provider racing every query across several other providers
+///
+/// Each query is sent to all providers at once and the first successful
+/// answer wins, e.g. a local [`ReplayProvider`](../cassette/struct.ReplayProvider.html)
+/// table against Cymru DNS. Like with a multi-resolver
+/// [`DnsProvider`](struct.DnsProvider.html), queries losing the race finish in
+/// the background and their answers are dropped, and while 64 threads are
+/// busy with them, further queries go to the first provider alone.
+///
+/// TXT answers name the winner in `answered_by`, which the client uses for
+/// the `backend` of [`QueryStats`](../struct.QueryStats.html) and the
+/// `source` of results. Metrics and the audit log name the race itself.
+///
+/// ```
+/// use std::time::Duration;
+/// use cymrust::provider::{MockProvider, Provider, RaceProvider};
+/// use cymrust::Stage;
+///
+/// let query = "AS64500.asn.cymru.com.";
+/// let record = ["64500 | US | arin | 2010-01-01 | EXAMPLE - Example Inc., US"];
+/// let slow = MockProvider::new()
+///     .answer(query, Duration::from_secs(60), &record)
+///     .delay(query, Duration::from_millis(200));
+/// let fast = MockProvider::new().answer(query, Duration::from_secs(60), &record);
+///
+/// let race = RaceProvider::new(slow).against(fast);
+/// let answer = race.txt(query, Stage::Asn).unwrap();
+/// assert_eq!(answer.answered_by, Some("mock"));
+/// ```
+///
+pub struct RaceProvider {
+    providers: Vec<Arc<dyn Provider>>,
+    race_threads: RaceThreads,
+}
+
+impl RaceProvider {
+    /// Create race with `provider` as the only contestant so far
+    ///
+    pub fn new<P: Provider + 'static>(provider: P) -> Self {
+        RaceProvider {
+            providers: vec![Arc::new(provider)],
+            race_threads: RaceThreads::default(),
+        }
+    }
+
+    /// Race queries against `provider` too
+    ///
+    pub fn against<P: Provider + 'static>(mut self, provider: P) -> Self {
+        self.providers.push(Arc::new(provider));
+        self
+    }
+
+    /// Do `lookup` with every provider at once and return the first success,
+    /// along with the name of the provider giving it
+    ///
+    fn race<T, F>(&self, lookup: F) -> Result<(T, &'static str), Error>
+    where
+        T: Send + 'static,
+        F: Fn(&dyn Provider) -> Result<T, Error> + Send + Sync + 'static,
+    {
+        race(&self.providers, &self.race_threads, move |provider| {
+            lookup(provider).map(|answer| (answer, provider.name()))
+        })
+    }
+}
+
+impl Provider for RaceProvider {
+    fn name(&self) -> &'static str {
+        "race"
+    }
+
+    /// Whether any of the providers does network I/O
+    fn is_network(&self) -> bool {
+        self.providers.iter().any(|provider| provider.is_network())
+    }
+
+    fn txt(&self, query: &str, stage: Stage) -> Result<TxtAnswer, Error> {
+        let query = query.to_string();
+        let (mut answer, winner) = self.race(move |provider| provider.txt(&query, stage))?;
+        answer.answered_by.get_or_insert(winner);
+        Ok(answer)
+    }
+
+    fn ptr(&self, query: &str, stage: Stage) -> Result<Vec<String>, Error> {
+        let query = query.to_string();
+        self.race(move |provider| provider.ptr(&query, stage))
+            .map(|(names, _)| names)
+    }
+
+    fn addrs(&self, query: &str, stage: Stage) -> Result<Vec<IpAddr>, Error> {
+        let query = query.to_string();
+        self.race(move |provider| provider.addrs(&query, stage))
+            .map(|(addrs, _)| addrs)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::str::FromStr;
+    use std::sync::atomic::Ordering;
+    use std::sync::{mpsc, Arc};
+    use std::time::{Duration, Instant};
+
+    use trust_dns_resolver::proto::rr::rdata::TXT;
+    use trust_dns_resolver::proto::rr::{Name, RData, Record};
+
+    use super::{
+        canonical_name, race, MockProvider, Provider, RaceProvider, RaceThreads, TxtJoin,
+        MAX_RACE_THREADS,
+    };
+    use crate::{Error, Stage};
+
+    #[test]
+    fn test_canonical_name() {
+        let name = |name| Name::from_str(name).unwrap();
+        let cname = |alias, target| Record::from_rdata(name(alias), 60, RData::CNAME(name(target)));
+        let txt = |owner| {
+            let data = TXT::new(vec![
+                "64500 | 192.0.2.0/24 | US | arin | 2010-01-01".to_string()
+            ]);
+            Record::from_rdata(name(owner), 60, RData::TXT(data))
+        };
+        let query = name("1.2.0.192.origin.asn.cymru.com.");
+
+        let chain = [
+            cname("1.2.0.192.origin.asn.cymru.com.", "alias.example.net."),
+            cname("alias.example.net.", "origin.example.net."),
+            txt("origin.example.net."),
+        ];
+        assert_eq!(
+            canonical_name(&chain, &query),
+            Some(name("origin.example.net."))
+        );
+        assert_eq!(
+            canonical_name(&[txt("1.2.0.192.origin.asn.cymru.com.")], &query),
+            None
+        );
+        assert_eq!(canonical_name(&chain[..2], &query), None);
+    }
+
+    #[test]
+    fn test_txt_join() {
+        let strings: Vec<Box<[u8]>> = vec![
+            b"64500 | US | arin | 2010-01-01 | EXA".to_vec().into(),
+            b"MPLE \xc3".to_vec().into(),
+            b"\xa4 Example, FI".to_vec().into(),
+        ];
+        let join = |txt_join: TxtJoin, strings: &[Box<[u8]>]| {
+            let mut joined = Vec::new();
+            txt_join.join(strings, &mut Vec::new(), |data| joined.push(data.to_vec()));
+            joined
+        };
+        assert_eq!(
+            join(TxtJoin::Concat, &strings),
+            vec!["64500 | US | arin | 2010-01-01 | EXAMPLE \u{e4} Example, FI".as_bytes()]
+        );
+        assert_eq!(join(TxtJoin::Split, &strings).len(), 3);
+
+        let words: Vec<Box<[u8]>> = vec![b"a".to_vec().into(), b"b".to_vec().into()];
+        assert_eq!(join(TxtJoin::Space, &words), vec![b"a b".to_vec()]);
+        assert_eq!(join(TxtJoin::Space, &words[..1]), vec![b"a".to_vec()]);
+        assert_eq!(TxtJoin::default(), TxtJoin::Concat);
+    }
+
+    #[test]
+    fn test_mock_provider() {
+        let mock = MockProvider::new()
+            .answer("a.", Duration::from_secs(5), &["x", "y"])
+            .fail("b.", "SERVFAIL")
+            .delay("a.", Duration::from_millis(20));
+
+        let started = Instant::now();
+        let answer = mock.txt("a.", Stage::Asn).unwrap();
+        assert!(started.elapsed() >= Duration::from_millis(20));
+        assert_eq!(answer.ttl, Duration::from_secs(5));
+        assert_eq!(answer.records.iter().collect::<Vec<_>>(), ["x", "y"]);
+
+        match mock.txt("b.", Stage::Origin) {
+            Err(Error::Resolver { query, stage, .. }) => {
+                assert_eq!(query, "b.");
+                assert_eq!(stage, Stage::Origin);
+            }
+            other => panic!("unexpected {:?}", other),
+        }
+
+        match mock.txt("c.", Stage::Asn) {
+            Err(Error::NoResultsFound { query, .. }) => assert_eq!(query, "c."),
+            other => panic!("unexpected {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_race_thread_limit() {
+        let first = MockProvider::new().fail("a.", "SERVFAIL");
+        let second = MockProvider::new().answer("a.", Duration::from_secs(5), &["second"]);
+        let candidates = [Arc::new(first), Arc::new(second)];
+        let threads = RaceThreads::default();
+
+        // Without threads to spare, only the first candidate is asked
+        threads.0.store(MAX_RACE_THREADS - 1, Ordering::SeqCst);
+        assert!(race(&candidates, &threads, |mock| mock.txt("a.", Stage::Asn)).is_err());
+        assert_eq!(threads.0.load(Ordering::SeqCst), MAX_RACE_THREADS - 1);
+
+        threads.0.store(MAX_RACE_THREADS - 2, Ordering::SeqCst);
+        let answer = race(&candidates, &threads, |mock| mock.txt("a.", Stage::Asn)).unwrap();
+        assert_eq!(answer.records.get(0), Some("second"));
+    }
+
+    #[test]
+    fn test_race_provider() {
+        let (release, held) = mpsc::channel();
+        let slow = MockProvider::new()
+            .answer("a.", Duration::from_secs(5), &["slow"])
+            .hold("a.", held)
+            .answer("b.", Duration::from_secs(5), &["slow"]);
+        let fast = MockProvider::new()
+            .answer("a.", Duration::from_secs(5), &["fast"])
+            .fail("b.", "SERVFAIL");
+        let race = RaceProvider::new(slow).against(fast);
+        assert!(!race.is_network());
+
+        // The slow answer is held until the race is over
+        let answer = race.txt("a.", Stage::Asn).unwrap();
+        assert_eq!(answer.records.get(0), Some("fast"));
+        drop(release);
+
+        // Failures don't win, however fast
+        let answer = race.txt("b.", Stage::Asn).unwrap();
+        assert_eq!(answer.records.get(0), Some("slow"));
+        assert_eq!(answer.answered_by, Some("mock"));
+
+        match race.txt("c.", Stage::Asn) {
+            Err(Error::NoResultsFound { query, .. }) => assert_eq!(query, "c."),
+            other => panic!("unexpected {:?}", other),
+        }
+    }
+}
@@ -0,0 +1,343 @@
+//! Aggregate many IP-to-ASN results into a ranked summary
+//!
+//! This is the "who is hitting my firewall" summary: given a pile of
+//! [`CymruIP2ASN`](../struct.CymruIP2ASN.html) results, count how many of
+//! them belong to each AS number, country and registry.
+//!
+//! For pivoting on the results themselves rather than counts,
+//! [`index`](fn.index.html) groups them by AS number and BGP prefix.
+
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fmt;
+use std::net::IpAddr;
+
+use crate::{AsNumber, CymruIP2ASN};
+
+/// Per AS number tally
+///
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "schemars", derive(schemars::JsonSchema))]
+pub struct AsnTally {
+    /// Autonomous System (AS) number
+    pub as_number: AsNumber,
+    /// Autonomous System (AS) description
+    pub as_name: String,
+    /// Number of results originated by this AS
+    pub count: usize,
+    /// Number of distinct IP addresses among those results
+    pub unique_ips: usize,
+}
+
+/// Tally of results sharing a key, e.g. country code or registry
+///
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "schemars", derive(schemars::JsonSchema))]
+pub struct Tally {
+    /// Value the results were grouped by
+    pub key: String,
+    /// Number of results with this value
+    pub count: usize,
+    /// Number of distinct IP addresses among those results
+    pub unique_ips: usize,
+}
+
+/// Ranked summary of IP-to-ASN results
+///
+/// All lists are sorted by count, most common first. Ties are broken by key
+/// so that the same input always produces the same report.
+///
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "schemars", derive(schemars::JsonSchema))]
+pub struct Report {
+    /// Total number of results summarized
+    pub total: usize,
+    /// Number of distinct IP addresses among all results
+    pub unique_ips: usize,
+    /// Result counts per AS number
+    pub by_asn: Vec<AsnTally>,
+    /// Result counts per country code
+    pub by_country: Vec<Tally>,
+    /// Result counts per regional registry
+    pub by_registry: Vec<Tally>,
+}
+
+/// Build a ranked [`Report`](struct.Report.html) from IP-to-ASN results
+///
+/// Note that one IP address can map to several results when it is
+/// originated by more than one AS, in which case it is counted once per
+/// origin. The `unique_ips` tallies count every address only once, which
+/// also makes it safe to summarize results with repeated addresses, e.g.
+/// from log data.
+///
+pub fn report<'a, I>(results: I) -> Report
+where
+    I: IntoIterator<Item = &'a CymruIP2ASN>,
+{
+    let mut total = 0;
+    let mut ips: HashSet<IpAddr> = HashSet::new();
+    let mut asns: HashMap<AsNumber, (&str, Counts)> = HashMap::new();
+    let mut countries: HashMap<&str, Counts> = HashMap::new();
+    let mut registries: HashMap<&str, Counts> = HashMap::new();
+
+    for result in results {
+        total += 1;
+        ips.insert(result.ip_addr);
+        asns.entry(result.as_number)
+            .or_insert_with(|| (&result.as_name, Counts::default()))
+            .1
+            .add(result.ip_addr);
+        countries
+            .entry(&result.country_code)
+            .or_default()
+            .add(result.ip_addr);
+        registries
+            .entry(&result.registry)
+            .or_default()
+            .add(result.ip_addr);
+    }
+
+    let mut by_asn: Vec<AsnTally> = asns
+        .into_iter()
+        .map(|(as_number, (as_name, counts))| AsnTally {
+            as_number,
+            as_name: as_name.to_string(),
+            count: counts.count,
+            unique_ips: counts.ips.len(),
+        })
+        .collect();
+    by_asn.sort_by(|a, b| b.count.cmp(&a.count).then(a.as_number.cmp(&b.as_number)));
+
+    Report {
+        total,
+        unique_ips: ips.len(),
+        by_asn,
+        by_country: ranked(countries),
+        by_registry: ranked(registries),
+    }
+}
+
+/// IP-to-ASN results grouped by AS number and by BGP prefix
+///
+/// Built with [`index`](fn.index.html). Groups keep the results in input
+/// order, and the maps iterate in key order, so the same input always
+/// indexes the same way.
+///
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct Index<'a> {
+    /// Results per origin AS number
+    pub by_asn: BTreeMap<AsNumber, Vec<&'a CymruIP2ASN>>,
+    /// Results per BGP prefix
+    pub by_prefix: BTreeMap<&'a str, Vec<&'a CymruIP2ASN>>,
+}
+
+/// Build an [`Index`](struct.Index.html) of IP-to-ASN results, borrowing them
+///
+/// Results of a bulk lookup are indexed by flattening them first:
+///
+/// ```
+/// use cymrust::report::index;
+/// use cymrust::{fixtures, CymruClient};
+///
+/// let client = CymruClient::with_provider(fixtures::mock_provider());
+/// let ips = [fixtures::GOOGLE_IP, fixtures::MULTI_ORIGIN_IP, "9.9.9.9"];
+/// let results = client.ip2asn_many(&ips.map(|ip| ip.parse().unwrap()));
+///
+/// let index = index(results.iter().flatten().flatten());
+/// assert_eq!(index.by_asn[&15169].len(), 1);
+/// assert_eq!(index.by_prefix["198.51.100.0/24"].len(), 2);
+/// ```
+///
+pub fn index<'a, I>(results: I) -> Index<'a>
+where
+    I: IntoIterator<Item = &'a CymruIP2ASN>,
+{
+    let mut index = Index::default();
+    for result in results {
+        index
+            .by_asn
+            .entry(result.as_number)
+            .or_default()
+            .push(result);
+        index
+            .by_prefix
+            .entry(result.bgp_prefix.as_str())
+            .or_default()
+            .push(result);
+    }
+    index
+}
+
+/// Result count and distinct addresses for one key
+///
+#[derive(Default)]
+struct Counts {
+    count: usize,
+    ips: HashSet<IpAddr>,
+}
+
+impl Counts {
+    fn add(&mut self, ip: IpAddr) {
+        self.count += 1;
+        self.ips.insert(ip);
+    }
+}
+
+/// Turn key counts into tallies sorted by count descending, then key
+///
+fn ranked(counts: HashMap<&str, Counts>) -> Vec<Tally> {
+    let mut tallies: Vec<Tally> = counts
+        .into_iter()
+        .map(|(key, counts)| Tally {
+            key: key.to_string(),
+            count: counts.count,
+            unique_ips: counts.ips.len(),
+        })
+        .collect();
+    tallies.sort_by(|a, b| b.count.cmp(&a.count).then_with(|| a.key.cmp(&b.key)));
+    tallies
+}
+
+impl fmt::Display for Report {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "{:>8} | {:>8} | {:<8}| AS Name", "Count", "IPs", "AS")?;
+        for tally in &self.by_asn {
+            writeln!(
+                f,
+                "{:>8} | {:>8} | {:<8}| {}",
+                tally.count, tally.unique_ips, tally.as_number, tally.as_name
+            )?;
+        }
+        writeln!(f)?;
+        writeln!(f, "{:>8} | {:>8} | CC", "Count", "IPs")?;
+        for tally in &self.by_country {
+            writeln!(
+                f,
+                "{:>8} | {:>8} | {}",
+                tally.count, tally.unique_ips, tally.key
+            )?;
+        }
+        writeln!(f)?;
+        writeln!(f, "{:>8} | {:>8} | Registry", "Count", "IPs")?;
+        for tally in &self.by_registry {
+            writeln!(
+                f,
+                "{:>8} | {:>8} | {}",
+                tally.count, tally.unique_ips, tally.key
+            )?;
+        }
+        write!(f, "{:>8} | {:>8} | Total", self.total, self.unique_ips)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::SystemTime;
+
+    use super::{index, report};
+    use crate::{AsNumber, CymruIP2ASN, Source};
+
+    fn result(ip: &str, as_number: AsNumber, country_code: &str, registry: &str) -> CymruIP2ASN {
+        CymruIP2ASN {
+            ip_addr: ip.parse().unwrap(),
+            bgp_prefix: "192.0.2.0/24".to_string(),
+            as_number,
+            as_name: format!("AS{}", as_number),
+            country_code: country_code.to_string(),
+            registry: registry.to_string(),
+            allocated: None,
+            expires: SystemTime::now(),
+            anomalous_origins: None,
+            source: Source::Unknown,
+        }
+    }
+
+    #[test]
+    fn test_report_ranking() {
+        let results = vec![
+            result("192.0.2.1", 64500, "US", "arin"),
+            result("192.0.2.2", 64501, "FI", "ripencc"),
+            result("192.0.2.3", 64501, "FI", "ripencc"),
+            result("192.0.2.4", 64502, "US", "arin"),
+        ];
+        let report = report(&results);
+        assert_eq!(report.total, 4);
+
+        let asns: Vec<(AsNumber, usize)> = report
+            .by_asn
+            .iter()
+            .map(|t| (t.as_number, t.count))
+            .collect();
+        assert_eq!(asns, vec![(64501, 2), (64500, 1), (64502, 1)]);
+        assert_eq!(report.by_asn[0].as_name, "AS64501");
+
+        let countries: Vec<(&str, usize)> = report
+            .by_country
+            .iter()
+            .map(|t| (t.key.as_str(), t.count))
+            .collect();
+        assert_eq!(countries, vec![("FI", 2), ("US", 2)]);
+        assert_eq!(report.by_registry[0].key, "arin");
+    }
+
+    #[test]
+    fn test_report_unique_ips() {
+        let results = vec![
+            result("192.0.2.1", 64500, "US", "arin"),
+            result("192.0.2.1", 64500, "US", "arin"),
+            result("192.0.2.1", 64501, "US", "arin"),
+            result("192.0.2.2", 64500, "US", "arin"),
+        ];
+        let report = report(&results);
+        assert_eq!((report.total, report.unique_ips), (4, 2));
+        assert_eq!(
+            (report.by_asn[0].count, report.by_asn[0].unique_ips),
+            (3, 2)
+        );
+        assert_eq!(
+            (report.by_asn[1].count, report.by_asn[1].unique_ips),
+            (1, 1)
+        );
+        assert_eq!(
+            (report.by_country[0].count, report.by_country[0].unique_ips),
+            (4, 2)
+        );
+        assert!(report.to_string().ends_with("       4 |        2 | Total"));
+    }
+
+    #[test]
+    fn test_report_empty() {
+        let report = report(&[]);
+        assert_eq!(report.total, 0);
+        assert_eq!(report.unique_ips, 0);
+        assert!(report.by_asn.is_empty());
+        assert!(report.by_country.is_empty());
+        assert!(report.by_registry.is_empty());
+    }
+
+    #[test]
+    fn test_index() {
+        let mut results = vec![
+            result("192.0.2.1", 64500, "US", "arin"),
+            result("192.0.2.2", 64501, "FI", "ripencc"),
+            result("198.51.100.1", 64500, "US", "arin"),
+        ];
+        results[2].bgp_prefix = "198.51.100.0/24".to_string();
+        let index = index(&results);
+
+        assert_eq!(
+            index.by_asn.keys().collect::<Vec<_>>(),
+            vec![&64500, &64501]
+        );
+        assert_eq!(index.by_asn[&64500], vec![&results[0], &results[2]]);
+        assert_eq!(index.by_asn[&64501], vec![&results[1]]);
+        assert_eq!(
+            index.by_prefix["192.0.2.0/24"],
+            vec![&results[0], &results[1]]
+        );
+        assert_eq!(index.by_prefix["198.51.100.0/24"], vec![&results[2]]);
+        assert_eq!(super::index(&[]), Default::default());
+    }
+}
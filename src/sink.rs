@@ -0,0 +1,235 @@
+//! Writing IP-to-ASN results out in various formats
+//!
+//! Tools built on this crate write their results through the
+//! [`OutputSink`](trait.OutputSink.html) trait, so the lookup code doesn't
+//! need to know the output format. [`JsonlSink`](struct.JsonlSink.html)
+//! writes one JSON object per line and [`CsvSink`](struct.CsvSink.html) CSV
+//! with a header row; other formats are added by implementing the trait.
+//!
+//! ```
+//! use cymrust::fixtures;
+//! use cymrust::sink::{CsvSink, OutputSink};
+//!
+//! let mut sink = CsvSink::new(Vec::new());
+//! sink.write(&fixtures::google()).unwrap();
+//! sink.finalize().unwrap();
+//! let csv = String::from_utf8(sink.into_inner()).unwrap();
+//! assert!(csv.starts_with("ip_addr,bgp_prefix,as_number,"));
+//! ```
+
+use std::fmt::Write as _;
+use std::io::{self, Write};
+use std::time::SystemTime;
+
+use chrono::{DateTime, SecondsFormat, Utc};
+
+use crate::audit::json_string;
+use crate::CymruIP2ASN;
+
+/// Destination for IP-to-ASN results
+///
+pub trait OutputSink {
+    /// Write a single `result`
+    fn write(&mut self, result: &CymruIP2ASN) -> io::Result<()>;
+
+    /// Flush buffered output, e.g. between batches of results
+    fn flush(&mut self) -> io::Result<()>;
+
+    /// Finish output after the last result
+    ///
+    /// Sinks with a trailer, or which write everything at once, do it here.
+    /// By default this only flushes.
+    ///
+    fn finalize(&mut self) -> io::Result<()> {
+        self.flush()
+    }
+}
+
+/// Format `expires` as RFC 3339 timestamp in UTC
+///
+fn timestamp(expires: SystemTime) -> String {
+    DateTime::<Utc>::from(expires).to_rfc3339_opts(SecondsFormat::Secs, true)
+}
+
+/// Sink writing one JSON object per line (JSON Lines)
+///
+/// Objects have the fields of [`CymruIP2ASN`](../struct.CymruIP2ASN.html),
+/// with `expires` as an RFC 3339 timestamp, `anomalous_origins` reduced
+/// to a boolean and `source` as its display name, e.g. `cymru-dns`.
+///
+pub struct JsonlSink<W> {
+    writer: W,
+}
+
+impl<W: Write> JsonlSink<W> {
+    /// Write JSON lines to `writer`
+    ///
+    pub fn new(writer: W) -> Self {
+        JsonlSink { writer }
+    }
+
+    /// Unwrap the underlying writer
+    ///
+    pub fn into_inner(self) -> W {
+        self.writer
+    }
+}
+
+impl<W: Write> OutputSink for JsonlSink<W> {
+    fn write(&mut self, result: &CymruIP2ASN) -> io::Result<()> {
+        let mut line = String::new();
+        let _ = write!(
+            line,
+            "{{\"ip_addr\":{},\"bgp_prefix\":{},\"as_number\":{},\"as_name\":{},\
+             \"country_code\":{},\"registry\":{},\"allocated\":{},\"expires\":{},\
+             \"anomalous_origins\":{},\"source\":{}}}",
+            json_string(&result.ip_addr.to_string()),
+            json_string(&result.bgp_prefix),
+            result.as_number,
+            json_string(&result.as_name),
+            json_string(&result.country_code),
+            json_string(&result.registry),
+            result
+                .allocated
+                .as_deref()
+                .map_or_else(|| "null".to_string(), json_string),
+            json_string(&timestamp(result.expires)),
+            result.anomalous_origins.is_some(),
+            json_string(&result.source.to_string()),
+        );
+        writeln!(self.writer, "{}", line)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.writer.flush()
+    }
+}
+
+/// Header row written by [`CsvSink`](struct.CsvSink.html)
+///
+const CSV_HEADER: &str =
+    "ip_addr,bgp_prefix,as_number,as_name,country_code,registry,allocated,expires,anomalous_origins,source";
+
+/// Sink writing CSV with a header row, as described in RFC 4180
+///
+/// Columns are those of [`JsonlSink`](struct.JsonlSink.html) objects. Unknown
+/// allocation dates are left empty.
+///
+pub struct CsvSink<W> {
+    writer: W,
+    header_written: bool,
+}
+
+impl<W: Write> CsvSink<W> {
+    /// Write CSV to `writer`
+    ///
+    pub fn new(writer: W) -> Self {
+        CsvSink {
+            writer,
+            header_written: false,
+        }
+    }
+
+    /// Unwrap the underlying writer
+    ///
+    pub fn into_inner(self) -> W {
+        self.writer
+    }
+
+    fn write_header(&mut self) -> io::Result<()> {
+        if !self.header_written {
+            write!(self.writer, "{}\r\n", CSV_HEADER)?;
+            self.header_written = true;
+        }
+        Ok(())
+    }
+}
+
+/// Quote CSV `field` if it contains separators, quotes or line breaks
+///
+fn csv_field(field: &str) -> String {
+    if field.contains([',', '"', '\r', '\n']) {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    } else {
+        field.to_string()
+    }
+}
+
+impl<W: Write> OutputSink for CsvSink<W> {
+    fn write(&mut self, result: &CymruIP2ASN) -> io::Result<()> {
+        self.write_header()?;
+        write!(
+            self.writer,
+            "{},{},{},{},{},{},{},{},{},{}\r\n",
+            result.ip_addr,
+            csv_field(&result.bgp_prefix),
+            result.as_number,
+            csv_field(&result.as_name),
+            csv_field(&result.country_code),
+            csv_field(&result.registry),
+            csv_field(result.allocated.as_deref().unwrap_or("")),
+            timestamp(result.expires),
+            result.anomalous_origins.is_some(),
+            csv_field(&result.source.to_string()),
+        )
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.writer.flush()
+    }
+
+    /// Write the header even if there were no results, then flush
+    fn finalize(&mut self) -> io::Result<()> {
+        self.write_header()?;
+        self.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{CsvSink, JsonlSink, OutputSink, CSV_HEADER};
+    use crate::fixtures;
+
+    #[test]
+    fn test_jsonl_sink() {
+        let mut sink = JsonlSink::new(Vec::new());
+        let mut unallocated = fixtures::cloudflare();
+        unallocated.allocated = None;
+        for result in fixtures::multi_origin().iter().chain(Some(&unallocated)) {
+            sink.write(result).unwrap();
+        }
+        sink.finalize().unwrap();
+
+        let output = String::from_utf8(sink.into_inner()).unwrap();
+        let lines: Vec<serde_json::Value> = output
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        assert_eq!(lines.len(), 3);
+        assert_eq!(lines[0]["as_number"], 64496);
+        assert_eq!(lines[0]["as_name"], "EXAMPLE-ONE - Example One Inc., US");
+        assert_eq!(lines[0]["anomalous_origins"], true);
+        assert_eq!(lines[2]["allocated"], serde_json::Value::Null);
+        assert_eq!(lines[2]["anomalous_origins"], false);
+        assert_eq!(lines[2]["source"], "mock");
+    }
+
+    #[test]
+    fn test_csv_sink() {
+        let mut sink = CsvSink::new(Vec::new());
+        sink.write(&fixtures::google()).unwrap();
+        sink.finalize().unwrap();
+        let output = String::from_utf8(sink.into_inner()).unwrap();
+        let lines: Vec<&str> = output.lines().collect();
+        assert_eq!(lines[0], CSV_HEADER);
+        assert!(lines[1].starts_with("8.8.8.8,8.8.8.0/24,15169,\"GOOGLE, US\",US,arin,1992-12-01,"));
+        assert!(lines[1].ends_with(",false,mock"));
+
+        let mut empty = CsvSink::new(Vec::new());
+        empty.finalize().unwrap();
+        assert_eq!(
+            empty.into_inner(),
+            format!("{}\r\n", CSV_HEADER).into_bytes()
+        );
+    }
+}
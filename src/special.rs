@@ -0,0 +1,310 @@
+//! IANA special-purpose address registries
+//!
+//! Addresses such as loopback, link-local, private-use or documentation
+//! ranges are never routed on the Internet, so Cymru has nothing to say
+//! about them. [`special_purpose`](fn.special_purpose.html) recognizes them
+//! locally, and [`CymruClient`](../struct.CymruClient.html) uses it to fail
+//! lookups of such addresses with `Error::NonRoutableInput` without sending
+//! a query. Checking never allocates, and most addresses are ruled out by a
+//! single bitmap lookup, so it is cheap enough for high-volume pipelines.
+//!
+//! The tables follow IANA's
+//! [IPv4](https://www.iana.org/assignments/iana-ipv4-special-registry/) and
+//! [IPv6](https://www.iana.org/assignments/iana-ipv6-special-registry/)
+//! special-purpose address registries, plus the IPv4 and IPv6 multicast
+//! ranges. Blocks which are only reachable by virtue of a more specific
+//! assignment, such as 192.0.0.0/24 and 2001::/23, are treated as not
+//! globally reachable, and the reachable assignments inside them are listed
+//! on their own.
+
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+
+/// Special-purpose address block an address belongs to
+///
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct SpecialPurpose {
+    /// Name of the block in IANA's registry, e.g. "Private-Use"
+    pub name: &'static str,
+    /// Document defining the block, e.g. "RFC 1918"
+    pub rfc: &'static str,
+    /// Whether addresses in the block are routed on the Internet
+    pub globally_reachable: bool,
+}
+
+struct Block {
+    addr: IpAddr,
+    len: u8,
+    purpose: SpecialPurpose,
+}
+
+const fn v4(a: u8, b: u8, c: u8, d: u8, len: u8, name: &'static str, rfc: &'static str) -> Block {
+    block(IpAddr::V4(Ipv4Addr::new(a, b, c, d)), len, name, rfc, false)
+}
+
+const fn v6(segments: [u16; 8], len: u8, name: &'static str, rfc: &'static str) -> Block {
+    let [a, b, c, d, e, f, g, h] = segments;
+    block(
+        IpAddr::V6(Ipv6Addr::new(a, b, c, d, e, f, g, h)),
+        len,
+        name,
+        rfc,
+        false,
+    )
+}
+
+const fn block(
+    addr: IpAddr,
+    len: u8,
+    name: &'static str,
+    rfc: &'static str,
+    globally_reachable: bool,
+) -> Block {
+    Block {
+        addr,
+        len,
+        purpose: SpecialPurpose {
+            name,
+            rfc,
+            globally_reachable,
+        },
+    }
+}
+
+const fn global(mut block: Block) -> Block {
+    block.purpose.globally_reachable = true;
+    block
+}
+
+#[rustfmt::skip]
+const BLOCKS: &[Block] = &[
+    v4(0, 0, 0, 0, 8, "This network", "RFC 791"),
+    v4(0, 0, 0, 0, 32, "This host on this network", "RFC 1122"),
+    v4(10, 0, 0, 0, 8, "Private-Use", "RFC 1918"),
+    v4(100, 64, 0, 0, 10, "Shared Address Space", "RFC 6598"),
+    v4(127, 0, 0, 0, 8, "Loopback", "RFC 1122"),
+    v4(169, 254, 0, 0, 16, "Link Local", "RFC 3927"),
+    v4(172, 16, 0, 0, 12, "Private-Use", "RFC 1918"),
+    v4(192, 0, 0, 0, 24, "IETF Protocol Assignments", "RFC 6890"),
+    v4(192, 0, 0, 0, 29, "IPv4 Service Continuity Prefix", "RFC 7335"),
+    v4(192, 0, 0, 8, 32, "IPv4 dummy address", "RFC 7600"),
+    global(v4(192, 0, 0, 9, 32, "Port Control Protocol Anycast", "RFC 7723")),
+    global(v4(192, 0, 0, 10, 32, "Traversal Using Relays around NAT Anycast", "RFC 8155")),
+    v4(192, 0, 0, 170, 32, "NAT64/DNS64 Discovery", "RFC 8880"),
+    v4(192, 0, 0, 171, 32, "NAT64/DNS64 Discovery", "RFC 8880"),
+    v4(192, 0, 2, 0, 24, "Documentation (TEST-NET-1)", "RFC 5737"),
+    global(v4(192, 31, 196, 0, 24, "AS112-v4", "RFC 7535")),
+    global(v4(192, 52, 193, 0, 24, "AMT", "RFC 7450")),
+    v4(192, 168, 0, 0, 16, "Private-Use", "RFC 1918"),
+    global(v4(192, 175, 48, 0, 24, "Direct Delegation AS112 Service", "RFC 7534")),
+    v4(198, 18, 0, 0, 15, "Benchmarking", "RFC 2544"),
+    v4(198, 51, 100, 0, 24, "Documentation (TEST-NET-2)", "RFC 5737"),
+    v4(203, 0, 113, 0, 24, "Documentation (TEST-NET-3)", "RFC 5737"),
+    v4(240, 0, 0, 0, 4, "Reserved", "RFC 1112"),
+    v4(255, 255, 255, 255, 32, "Limited Broadcast", "RFC 919"),
+    // Not special-purpose as such, but never unicast routed either
+    v4(224, 0, 0, 0, 4, "Multicast", "RFC 5771"),
+
+    v6([0, 0, 0, 0, 0, 0, 0, 1], 128, "Loopback Address", "RFC 4291"),
+    v6([0, 0, 0, 0, 0, 0, 0, 0], 128, "Unspecified Address", "RFC 4291"),
+    v6([0, 0, 0, 0, 0, 0xffff, 0, 0], 96, "IPv4-mapped Address", "RFC 4291"),
+    global(v6([0x64, 0xff9b, 0, 0, 0, 0, 0, 0], 96, "IPv4-IPv6 Translat.", "RFC 6052")),
+    v6([0x64, 0xff9b, 1, 0, 0, 0, 0, 0], 48, "IPv4-IPv6 Translat.", "RFC 8215"),
+    v6([0x100, 0, 0, 0, 0, 0, 0, 0], 64, "Discard-Only Address Block", "RFC 6666"),
+    v6([0x2001, 0, 0, 0, 0, 0, 0, 0], 23, "IETF Protocol Assignments", "RFC 2928"),
+    global(v6([0x2001, 0, 0, 0, 0, 0, 0, 0], 32, "TEREDO", "RFC 4380")),
+    global(v6([0x2001, 1, 0, 0, 0, 0, 0, 1], 128, "Port Control Protocol Anycast", "RFC 7723")),
+    global(v6([0x2001, 1, 0, 0, 0, 0, 0, 2], 128, "Traversal Using Relays around NAT Anycast", "RFC 8155")),
+    v6([0x2001, 2, 0, 0, 0, 0, 0, 0], 48, "Benchmarking", "RFC 5180"),
+    global(v6([0x2001, 3, 0, 0, 0, 0, 0, 0], 32, "AMT", "RFC 7450")),
+    global(v6([0x2001, 4, 0x112, 0, 0, 0, 0, 0], 48, "AS112-v6", "RFC 7535")),
+    global(v6([0x2001, 0x20, 0, 0, 0, 0, 0, 0], 28, "ORCHIDv2", "RFC 7343")),
+    v6([0x2001, 0xdb8, 0, 0, 0, 0, 0, 0], 32, "Documentation", "RFC 3849"),
+    global(v6([0x2002, 0, 0, 0, 0, 0, 0, 0], 16, "6to4", "RFC 3056")),
+    global(v6([0x2620, 0x4f, 0x8000, 0, 0, 0, 0, 0], 48, "Direct Delegation AS112 Service", "RFC 7534")),
+    v6([0x3fff, 0, 0, 0, 0, 0, 0, 0], 20, "Documentation", "RFC 9637"),
+    v6([0x5f00, 0, 0, 0, 0, 0, 0, 0], 16, "Segment Routing (SRv6) SIDs", "RFC 9602"),
+    v6([0xfc00, 0, 0, 0, 0, 0, 0, 0], 7, "Unique-Local", "RFC 4193"),
+    v6([0xfe80, 0, 0, 0, 0, 0, 0, 0], 10, "Link-Local Unicast", "RFC 4291"),
+    v6([0xff00, 0, 0, 0, 0, 0, 0, 0], 8, "Multicast", "RFC 4291"),
+];
+
+/// Bitmap of the leading `width` address bits, 8 for IPv4 and 16 for IPv6,
+/// of all `BLOCKS` of the `v6` family
+///
+/// Most addresses, and all routable ones outside a few blocks, have leading
+/// bits no block covers. Checking this first rules them out with a single
+/// lookup instead of scanning the tables.
+///
+const fn index<const WORDS: usize>(v6: bool) -> [u64; WORDS] {
+    let mut map = [0; WORDS];
+    let mut i = 0;
+    while i < BLOCKS.len() {
+        let block = &BLOCKS[i];
+        i += 1;
+        let (top, width) = match block.addr {
+            IpAddr::V4(addr) if !v6 => (addr.octets()[0] as usize, 8),
+            IpAddr::V6(addr) if v6 => (addr.segments()[0] as usize, 16),
+            _ => continue,
+        };
+        let span = if block.len < width {
+            1 << (width - block.len)
+        } else {
+            1
+        };
+        let mut value = top & !(span - 1);
+        while value < (top & !(span - 1)) + span {
+            map[value / 64] |= 1 << (value % 64);
+            value += 1;
+        }
+    }
+    map
+}
+
+static V4_INDEX: [u64; 4] = index(false);
+static V6_INDEX: [u64; 1024] = index(true);
+
+/// Whether some block may contain `ip`, judging by its leading bits only
+///
+fn may_be_special(ip: IpAddr) -> bool {
+    let (index, top): (&[u64], usize) = match ip {
+        IpAddr::V4(ip) => (&V4_INDEX, ip.octets()[0].into()),
+        IpAddr::V6(ip) => (&V6_INDEX, ip.segments()[0].into()),
+    };
+    index[top / 64] & (1 << (top % 64)) != 0
+}
+
+/// Most specific special-purpose block containing `ip`, if any
+///
+/// ```
+/// use cymrust::special::special_purpose;
+///
+/// let purpose = special_purpose("10.1.2.3".parse().unwrap()).unwrap();
+/// assert_eq!(purpose.name, "Private-Use");
+/// assert!(!purpose.globally_reachable);
+/// assert_eq!(special_purpose("8.8.8.8".parse().unwrap()), None);
+/// ```
+///
+pub fn special_purpose(ip: IpAddr) -> Option<SpecialPurpose> {
+    if !may_be_special(ip) {
+        return None;
+    }
+    BLOCKS
+        .iter()
+        .filter(|block| contains(block.addr, block.len, ip))
+        .max_by_key(|block| block.len)
+        .map(|block| block.purpose)
+}
+
+/// Whether `ip` can be routed on the Internet, and so be looked up
+///
+/// This is false for private-use, unique-local, multicast, loopback and the
+/// other special-purpose addresses which are not globally reachable.
+///
+/// ```
+/// use cymrust::special::is_routable;
+///
+/// assert!(is_routable("8.8.8.8".parse().unwrap()));
+/// assert!(!is_routable("192.168.1.1".parse().unwrap()));
+/// assert!(!is_routable("ff02::1".parse().unwrap()));
+/// ```
+///
+pub fn is_routable(ip: IpAddr) -> bool {
+    special_purpose(ip).is_none_or(|purpose| purpose.globally_reachable)
+}
+
+/// Whether prefix `net`/`len` contains `ip`
+///
+pub(crate) fn contains(net: IpAddr, len: u8, ip: IpAddr) -> bool {
+    match (net, ip) {
+        (IpAddr::V4(net), IpAddr::V4(ip)) => {
+            let mask = u32::MAX.checked_shl(32 - u32::from(len)).unwrap_or(0);
+            u32::from(net) & mask == u32::from(ip) & mask
+        }
+        (IpAddr::V6(net), IpAddr::V6(ip)) => {
+            let mask = u128::MAX.checked_shl(128 - u32::from(len)).unwrap_or(0);
+            u128::from(net) & mask == u128::from(ip) & mask
+        }
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+
+    use super::{is_routable, may_be_special, special_purpose, BLOCKS};
+
+    fn name(ip: &str) -> Option<&'static str> {
+        special_purpose(ip.parse().unwrap()).map(|p| p.name)
+    }
+
+    #[test]
+    fn test_special_purpose() {
+        assert_eq!(name("0.0.0.0"), Some("This host on this network"));
+        assert_eq!(name("0.1.2.3"), Some("This network"));
+        assert_eq!(name("100.100.1.1"), Some("Shared Address Space"));
+        assert_eq!(name("172.31.255.255"), Some("Private-Use"));
+        assert_eq!(name("172.32.0.0"), None);
+        assert_eq!(name("192.0.0.9"), Some("Port Control Protocol Anycast"));
+        assert_eq!(name("255.255.255.255"), Some("Limited Broadcast"));
+        assert_eq!(name("1.1.1.1"), None);
+
+        assert_eq!(name("::1"), Some("Loopback Address"));
+        assert_eq!(name("::ffff:10.0.0.1"), Some("IPv4-mapped Address"));
+        assert_eq!(
+            name("2001:1::2"),
+            Some("Traversal Using Relays around NAT Anycast")
+        );
+        assert_eq!(name("2001:db8::1"), Some("Documentation"));
+        assert_eq!(name("fd00::1"), Some("Unique-Local"));
+        assert_eq!(name("fe80::1"), Some("Link-Local Unicast"));
+        assert_eq!(name("2606:4700::1111"), None);
+
+        let teredo = special_purpose("2001::1".parse().unwrap()).unwrap();
+        assert!(teredo.globally_reachable);
+
+        for ip in &["192.0.0.100", "2001:100::1"] {
+            let purpose = special_purpose(ip.parse().unwrap()).unwrap();
+            assert_eq!(purpose.name, "IETF Protocol Assignments", "{}", ip);
+            assert!(!purpose.globally_reachable, "{}", ip);
+        }
+    }
+
+    #[test]
+    fn test_is_routable() {
+        for ip in &[
+            "10.0.0.1",
+            "172.20.1.1",
+            "192.168.0.1",
+            "239.255.255.250",
+            "fd12:3456::1",
+            "ff05::2",
+        ] {
+            assert!(!is_routable(ip.parse().unwrap()), "{}", ip);
+        }
+        for ip in &[
+            "1.1.1.1",
+            "223.255.255.255",
+            "192.31.196.1",
+            "2606:4700::1111",
+        ] {
+            assert!(is_routable(ip.parse().unwrap()), "{}", ip);
+        }
+    }
+
+    #[test]
+    fn test_index() {
+        for block in BLOCKS {
+            assert!(may_be_special(block.addr), "{}/{}", block.addr, block.len);
+        }
+        for top in 0..=255u8 {
+            let ip = IpAddr::V4(Ipv4Addr::new(top, 1, 2, 3));
+            assert!(special_purpose(ip).is_none() || may_be_special(ip));
+        }
+        assert!(may_be_special("fd00::1".parse().unwrap()));
+        assert!(!may_be_special("8.8.8.8".parse().unwrap()));
+        assert!(!may_be_special(IpAddr::V6(Ipv6Addr::new(
+            0x2a00, 0x1450, 0, 0, 0, 0, 0, 1
+        ))));
+    }
+}
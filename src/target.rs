@@ -0,0 +1,508 @@
+//! Lookup targets given as strings
+
+use std::error;
+use std::fmt;
+use std::net::IpAddr;
+use std::str::FromStr;
+
+#[cfg(feature = "ipnet")]
+use ipnet::IpNet;
+
+use crate::client::bulk;
+use crate::{AsNumber, CymruASN, CymruClient, CymruIP2ASN, Error, Stage};
+
+/// Something to look up: an IP address, AS number, hostname or network
+///
+/// Parsing accepts IP address literals, AS numbers with an `AS` prefix in
+/// any case (e.g. `AS15169` or `as15169`), networks in CIDR notation with the
+/// `ipnet` feature, and DNS hostnames, tried in that order.
+///
+/// ```
+/// use cymrust::Target;
+///
+/// assert_eq!("8.8.8.8".parse(), Ok(Target::Ip("8.8.8.8".parse().unwrap())));
+/// assert_eq!("AS15169".parse(), Ok(Target::Asn(15169)));
+/// assert_eq!("dns.google".parse(), Ok(Target::Host("dns.google".to_string())));
+/// assert!("not a target".parse::<Target>().is_err());
+/// ```
+///
+/// More kinds of targets may be added, e.g. networks come with the `ipnet`
+/// feature, so matches need a wildcard arm.
+///
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+#[non_exhaustive]
+pub enum Target {
+    /// IP address
+    Ip(IpAddr),
+    /// Autonomous System (AS) number
+    Asn(AsNumber),
+    /// Hostname, looked up through its addresses
+    Host(String),
+    /// Network, looked up by its network address
+    #[cfg(feature = "ipnet")]
+    Net(IpNet),
+}
+
+/// Error parsing a [`Target`](enum.Target.html)
+///
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ParseTargetError {
+    input: String,
+}
+
+impl ParseTargetError {
+    /// String which failed to parse
+    ///
+    pub fn input(&self) -> &str {
+        &self.input
+    }
+}
+
+impl fmt::Display for ParseTargetError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "Not an IP address, AS number, network or hostname: {:?}",
+            self.input
+        )
+    }
+}
+
+impl error::Error for ParseTargetError {}
+
+impl FromStr for Target {
+    type Err = ParseTargetError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if let Ok(ip) = s.parse() {
+            return Ok(Target::Ip(ip));
+        }
+        let digits = s
+            .get(..2)
+            .filter(|prefix| prefix.eq_ignore_ascii_case("AS"))
+            .and_then(|_| s.get(2..))
+            .filter(|digits| !digits.is_empty() && digits.bytes().all(|b| b.is_ascii_digit()));
+        if let Some(digits) = digits {
+            return match digits.parse() {
+                Ok(asn) => Ok(Target::Asn(asn)),
+                Err(_) => Err(ParseTargetError {
+                    input: s.to_string(),
+                }),
+            };
+        }
+        #[cfg(feature = "ipnet")]
+        {
+            if let Ok(net) = s.parse() {
+                return Ok(Target::Net(net));
+            }
+        }
+        if is_hostname(s) {
+            return Ok(Target::Host(s.to_string()));
+        }
+        Err(ParseTargetError {
+            input: s.to_string(),
+        })
+    }
+}
+
+impl fmt::Display for Target {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Target::Ip(ip) => ip.fmt(f),
+            Target::Asn(asn) => write!(f, "AS{}", asn),
+            Target::Host(host) => host.fmt(f),
+            #[cfg(feature = "ipnet")]
+            Target::Net(net) => net.fmt(f),
+        }
+    }
+}
+
+impl From<IpAddr> for Target {
+    fn from(ip: IpAddr) -> Self {
+        Target::Ip(ip)
+    }
+}
+
+/// Whether `s` is a syntactically valid DNS hostname
+///
+/// Underscores are allowed, as they show up in practice. A trailing dot
+/// marks a fully qualified name. All-numeric top-level labels are refused so
+/// that mistyped addresses such as `1.2.3` don't pass as hostnames.
+///
+fn is_hostname(s: &str) -> bool {
+    let name = s.strip_suffix('.').unwrap_or(s);
+    !name.is_empty()
+        && name.len() <= 253
+        && !name
+            .rsplit('.')
+            .next()
+            .unwrap_or_default()
+            .bytes()
+            .all(|b| b.is_ascii_digit())
+        && name.split('.').all(|label| {
+            !label.is_empty()
+                && label.len() <= 63
+                && !label.starts_with('-')
+                && !label.ends_with('-')
+                && label
+                    .bytes()
+                    .all(|b| b.is_ascii_alphanumeric() || b == b'-' || b == b'_')
+        })
+}
+
+/// Results of looking up a [`Target`](enum.Target.html)
+///
+/// Networks are looked up by their network address, so they yield IP-to-ASN
+/// results just like addresses and hostnames do. More kinds of results may
+/// be added along with kinds of targets.
+///
+#[derive(Debug, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum TargetResults {
+    /// IP-to-ASN results of an IP address, or of all addresses of a hostname
+    Ip(Vec<CymruIP2ASN>),
+    /// AS number details
+    Asn(Vec<CymruASN>),
+}
+
+/// Outcome of looking up one of many targets
+///
+#[derive(Debug)]
+pub struct TargetLookup {
+    /// Target given as input
+    pub target: Target,
+    /// Results or error of looking up `target`
+    pub results: Result<TargetResults, Error>,
+}
+
+impl CymruClient {
+    /// Look up `target`, whatever kind it is
+    ///
+    /// See [`cymru_lookup`](fn.cymru_lookup.html) for details. A hostname's
+    /// addresses which have no results or are not routable are skipped; the
+    /// lookup fails only if none of them have results. Hostnames which are
+    /// not valid DNS names fail with `Error::InvalidTarget` without being
+    /// queried.
+    ///
+    pub fn lookup(&self, target: &Target) -> Result<TargetResults, Error> {
+        match target {
+            Target::Ip(ip) => self.ip2asn(*ip).map(TargetResults::Ip),
+            Target::Asn(asn) => self.asn(*asn).map(TargetResults::Asn),
+            Target::Host(host) => self.host2asn(host).map(TargetResults::Ip),
+            #[cfg(feature = "ipnet")]
+            Target::Net(net) => self
+                .net2asn(*net)
+                .map(|lookup| TargetResults::Ip(lookup.results)),
+        }
+    }
+
+    /// Look up many `targets` of any kind, e.g. an indicator list
+    ///
+    /// Returns one [`TargetLookup`](struct.TargetLookup.html) per target, in
+    /// input order. A failed lookup doesn't stop the others.
+    ///
+    /// Targets are looked up in bulk, like with
+    /// [`ip2asn_many`](struct.CymruClient.html#method.ip2asn_many):
+    /// hostnames are resolved a few at a time, and their addresses queried
+    /// together with address and network targets, each distinct one once.
+    /// AS numbers are queried once each too.
+    ///
+    /// ```
+    /// use cymrust::{fixtures, CymruClient, Target, TargetResults};
+    ///
+    /// let client = CymruClient::with_provider(fixtures::mock_provider());
+    /// let targets: Vec<Target> = ["8.8.8.8", "AS13335", "192.0.2.1"]
+    ///     .iter()
+    ///     .map(|target| target.parse().unwrap())
+    ///     .collect();
+    /// let lookups = client.lookup_all(targets);
+    /// assert!(matches!(lookups[0].results, Ok(TargetResults::Ip(_))));
+    /// assert!(matches!(lookups[1].results, Ok(TargetResults::Asn(_))));
+    /// assert!(lookups[2].results.is_err());
+    /// ```
+    ///
+    pub fn lookup_all<I>(&self, targets: I) -> Vec<TargetLookup>
+    where
+        I: IntoIterator<Item = Target>,
+    {
+        let targets: Vec<Target> = targets.into_iter().collect();
+        let job = self.job();
+
+        let hosts = bulk(
+            targets.iter().filter_map(|target| match target {
+                Target::Host(host) => Some(host.as_str()),
+                _ => None,
+            }),
+            &job,
+            |host| self.valid_host_addrs(host),
+        );
+        let asns = bulk(
+            targets.iter().filter_map(|target| match target {
+                Target::Asn(asn) => Some(*asn),
+                _ => None,
+            }),
+            &job,
+            |asn| self.asn(asn),
+        );
+
+        // Addresses of all IP, network and hostname targets, in input order
+        let mut ips = Vec::new();
+        for target in &targets {
+            match target {
+                Target::Ip(ip) => ips.push(*ip),
+                Target::Asn(_) => {}
+                Target::Host(host) => {
+                    if let Ok(addrs) = &hosts[host.as_str()] {
+                        ips.extend(addrs);
+                    }
+                }
+                #[cfg(feature = "ipnet")]
+                Target::Net(net) => ips.push(net.network()),
+            }
+        }
+        let mut ip_results = self.ip2asn_many_in(&ips, &job).into_iter();
+
+        targets
+            .iter()
+            .map(|target| {
+                let results = match target {
+                    Target::Asn(asn) => match &asns[asn] {
+                        Ok(results) => Ok(TargetResults::Asn(results.clone())),
+                        Err(err) => Err(err.duplicate()),
+                    },
+                    Target::Host(host) => match &hosts[host.as_str()] {
+                        Ok(addrs) => {
+                            let results = ip_results.by_ref().take(addrs.len());
+                            host_results(host, results).map(TargetResults::Ip)
+                        }
+                        Err(err) => Err(err.duplicate()),
+                    },
+                    _ => ip_results
+                        .next()
+                        .expect("results for every address")
+                        .map(TargetResults::Ip),
+                };
+                TargetLookup {
+                    target: target.clone(),
+                    results,
+                }
+            })
+            .collect()
+    }
+
+    fn host2asn(&self, host: &str) -> Result<Vec<CymruIP2ASN>, Error> {
+        let addrs = self.valid_host_addrs(host)?;
+        host_results(host, addrs.into_iter().map(|ip| self.ip2asn(ip)))
+    }
+
+    /// Addresses of `host`, unless it's not a valid DNS name
+    ///
+    fn valid_host_addrs(&self, host: &str) -> Result<Vec<IpAddr>, Error> {
+        // Targets built directly rather than parsed may hold anything
+        if !is_hostname(host) {
+            return Err(Error::InvalidTarget(ParseTargetError {
+                input: host.to_string(),
+            }));
+        }
+        self.host_addrs(host)
+    }
+}
+
+/// Combine IP-to-ASN `results` of the addresses of `host`
+///
+/// Addresses which have no results or are not routable are skipped; other
+/// errors fail the whole host. All of `results` are consumed either way.
+///
+fn host_results<I>(host: &str, results: I) -> Result<Vec<CymruIP2ASN>, Error>
+where
+    I: Iterator<Item = Result<Vec<CymruIP2ASN>, Error>>,
+{
+    let mut found = Vec::new();
+    let mut first_err = None;
+    let mut failed = None;
+    for result in results {
+        match result {
+            Ok(results) => found.extend(results),
+            Err(err @ Error::NoResultsFound { .. }) | Err(err @ Error::NonRoutableInput { .. }) => {
+                first_err.get_or_insert(err);
+            }
+            Err(err) => {
+                failed.get_or_insert(err);
+            }
+        }
+    }
+
+    match (failed, first_err) {
+        (Some(err), _) => Err(err),
+        (None, Some(err)) if found.is_empty() => Err(err),
+        (None, _) if found.is_empty() => Err(Error::NoResultsFound {
+            query: host.to_string(),
+            stage: Stage::Host,
+        }),
+        (None, _) => Ok(found),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, Mutex};
+
+    use super::{Target, TargetResults};
+    use crate::{fixtures, AsNumber, CymruClient, Error, Metrics, Stage};
+
+    #[test]
+    fn test_parse_target() {
+        assert_eq!("as64500".parse(), Ok(Target::Asn(64500)));
+        assert_eq!(
+            "2001:db8::1".parse(),
+            Ok(Target::Ip("2001:db8::1".parse().unwrap()))
+        );
+        assert_eq!(
+            "example.com.".parse(),
+            Ok(Target::Host("example.com.".to_string()))
+        );
+        assert_eq!("AS".parse(), Ok(Target::Host("AS".to_string())));
+        for bad in &[
+            "",
+            "AS+1",
+            "AS99999999999",
+            "-bad.example",
+            "a..b",
+            "a b",
+            "1.2.3",
+            "€x",
+            "Aé1",
+        ] {
+            let err = bad.parse::<Target>().unwrap_err();
+            assert_eq!(err.input(), *bad);
+        }
+    }
+
+    #[test]
+    fn test_lookup() {
+        let mock = fixtures::mock_provider()
+            .host("dns.google", "8.8.8.8".parse().unwrap())
+            .host("dns.google", "192.168.0.1".parse().unwrap())
+            .host("private.example", "10.0.0.1".parse().unwrap());
+        let client = CymruClient::with_provider(mock);
+
+        match client.lookup(&"AS15169".parse().unwrap()).unwrap() {
+            TargetResults::Asn(results) => assert_eq!(results[0].as_name, "GOOGLE, US"),
+            other => panic!("unexpected {:?}", other),
+        }
+        match client.lookup(&"dns.google".parse().unwrap()).unwrap() {
+            TargetResults::Ip(results) => {
+                assert_eq!(results.len(), 1);
+                assert_eq!(
+                    results[0].ip_addr,
+                    "8.8.8.8".parse::<std::net::IpAddr>().unwrap()
+                );
+            }
+            other => panic!("unexpected {:?}", other),
+        }
+
+        match client.lookup(&"missing.example".parse().unwrap()) {
+            Err(Error::NoResultsFound { query, stage }) => {
+                assert_eq!(query, "missing.example");
+                assert_eq!(stage, Stage::Host);
+            }
+            other => panic!("unexpected {:?}", other),
+        }
+        assert!(client.lookup(&"private.example".parse().unwrap()).is_err());
+
+        for bad in &["", "a..b", "bad host.example", "x.-bad-"] {
+            match client.lookup(&Target::Host(bad.to_string())) {
+                Err(Error::InvalidTarget(err)) => assert_eq!(err.input(), *bad),
+                other => panic!("unexpected {:?}", other),
+            }
+        }
+    }
+
+    #[derive(Clone, Default)]
+    struct Started(Arc<Mutex<Vec<String>>>);
+
+    impl Metrics for Started {
+        fn query_start(&self, _backend: &str, query: &str) {
+            self.0.lock().unwrap().push(query.to_string());
+        }
+    }
+
+    #[test]
+    fn test_lookup_all_in_bulk() {
+        let mock = fixtures::mock_provider().host("dns.google", "8.8.8.8".parse().unwrap());
+        let started = Started::default();
+        let client = CymruClient::with_provider(mock).with_metrics(started.clone());
+        let targets = [
+            "dns.google",
+            "8.8.8.8",
+            "AS15169",
+            "dns.google",
+            "AS15169",
+            "missing.example",
+            "1.1.1.1",
+        ]
+        .iter()
+        .map(|target| target.parse().unwrap());
+
+        let lookups = client.lookup_all(targets);
+        let summary: Vec<(String, Option<AsNumber>)> = lookups
+            .iter()
+            .map(|lookup| {
+                let asn = match &lookup.results {
+                    Ok(TargetResults::Ip(results)) => Some(results[0].as_number),
+                    Ok(TargetResults::Asn(results)) => Some(results[0].as_number),
+                    _ => None,
+                };
+                (lookup.target.to_string(), asn)
+            })
+            .collect();
+        assert_eq!(
+            summary,
+            vec![
+                ("dns.google".to_string(), Some(15169)),
+                ("8.8.8.8".to_string(), Some(15169)),
+                ("AS15169".to_string(), Some(15169)),
+                ("dns.google".to_string(), Some(15169)),
+                ("AS15169".to_string(), Some(15169)),
+                ("missing.example".to_string(), None),
+                ("1.1.1.1".to_string(), Some(13335)),
+            ]
+        );
+
+        // Every distinct name is queried once
+        let mut queries = started.0.lock().unwrap().clone();
+        queries.sort();
+        let count = queries.len();
+        queries.dedup();
+        assert_eq!(queries.len(), count, "{:?}", queries);
+        assert_eq!(count, 6, "{:?}", queries);
+    }
+
+    #[cfg(feature = "ipnet")]
+    #[test]
+    fn test_lookup_all() {
+        let client = CymruClient::with_provider(fixtures::mock_provider());
+        let targets = ["8.8.8.8/32", "AS15169", "missing.example"]
+            .iter()
+            .map(|target| target.parse().unwrap());
+
+        let lookups = client.lookup_all(targets);
+        assert_eq!(lookups.len(), 3);
+        assert_eq!(
+            lookups[0].target,
+            Target::Net("8.8.8.8/32".parse().unwrap())
+        );
+        match &lookups[0].results {
+            Ok(TargetResults::Ip(results)) => assert_eq!(results[0].as_number, 15169),
+            other => panic!("unexpected {:?}", other),
+        }
+        assert!(matches!(lookups[1].results, Ok(TargetResults::Asn(_))));
+        assert!(matches!(
+            lookups[2].results,
+            Err(Error::NoResultsFound {
+                stage: Stage::Host,
+                ..
+            })
+        ));
+    }
+}
@@ -0,0 +1,313 @@
+//! Tiny in-process DNS server for integration tests
+//!
+//! Available with the `test-server` feature. [`TestDnsServer`](struct.TestDnsServer.html)
+//! serves TXT records from a [`TestZone`](struct.TestZone.html) over UDP and
+//! TCP on localhost, so lookups can be tested end-to-end through the real
+//! resolver: TTL handling, multi-record answers, truncated UDP answers
+//! falling back to TCP, CNAME chains and reverse DNS.
+//!
+//! ```
+//! use cymrust::test_server::{TestDnsServer, TestZone};
+//!
+//! let zone = TestZone::new()
+//!     .txt("1.2.0.192.origin.asn.cymru.com.", 300,
+//!          &["64500 | 192.0.2.0/24 | US | arin | 2010-01-01"])
+//!     .txt("AS64500.asn.cymru.com.", 300,
+//!          &["64500 | US | arin | 2010-01-01 | EXAMPLE - Example Inc., US"]);
+//! let server = TestDnsServer::start(zone).unwrap();
+//!
+//! let results = server.client().ip2asn("192.0.2.1".parse().unwrap()).unwrap();
+//! assert_eq!(results[0].as_number, 64500);
+//! ```
+
+use std::collections::HashMap;
+use std::io::{self, Read, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream, UdpSocket};
+use std::str::FromStr;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+use trust_dns_proto::op::{Message, MessageType, ResponseCode};
+use trust_dns_proto::rr::rdata::TXT;
+use trust_dns_proto::rr::{Name, RData, Record, RecordType};
+use trust_dns_resolver::config::{NameServerConfigGroup, ResolverConfig, ResolverOpts};
+use trust_dns_resolver::Resolver;
+
+use crate::CymruClient;
+
+/// Largest DNS message sent over UDP before answers are truncated
+///
+const MAX_UDP_SIZE: usize = 512;
+
+/// How often server threads check whether they should stop
+///
+const POLL_INTERVAL: Duration = Duration::from_millis(20);
+
+/// TXT records served by `TestDnsServer`
+///
+#[derive(Debug, Clone, Default)]
+pub struct TestZone {
+    names: HashMap<String, (u32, Vec<Vec<String>>)>,
+    ptrs: HashMap<String, (u32, Vec<String>)>,
+    cnames: HashMap<String, (u32, String)>,
+}
+
+impl TestZone {
+    /// Create empty zone, answering NXDOMAIN to everything
+    ///
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add one TXT record for `name` consisting of given character-strings
+    ///
+    /// Calling this several times for the same name adds more records to its
+    /// answer. The TTL of the last call is used for all records of the name.
+    ///
+    pub fn txt(mut self, name: &str, ttl: u32, strings: &[&str]) -> Self {
+        let entry = self
+            .names
+            .entry(name.to_lowercase())
+            .or_insert_with(|| (ttl, Vec::new()));
+        entry.0 = ttl;
+        entry
+            .1
+            .push(strings.iter().map(|s| s.to_string()).collect());
+        self
+    }
+
+    /// Add PTR record for reverse DNS `name` pointing to `target`
+    ///
+    pub fn ptr(mut self, name: &str, ttl: u32, target: &str) -> Self {
+        let entry = self
+            .ptrs
+            .entry(name.to_lowercase())
+            .or_insert_with(|| (ttl, Vec::new()));
+        entry.0 = ttl;
+        entry.1.push(target.to_string());
+        self
+    }
+
+    /// Make `name` an alias (CNAME) of `target`
+    ///
+    /// Queries for `name` are answered with the CNAME record only, as for a
+    /// target outside the zone, so resolvers have to query `target` separately.
+    ///
+    pub fn cname(mut self, name: &str, ttl: u32, target: &str) -> Self {
+        self.cnames
+            .insert(name.to_lowercase(), (ttl, target.to_string()));
+        self
+    }
+
+    /// Build response to DNS `request`, truncated to `max_size` if given
+    ///
+    fn respond(&self, request: &Message, max_size: Option<usize>) -> Vec<u8> {
+        let mut response = Message::new();
+        response
+            .set_id(request.id())
+            .set_message_type(MessageType::Response)
+            .set_op_code(request.op_code())
+            .set_recursion_desired(request.recursion_desired())
+            .set_recursion_available(true)
+            .set_authoritative(true);
+
+        for query in request.queries() {
+            response.add_query(query.clone());
+            let name = query.name().to_lowercase().to_string();
+            if let Some((ttl, target)) = self.cnames.get(&name) {
+                if let Ok(target) = Name::from_str(target) {
+                    response.add_answer(Record::from_rdata(
+                        query.name().clone(),
+                        *ttl,
+                        RData::CNAME(target),
+                    ));
+                }
+                continue;
+            }
+            if !self.names.contains_key(&name) && !self.ptrs.contains_key(&name) {
+                response.set_response_code(ResponseCode::NXDomain);
+                continue;
+            }
+            match query.query_type() {
+                RecordType::TXT => {
+                    if let Some((ttl, records)) = self.names.get(&name) {
+                        for strings in records {
+                            response.add_answer(Record::from_rdata(
+                                query.name().clone(),
+                                *ttl,
+                                RData::TXT(TXT::new(strings.clone())),
+                            ));
+                        }
+                    }
+                }
+                RecordType::PTR => {
+                    if let Some((ttl, targets)) = self.ptrs.get(&name) {
+                        for target in targets {
+                            if let Ok(target) = Name::from_str(target) {
+                                response.add_answer(Record::from_rdata(
+                                    query.name().clone(),
+                                    *ttl,
+                                    RData::PTR(target),
+                                ));
+                            }
+                        }
+                    }
+                }
+                _ => {}
+            }
+        }
+
+        let bytes = response.to_vec().unwrap_or_default();
+        match max_size {
+            Some(max_size) if bytes.len() > max_size => {
+                response.take_answers();
+                response.set_truncated(true);
+                response.to_vec().unwrap_or_default()
+            }
+            _ => bytes,
+        }
+    }
+}
+
+/// DNS server serving a `TestZone` on localhost until dropped
+///
+pub struct TestDnsServer {
+    addr: SocketAddr,
+    stop: Arc<AtomicBool>,
+    threads: Vec<JoinHandle<()>>,
+}
+
+impl TestDnsServer {
+    /// Start serving `zone` on a free localhost port, over both UDP and TCP
+    ///
+    pub fn start(zone: TestZone) -> io::Result<Self> {
+        let (udp, tcp) = bind_pair()?;
+        let addr = udp.local_addr()?;
+        let zone = Arc::new(zone);
+        let stop = Arc::new(AtomicBool::new(false));
+
+        udp.set_read_timeout(Some(POLL_INTERVAL))?;
+        tcp.set_nonblocking(true)?;
+
+        let udp_thread = {
+            let zone = zone.clone();
+            let stop = stop.clone();
+            thread::spawn(move || serve_udp(&udp, &zone, &stop))
+        };
+        let tcp_thread = {
+            let stop = stop.clone();
+            thread::spawn(move || serve_tcp(&tcp, &zone, &stop))
+        };
+
+        Ok(TestDnsServer {
+            addr,
+            stop,
+            threads: vec![udp_thread, tcp_thread],
+        })
+    }
+
+    /// Address the server listens on, for both UDP and TCP
+    ///
+    pub fn addr(&self) -> SocketAddr {
+        self.addr
+    }
+
+    /// Resolver using only this server, without caching
+    ///
+    pub fn resolver(&self) -> Resolver {
+        let servers =
+            NameServerConfigGroup::from_ips_clear(&[self.addr.ip()], self.addr.port(), true);
+        let config = ResolverConfig::from_parts(None, vec![], servers);
+        let opts = ResolverOpts {
+            timeout: Duration::from_secs(2),
+            attempts: 1,
+            cache_size: 0,
+            ..ResolverOpts::default()
+        };
+        Resolver::new(config, opts).expect("resolver for test server")
+    }
+
+    /// Client querying only this server
+    ///
+    /// Special-purpose address pre-filtering is disabled, so that zones can
+    /// use documentation addresses.
+    ///
+    pub fn client(&self) -> CymruClient {
+        CymruClient::with_resolver(self.resolver()).with_prefilter(false)
+    }
+}
+
+impl Drop for TestDnsServer {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::SeqCst);
+        for thread in self.threads.drain(..) {
+            let _ = thread.join();
+        }
+    }
+}
+
+/// Bind UDP and TCP sockets to the same free localhost port
+///
+fn bind_pair() -> io::Result<(UdpSocket, TcpListener)> {
+    let mut last_err = None;
+    for _ in 0..10 {
+        let udp = UdpSocket::bind("127.0.0.1:0")?;
+        match TcpListener::bind(udp.local_addr()?) {
+            Ok(tcp) => return Ok((udp, tcp)),
+            Err(err) => last_err = Some(err),
+        }
+    }
+    Err(last_err.unwrap_or_else(|| io::Error::other("no free port")))
+}
+
+fn serve_udp(socket: &UdpSocket, zone: &TestZone, stop: &AtomicBool) {
+    let mut buf = [0u8; 4096];
+    while !stop.load(Ordering::SeqCst) {
+        let (len, peer) = match socket.recv_from(&mut buf) {
+            Ok(received) => received,
+            Err(_) => continue,
+        };
+        if let Ok(request) = Message::from_vec(&buf[..len]) {
+            let max_size = match request.edns() {
+                Some(edns) => usize::from(edns.max_payload()),
+                None => MAX_UDP_SIZE,
+            };
+            let _ = socket.send_to(&zone.respond(&request, Some(max_size)), peer);
+        }
+    }
+}
+
+fn serve_tcp(listener: &TcpListener, zone: &TestZone, stop: &AtomicBool) {
+    while !stop.load(Ordering::SeqCst) {
+        match listener.accept() {
+            Ok((stream, _)) => {
+                let _ = handle_tcp(stream, zone);
+            }
+            Err(_) => thread::sleep(POLL_INTERVAL),
+        }
+    }
+}
+
+/// Answer length-prefixed DNS messages on `stream` until client closes it
+///
+fn handle_tcp(mut stream: TcpStream, zone: &TestZone) -> io::Result<()> {
+    stream.set_nonblocking(false)?;
+    stream.set_read_timeout(Some(Duration::from_secs(2)))?;
+    loop {
+        let mut len = [0u8; 2];
+        if stream.read_exact(&mut len).is_err() {
+            return Ok(());
+        }
+        let mut buf = vec![0u8; usize::from(u16::from_be_bytes(len))];
+        stream.read_exact(&mut buf)?;
+        let request = match Message::from_vec(&buf) {
+            Ok(request) => request,
+            Err(_) => return Ok(()),
+        };
+        let response = zone.respond(&request, None);
+        stream.write_all(&(response.len() as u16).to_be_bytes())?;
+        stream.write_all(&response)?;
+    }
+}
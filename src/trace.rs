@@ -0,0 +1,122 @@
+//! Optional [tracing](https://docs.rs/tracing) and [log](https://docs.rs/log)
+//! instrumentation
+//!
+//! With the `tracing` and `log` features disabled these helpers are no-ops and
+//! compile away entirely, so the lookup code can call them unconditionally.
+
+/// Emit a debug level `log` record when the `log` feature is enabled
+///
+macro_rules! debug {
+    ($($arg:tt)*) => {{
+        #[cfg(feature = "log")]
+        log::debug!($($arg)*);
+        #[cfg(not(feature = "log"))]
+        let _ = format_args!($($arg)*);
+    }};
+}
+
+/// Emit a warn level `log` record when the `log` feature is enabled
+///
+macro_rules! warn {
+    ($($arg:tt)*) => {{
+        #[cfg(feature = "log")]
+        log::warn!($($arg)*);
+        #[cfg(not(feature = "log"))]
+        let _ = format_args!($($arg)*);
+    }};
+}
+
+use crate::Error;
+
+/// Short, stable description of a lookup outcome for span fields
+///
+#[cfg(feature = "tracing")]
+fn outcome<T>(result: &Result<T, Error>) -> &'static str {
+    match result {
+        Ok(_) => "ok",
+        Err(Error::NoResultsFound { .. }) => "no_results",
+        Err(_) => "error",
+    }
+}
+
+/// Span covering a single DNS query
+///
+#[cfg(feature = "tracing")]
+pub(crate) struct QuerySpan {
+    span: tracing::span::EnteredSpan,
+    started: std::time::Instant,
+}
+
+#[cfg(feature = "tracing")]
+impl QuerySpan {
+    pub(crate) fn enter(query: &str, rtype: &str, backend: &str) -> Self {
+        #[cfg(not(feature = "otel"))]
+        let span = tracing::debug_span!(
+            "cymru_query",
+            query,
+            rtype,
+            backend,
+            duration_us = tracing::field::Empty,
+            outcome = tracing::field::Empty,
+        );
+        // Same span, annotated with OpenTelemetry semantic conventions
+        // understood by tracing-opentelemetry and OTLP collectors
+        #[cfg(feature = "otel")]
+        let span = tracing::debug_span!(
+            "cymru_query",
+            query,
+            backend,
+            duration_us = tracing::field::Empty,
+            outcome = tracing::field::Empty,
+            otel.name = %format_args!("DNS {}", rtype),
+            otel.kind = "client",
+            otel.status_code = tracing::field::Empty,
+            rpc.system = "dns",
+            dns.question.name = query,
+            dns.question.type = rtype,
+            peer.service = "cymru",
+        );
+        QuerySpan {
+            span: span.entered(),
+            started: std::time::Instant::now(),
+        }
+    }
+
+    pub(crate) fn finish<T>(self, result: &Result<T, Error>) {
+        let elapsed = self.started.elapsed().as_micros() as u64;
+        self.span.record("duration_us", elapsed);
+        self.span.record("outcome", outcome(result));
+        #[cfg(feature = "otel")]
+        self.span.record(
+            "otel.status_code",
+            if result.is_ok() { "OK" } else { "ERROR" },
+        );
+    }
+}
+
+/// Enter a span covering parsing of `records` TXT strings of given `kind`
+///
+#[cfg(feature = "tracing")]
+pub(crate) fn parse_span(kind: &'static str, records: usize) -> tracing::span::EnteredSpan {
+    tracing::debug_span!("cymru_parse", kind, records).entered()
+}
+
+#[cfg(not(feature = "tracing"))]
+pub(crate) struct QuerySpan;
+
+#[cfg(not(feature = "tracing"))]
+impl QuerySpan {
+    pub(crate) fn enter(_query: &str, _rtype: &str, _backend: &str) -> Self {
+        QuerySpan
+    }
+
+    pub(crate) fn finish<T>(self, _result: &Result<T, Error>) {}
+}
+
+#[cfg(not(feature = "tracing"))]
+pub(crate) struct ParseSpan;
+
+#[cfg(not(feature = "tracing"))]
+pub(crate) fn parse_span(_kind: &'static str, _records: usize) -> ParseSpan {
+    ParseSpan
+}
@@ -0,0 +1,236 @@
+use std::net::UdpSocket;
+use std::time::{Duration, Instant, SystemTime};
+
+use cymrust::provider::{DnsProvider, TxtJoin};
+use cymrust::test_server::{TestDnsServer, TestZone};
+use cymrust::{CymruClient, Stage};
+
+const ORIGIN: &str = "1.2.0.192.origin.asn.cymru.com.";
+
+fn asn_zone() -> TestZone {
+    TestZone::new()
+        .txt(
+            "AS64500.asn.cymru.com.",
+            3600,
+            &["64500 | US | arin | 2010-01-01 | EXAMPLE-A - Example A Inc., US"],
+        )
+        .txt(
+            "AS64501.asn.cymru.com.",
+            3600,
+            &["64501 | FI | ripencc | 2012-06-30 | EXAMPLE-B - Example B Oy, FI"],
+        )
+}
+
+#[test]
+fn test_ip2asn() {
+    let zone = asn_zone().txt(
+        ORIGIN,
+        3600,
+        &["64500 | 192.0.2.0/24 | US | arin | 2010-01-01"],
+    );
+    let server = TestDnsServer::start(zone).unwrap();
+
+    let results = server
+        .client()
+        .ip2asn("192.0.2.1".parse().unwrap())
+        .unwrap();
+    assert_eq!(results.len(), 1);
+    assert_eq!(
+        results[0].ip_addr,
+        "192.0.2.1".parse::<std::net::IpAddr>().unwrap()
+    );
+    assert_eq!(results[0].bgp_prefix, "192.0.2.0/24");
+    assert_eq!(results[0].as_number, 64500);
+    assert_eq!(results[0].as_name, "EXAMPLE-A - Example A Inc., US");
+    assert_eq!(results[0].allocated.as_deref(), Some("2010-01-01"));
+}
+
+#[test]
+fn test_ip2asn_ipv6() {
+    let zone = asn_zone().txt(
+        "1.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.8.b.d.0.1.0.0.2.origin6.asn.cymru.com.",
+        3600,
+        &["64501 | 2001:db8::/32 | FI | ripencc | 2012-06-30"],
+    );
+    let server = TestDnsServer::start(zone).unwrap();
+
+    let results = server
+        .client()
+        .ip2asn("2001:db8::1".parse().unwrap())
+        .unwrap();
+    assert_eq!(results.len(), 1);
+    assert_eq!(results[0].bgp_prefix, "2001:db8::/32");
+    assert_eq!(results[0].as_number, 64501);
+}
+
+#[test]
+fn test_expires_uses_shortest_ttl() {
+    let zone = asn_zone().txt(
+        ORIGIN,
+        60,
+        &["64500 | 192.0.2.0/24 | US | arin | 2010-01-01"],
+    );
+    let server = TestDnsServer::start(zone).unwrap();
+
+    let before = SystemTime::now();
+    let results = server
+        .client()
+        .ip2asn("192.0.2.1".parse().unwrap())
+        .unwrap();
+    let ttl = results[0].expires.duration_since(before).unwrap();
+    assert!(ttl <= Duration::from_secs(61), "{:?}", ttl);
+    assert!(ttl >= Duration::from_secs(55), "{:?}", ttl);
+}
+
+#[test]
+fn test_cname_chain_ttl() {
+    let zone = asn_zone()
+        .cname(ORIGIN, 60, "alias.example.net.")
+        .cname("alias.example.net.", 30, "origin.example.net.")
+        .txt(
+            "origin.example.net.",
+            3600,
+            &["64500 | 192.0.2.0/24 | US | arin | 2010-01-01"],
+        );
+    let server = TestDnsServer::start(zone).unwrap();
+
+    let before = SystemTime::now();
+    let (results, stats) = server
+        .client()
+        .ip2asn_with_stats("192.0.2.1".parse().unwrap())
+        .unwrap();
+    assert_eq!(results[0].as_number, 64500);
+    let ttl = results[0].expires.duration_since(before).unwrap();
+    assert!(ttl <= Duration::from_secs(31), "{:?}", ttl);
+    assert_eq!(
+        stats.queries[0].canonical_name.as_deref(),
+        Some("origin.example.net.")
+    );
+    assert_eq!(stats.queries[1].canonical_name, None);
+}
+
+#[test]
+fn test_multiple_origin_records() {
+    let zone = asn_zone()
+        .txt(
+            ORIGIN,
+            3600,
+            &["64500 | 192.0.2.0/24 | US | arin | 2010-01-01"],
+        )
+        .txt(
+            ORIGIN,
+            3600,
+            &["64501 | 192.0.0.0/16 | FI | ripencc | 2012-06-30"],
+        );
+    let server = TestDnsServer::start(zone).unwrap();
+
+    let mut results = server
+        .client()
+        .ip2asn("192.0.2.1".parse().unwrap())
+        .unwrap();
+    results.sort_by_key(|r| r.as_number);
+    assert_eq!(results.len(), 2);
+    assert_eq!(results[0].bgp_prefix, "192.0.2.0/24");
+    assert_eq!(results[1].bgp_prefix, "192.0.0.0/16");
+    assert_eq!(results[1].as_name, "EXAMPLE-B - Example B Oy, FI");
+}
+
+#[test]
+fn test_truncated_answer_retried_over_tcp() {
+    let mut zone = TestZone::new();
+    for asn in 64500..64530 {
+        zone = zone.txt(
+            &format!("AS{}.asn.cymru.com.", asn),
+            3600,
+            &[&format!(
+                "{} | US | arin | 2010-01-01 | EXAMPLE-{}",
+                asn, asn
+            )],
+        );
+        zone = zone.txt(
+            ORIGIN,
+            3600,
+            &[&format!("{} | 192.0.2.0/24 | US | arin | 2010-01-01", asn)],
+        );
+    }
+    let server = TestDnsServer::start(zone).unwrap();
+
+    let results = server
+        .client()
+        .ip2asn("192.0.2.1".parse().unwrap())
+        .unwrap();
+    assert_eq!(results.len(), 30);
+}
+
+#[test]
+fn test_nxdomain_error_context() {
+    let zone = TestZone::new().txt(ORIGIN, 3600, &["64999 | 192.0.2.0/24 | US | arin |"]);
+    let server = TestDnsServer::start(zone).unwrap();
+    let client = server.client();
+
+    let err = client.ip2asn("192.0.2.1".parse().unwrap()).unwrap_err();
+    assert_eq!(err.query(), Some("AS64999.asn.cymru.com."));
+    assert_eq!(err.stage(), Some(Stage::Asn));
+
+    let err = client.ip2asn("198.51.100.1".parse().unwrap()).unwrap_err();
+    assert_eq!(err.query(), Some("1.100.51.198.origin.asn.cymru.com."));
+    assert_eq!(err.stage(), Some(Stage::Origin));
+}
+
+#[test]
+fn test_reverse_dns() {
+    let zone = asn_zone()
+        .txt(
+            ORIGIN,
+            3600,
+            &["64500 | 192.0.2.0/24 | US | arin | 2010-01-01"],
+        )
+        .ptr("1.2.0.192.in-addr.arpa.", 3600, "host.example.com.");
+    let server = TestDnsServer::start(zone).unwrap();
+    let client = server.client().with_reverse_dns(true);
+
+    let info = client.ip_info("192.0.2.1".parse().unwrap()).unwrap();
+    assert_eq!(info.results[0].as_number, 64500);
+    assert_eq!(info.hostname.as_deref(), Some("host.example.com"));
+}
+
+#[test]
+fn test_racing_nameservers() {
+    let zone = asn_zone().txt(
+        ORIGIN,
+        3600,
+        &["64500 | 192.0.2.0/24 | US | arin | 2010-01-01"],
+    );
+    let server = TestDnsServer::start(zone).unwrap();
+    // Nameserver that never answers
+    let silent = UdpSocket::bind("127.0.0.1:0").unwrap();
+
+    let provider =
+        DnsProvider::with_nameservers(&[silent.local_addr().unwrap(), server.addr()]).unwrap();
+    let client = CymruClient::with_provider(provider).with_prefilter(false);
+
+    let started = Instant::now();
+    let results = client.ip2asn("192.0.2.1".parse().unwrap()).unwrap();
+    assert_eq!(results[0].as_number, 64500);
+    assert!(started.elapsed() < Duration::from_secs(2));
+}
+
+#[test]
+fn test_multi_string_txt() {
+    let long_name = format!("EXAMPLE-LONG - {}, US", "Very Long Example ".repeat(16));
+    let record = format!("64502 | US | arin | 2015-01-01 | {}", long_name);
+    assert!(record.len() > 255);
+    let (head, tail) = record.split_at(255);
+    let zone = TestZone::new().txt("AS64502.asn.cymru.com.", 3600, &[head, tail]);
+    let server = TestDnsServer::start(zone).unwrap();
+
+    let results = server.client().asn(64502u32).unwrap();
+    assert_eq!(results.len(), 1);
+    assert_eq!(results[0].as_name, long_name);
+
+    let provider = DnsProvider::with_resolver(server.resolver()).with_txt_join(TxtJoin::Split);
+    let client = CymruClient::with_provider(provider).with_prefilter(false);
+    let (results, stats) = client.asn_with_stats(64502u32).unwrap();
+    assert_eq!(stats.queries[0].records, 2);
+    assert_ne!(results[0].as_name, long_name);
+}
@@ -0,0 +1,33 @@
+use cymrust::{fixtures, CymruASN, CymruIP2ASN, Source};
+
+#[test]
+fn test_serde_roundtrip() {
+    let json = serde_json::to_string(&fixtures::cloudflare()).unwrap();
+    let parsed: CymruIP2ASN = serde_json::from_str(&json).unwrap();
+    assert_eq!(parsed, fixtures::cloudflare());
+
+    let mut value = serde_json::to_value(fixtures::cloudflare()).unwrap();
+    assert_eq!(value["source"], "mock");
+    value.as_object_mut().unwrap().remove("source");
+    let parsed: CymruIP2ASN = serde_json::from_value(value).unwrap();
+    assert_eq!(parsed.source, Source::Unknown);
+
+    let json = serde_json::to_value(fixtures::google_asn()).unwrap();
+    assert_eq!(json["allocated"], "2000-03-30");
+    let parsed: CymruASN = serde_json::from_value(json).unwrap();
+    assert_eq!(parsed, fixtures::google_asn());
+}
+
+#[test]
+fn test_schema_validates_fields() {
+    let schema = serde_json::to_value(schemars::schema_for!(CymruIP2ASN)).unwrap();
+    let properties = schema["properties"].as_object().unwrap();
+    let value = serde_json::to_value(fixtures::team_cymru()).unwrap();
+    for key in value.as_object().unwrap().keys() {
+        assert!(properties.contains_key(key), "{} missing from schema", key);
+    }
+    assert!(schema["required"]
+        .as_array()
+        .unwrap()
+        .contains(&"as_number".into()));
+}